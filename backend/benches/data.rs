@@ -1,8 +1,14 @@
+#![allow(clippy::unwrap_used)]
+
 use criterion::{criterion_group, criterion_main, Criterion};
 use eftb::units::Meters;
 
 fn star_by_name(c: &mut Criterion) {
-    let universe = eftb::data::Universe::build(Meters::from_light_years(500.0)).unwrap();
+    let universe = eftb::data::Universe::build(
+        &Default::default(),
+        eftb::data::JumpEdges::Precomputed(Meters::from_light_years(500.0)),
+    )
+    .unwrap();
     c.bench_function("star_by_name", |b| {
         b.iter(|| {
             universe.star_by_name(&"E9R-5PC".to_string()).unwrap();
@@ -11,5 +17,22 @@ fn star_by_name(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, star_by_name);
+fn build_hierarchy(c: &mut Criterion) {
+    let universe =
+        eftb::data::Universe::build(&Default::default(), eftb::data::JumpEdges::OnDemand).unwrap();
+    let key = eftb::calc::hierarchy::HierarchyKey {
+        jump_distance: Meters::from_light_years(100.0),
+        optimize: eftb::calc::path::PathOptimize::Hops,
+        use_smart_gates: true,
+    };
+    // Takes long enough that the default 100 samples would run for hours
+    let mut group = c.benchmark_group("hierarchy");
+    group.sample_size(10);
+    group.bench_function("build (100 ly, hops)", |b| {
+        b.iter(|| eftb::calc::hierarchy::ContractionHierarchy::build(&universe, key))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, star_by_name, build_hierarchy);
 criterion_main!(benches);
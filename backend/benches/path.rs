@@ -1,13 +1,53 @@
+#![allow(clippy::unwrap_used)]
+
 use criterion::{criterion_group, criterion_main, Criterion};
 use eftb::units::Meters;
 
 fn path(c: &mut Criterion) {
-    let universe = eftb::data::Universe::build(Meters::from_light_years(500.0)).unwrap();
+    let jump_distance = Meters::from_light_years(200.0);
+    let universe = eftb::data::Universe::build_with_hierarchies(
+        &Default::default(),
+        eftb::data::JumpEdges::Precomputed(Meters::from_light_years(500.0)),
+        &[eftb::calc::hierarchy::HierarchyKey {
+            jump_distance,
+            optimize: eftb::calc::path::PathOptimize::Fuel,
+            use_smart_gates: false,
+        }],
+    )
+    .unwrap();
     // ~1700LY across dense space, takes ~20ms on my laptop
     let start = universe.star_by_name(&"OKL-N56".to_string()).unwrap();
     let end = universe.star_by_name(&"EBD-716".to_string()).unwrap();
+    for algorithm in [
+        eftb::calc::path::PathAlgorithm::AStar,
+        eftb::calc::path::PathAlgorithm::Bidirectional,
+        eftb::calc::path::PathAlgorithm::Hierarchy,
+    ] {
+        c.bench_function(format!("calc_path ({:?})", algorithm).as_str(), |b| {
+            b.iter(|| {
+                eftb::calc_path(
+                    &universe,
+                    start,
+                    end,
+                    jump_distance,
+                    eftb::calc::path::PathOptimize::Fuel,
+                    &Default::default(),
+                    algorithm,
+                    &eftb::calc::budget::SearchBudget::unlimited()
+                        .with_time(std::time::Duration::from_secs(10)),
+                )
+            })
+        });
+    }
+}
+
+fn path_on_demand(c: &mut Criterion) {
+    let universe =
+        eftb::data::Universe::build(&Default::default(), eftb::data::JumpEdges::OnDemand).unwrap();
+    let start = universe.star_by_name(&"OKL-N56".to_string()).unwrap();
+    let end = universe.star_by_name(&"EBD-716".to_string()).unwrap();
     let jump_distance = Meters::from_light_years(200.0);
-    c.bench_function("calc_path", |b| {
+    c.bench_function("calc_path (on-demand jumps)", |b| {
         b.iter(|| {
             eftb::calc_path(
                 &universe,
@@ -15,42 +55,62 @@ fn path(c: &mut Criterion) {
                 end,
                 jump_distance,
                 eftb::calc::path::PathOptimize::Fuel,
-                false,
-                Some(10),
+                &Default::default(),
+                Default::default(),
+                &eftb::calc::budget::SearchBudget::unlimited()
+                    .with_time(std::time::Duration::from_secs(10)),
             )
         })
     });
 }
 
 fn heuristic(c: &mut Criterion) {
-    let universe = eftb::data::Universe::build(Meters::from_light_years(500.0)).unwrap();
+    let universe = eftb::data::Universe::build(
+        &Default::default(),
+        eftb::data::JumpEdges::Precomputed(Meters::from_light_years(500.0)),
+    )
+    .unwrap();
     let start = universe.star_by_name(&"U75-4J4".to_string()).unwrap();
     let end = universe.star_by_name(&"OVD-1SM".to_string()).unwrap();
 
-    c.bench_function(
-        format!("heuristic ({} connections)", start.connections.len()).as_str(),
-        |b| {
-            b.iter(|| {
-                start
-                    .connections
-                    .iter()
-                    .map(|conn| eftb::calc::path::heuristic(&universe, conn, end))
-                    .collect::<Vec<_>>()
-            })
-        },
-    );
+    let jump_distance = Meters::from_light_years(100.0);
+
+    for opt in [
+        eftb::calc::path::PathOptimize::Distance,
+        eftb::calc::path::PathOptimize::Fuel,
+        eftb::calc::path::PathOptimize::Hops,
+    ] {
+        let heuristic = eftb::calc::path::Heuristic::new(&universe, end, jump_distance, opt, true);
+        c.bench_function(
+            format!(
+                "heuristic ({:?}, {} connections)",
+                opt,
+                start.connections.len()
+            )
+            .as_str(),
+            |b| {
+                b.iter(|| {
+                    start
+                        .connections
+                        .iter()
+                        .map(|conn| heuristic.estimate(conn.target))
+                        .collect::<Vec<_>>()
+                })
+            },
+        );
+    }
 }
 
 fn successors(c: &mut Criterion) {
-    let universe = eftb::data::Universe::build(Meters::from_light_years(500.0)).unwrap();
+    let universe = eftb::data::Universe::build(
+        &Default::default(),
+        eftb::data::JumpEdges::Precomputed(Meters::from_light_years(500.0)),
+    )
+    .unwrap();
     let start = universe.star_by_name(&"U75-4J4".to_string()).unwrap();
-    let conn = &eftb::data::Connection {
-        id: 0,
-        conn_type: eftb::data::ConnType::NpcGate,
-        distance: Meters::from_light_years(100.0),
-        target: universe.star_id_to_idx[&start.id],
-    };
+    let star_idx = universe.star_id_to_idx[&start.id];
     let jump_distance = Meters::from_light_years(100.0);
+    let constraints = eftb::calc::path::PathConstraints::default();
 
     for opt in [
         eftb::calc::path::PathOptimize::Distance,
@@ -59,7 +119,13 @@ fn successors(c: &mut Criterion) {
     ] {
         c.bench_function(format!("successors ({:?})", opt).as_str(), |b| {
             b.iter(|| {
-                eftb::calc::path::successors(&universe, conn, jump_distance, opt, false);
+                eftb::calc::path::successors(
+                    &universe,
+                    star_idx,
+                    jump_distance,
+                    &opt,
+                    &constraints,
+                );
             })
         });
     }
@@ -68,7 +134,7 @@ fn successors(c: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = Criterion::default().significance_level(0.1).sample_size(200);
-    targets = path, heuristic, successors
+    targets = path, path_on_demand, heuristic, successors
 }
 //criterion_group!(benches, path, heuristic, successors);
 criterion_main!(benches);
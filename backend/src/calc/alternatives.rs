@@ -0,0 +1,255 @@
+use crate::calc::budget::SearchBudget;
+use crate::calc::path::{
+    calc_path, cost, PathAlgorithm, PathConstraints, PathOptimize, PathResult,
+};
+use crate::data::*;
+use crate::error::{Error, Result};
+use crate::units::Meters;
+
+/// One of several routes between the same two stars
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+    pub path: Vec<Connection>,
+    pub cost: f64,
+    /// How much more this costs than the best route
+    pub extra_cost: f64,
+}
+
+impl Alternative {
+    pub fn distance(&self) -> Meters {
+        Meters::new(self.path.iter().map(|c| c.distance.get()).sum())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AlternativesResult {
+    /// The best routes, cheapest first. There may be fewer than asked for
+    /// if there aren't that many, or if time ran out after finding some.
+    Found(Vec<Alternative>),
+    NotFound,
+    Timeout,
+}
+
+fn path_cost(optimize: PathOptimize, path: &[Connection]) -> f64 {
+    path.iter().map(|c| cost(optimize, c)).sum()
+}
+
+fn same_connections(a: &[Connection], b: &[Connection]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.id == y.id)
+}
+
+/// Find up to `count` of the cheapest routes from `start` to `end` which
+/// never visit a star twice, using Yen's algorithm: each new route leaves
+/// one of the earlier routes at some star, and takes the best path from
+/// there which doesn't go back through the earlier route's stars or
+/// repeat a turn that an earlier route already took.
+#[allow(clippy::too_many_arguments)]
+pub fn calc_alternatives(
+    universe: &Universe,
+    start: &Star,
+    end: &Star,
+    jump_distance: Meters,
+    optimize: PathOptimize,
+    constraints: &PathConstraints,
+    count: usize,
+    budget: &SearchBudget,
+) -> Result<AlternativesResult> {
+    if count == 0 {
+        return Err(Error::InvalidParameter {
+            name: "count",
+            message: "must be at least 1".to_string(),
+        });
+    }
+    let best = match calc_path(
+        universe,
+        start,
+        end,
+        jump_distance,
+        optimize,
+        constraints,
+        PathAlgorithm::default(),
+        budget,
+    ) {
+        PathResult::Found(path) => path,
+        PathResult::NotFound => return Ok(AlternativesResult::NotFound),
+        PathResult::Timeout(_) => return Ok(AlternativesResult::Timeout),
+    };
+    let start_idx = universe.star_id_to_idx[&start.id];
+    let mut found: Vec<Vec<Connection>> = vec![best];
+    let mut candidates: Vec<(f64, Vec<Connection>)> = Vec::new();
+
+    'search: while found.len() < count {
+        let previous = found[found.len() - 1].clone();
+        let stars: Vec<StarIdx> = std::iter::once(start_idx)
+            .chain(previous.iter().map(|c| c.target))
+            .collect();
+        for i in 0..previous.len() {
+            let root = &previous[..i];
+            let spur_star = &universe.stars[stars[i]];
+
+            let mut spur_constraints = constraints.clone();
+            // Don't loop back through the root...
+            spur_constraints
+                .avoid_systems
+                .extend(stars[..i].iter().map(|&idx| universe.stars[idx].id));
+            // ...or leave it the same way as any route we already have
+            spur_constraints.avoid_connections.extend(
+                found
+                    .iter()
+                    .filter(|path| path.len() > i && same_connections(&path[..i], root))
+                    .map(|path| path[i].id),
+            );
+            if let Some(max_jumps) = constraints.max_jumps {
+                let root_jumps = root
+                    .iter()
+                    .filter(|c| c.conn_type == ConnType::Jump)
+                    .count();
+                spur_constraints.max_jumps = Some(max_jumps - root_jumps);
+            }
+
+            let spur = match calc_path(
+                universe,
+                spur_star,
+                end,
+                jump_distance,
+                optimize,
+                &spur_constraints,
+                PathAlgorithm::default(),
+                budget,
+            ) {
+                PathResult::Found(spur) => spur,
+                PathResult::NotFound => continue,
+                PathResult::Timeout(_) => break 'search,
+            };
+            let path: Vec<Connection> = root.iter().cloned().chain(spur).collect();
+            if !candidates.iter().any(|(_, p)| same_connections(p, &path)) {
+                candidates.push((path_cost(optimize, &path), path));
+            }
+        }
+
+        // The cheapest candidate is the next best route
+        let Some(next) = candidates
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1 .0.total_cmp(&b.1 .0))
+            .map(|(i, _)| i)
+        else {
+            break;
+        };
+        found.push(candidates.remove(next).1);
+    }
+
+    let best_cost = path_cost(optimize, &found[0]);
+    Ok(AlternativesResult::Found(
+        found
+            .into_iter()
+            .map(|path| {
+                let cost = path_cost(optimize, &path);
+                Alternative {
+                    path,
+                    cost,
+                    extra_cost: cost - best_cost,
+                }
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alternatives(
+        start: StarIdx,
+        end: StarIdx,
+        jump_distance: f64,
+        use_smart_gates: bool,
+        count: usize,
+    ) -> Vec<(Vec<StarIdx>, f64)> {
+        let universe = Universe::tiny_test();
+        match calc_alternatives(
+            &universe,
+            &universe.stars[start],
+            &universe.stars[end],
+            Meters::new(jump_distance),
+            PathOptimize::Distance,
+            &PathConstraints::with_smart_gates(use_smart_gates),
+            count,
+            &SearchBudget::unlimited(),
+        )
+        .expect("Count should be valid")
+        {
+            AlternativesResult::Found(routes) => routes
+                .into_iter()
+                .map(|r| {
+                    let targets = r.path.iter().map(|c| c.target).collect();
+                    (
+                        targets,
+                        Meters::from_light_years(r.extra_cost).get().round(),
+                    )
+                })
+                .collect(),
+            AlternativesResult::NotFound => vec![],
+            AlternativesResult::Timeout => panic!("Path search timed out"),
+        }
+    }
+
+    #[test]
+    fn test_alternatives_ranked() {
+        // 1 -> 2 directly (10), via 0 (10 + 20), via 3 (22 + 20), and then
+        // via 0 and 3 in either order, using the gate between them (28)
+        let routes = alternatives(1, 2, 25.0, false, 10);
+        assert_eq!(routes.len(), 5);
+        assert_eq!(routes[0], (vec![2], 0.0));
+        assert_eq!(routes[1], (vec![0, 2], 20.0));
+        assert_eq!(routes[2], (vec![3, 2], 32.0));
+        assert_eq!(routes[3], (vec![0, 3, 2], 48.0));
+        assert_eq!(routes[4], (vec![3, 0, 2], 61.0));
+        // Every route is different, loopless, and no better than the last
+        for (i, (path, extra)) in routes.iter().enumerate() {
+            assert_eq!(path.last(), Some(&2));
+            let mut stars = path.clone();
+            stars.sort();
+            stars.dedup();
+            assert_eq!(stars.len(), path.len());
+            assert!(!routes[..i].iter().any(|(p, _)| p == path));
+            if i > 0 {
+                assert!(*extra >= routes[i - 1].1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alternatives_gate_and_jump_between_same_stars() {
+        // The smart gate and the jump are different routes
+        let routes = alternatives(3, 2, 25.0, true, 2);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].0, vec![2]);
+        assert_eq!(routes[1].0, vec![2]);
+    }
+
+    #[test]
+    fn test_alternatives_run_out() {
+        // Jumps too short to go anywhere except by the 0 <-> 3 gate
+        assert_eq!(alternatives(0, 3, 5.0, false, 3), vec![(vec![3], 0.0)]);
+        assert_eq!(alternatives(0, 1, 5.0, false, 3), vec![]);
+    }
+
+    #[test]
+    fn test_alternatives_need_count() {
+        let universe = Universe::tiny_test();
+        assert!(matches!(
+            calc_alternatives(
+                &universe,
+                &universe.stars[1],
+                &universe.stars[2],
+                Meters::new(25.0),
+                PathOptimize::Distance,
+                &PathConstraints::default(),
+                0,
+                &SearchBudget::unlimited(),
+            ),
+            Err(Error::InvalidParameter { name: "count", .. })
+        ));
+    }
+}
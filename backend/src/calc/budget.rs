@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Stops a search from elsewhere, eg another thread. Clones share the
+/// same flag, so keep one and give the other to the search.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How much work a search may do before giving up.
+///
+/// The clock starts when the budget is made, not when a search starts
+/// using it, and every search given the same budget draws from it - so
+/// a route made of several searches is limited as a whole.
+#[derive(Debug, Default)]
+pub struct SearchBudget {
+    deadline: Option<Instant>,
+    max_expansions: Option<usize>,
+    expansions: AtomicUsize,
+    cancel: Option<CancelToken>,
+}
+
+impl SearchBudget {
+    pub fn unlimited() -> SearchBudget {
+        SearchBudget::default()
+    }
+
+    /// Give up once this much time has passed
+    pub fn with_time(mut self, time: Duration) -> SearchBudget {
+        self.deadline = Instant::now().checked_add(time);
+        self
+    }
+
+    /// Give up after expanding this many stars
+    pub fn with_max_expansions(mut self, max_expansions: usize) -> SearchBudget {
+        self.max_expansions = Some(max_expansions);
+        self
+    }
+
+    /// Give up once `token` is cancelled
+    pub fn with_cancel(mut self, token: CancelToken) -> SearchBudget {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Count one more star expanded, returning false if the budget has
+    /// run out and the search should stop
+    pub fn expand(&self) -> bool {
+        let expansions = self.expansions.fetch_add(1, Ordering::Relaxed) + 1;
+        !(self.max_expansions.is_some_and(|max| expansions > max)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            || self
+                .cancel
+                .as_ref()
+                .is_some_and(|token| token.is_cancelled()))
+    }
+
+    /// How many stars have been expanded so far
+    pub fn expansions(&self) -> usize {
+        self.expansions.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_limits() {
+        let budget = SearchBudget::unlimited();
+        assert!((0..1000).all(|_| budget.expand()));
+        assert_eq!(budget.expansions(), 1000);
+
+        let budget = SearchBudget::unlimited().with_max_expansions(2);
+        assert!(budget.expand());
+        assert!(budget.expand());
+        assert!(!budget.expand());
+
+        let budget = SearchBudget::unlimited().with_time(Duration::ZERO);
+        assert!(!budget.expand());
+        let budget = SearchBudget::unlimited().with_time(Duration::MAX);
+        assert!(budget.expand());
+
+        let token = CancelToken::new();
+        let budget = SearchBudget::unlimited().with_cancel(token.clone());
+        assert!(budget.expand());
+        token.cancel();
+        assert!(!budget.expand());
+    }
+}
@@ -0,0 +1,50 @@
+use std::cmp::Ordering;
+
+/// A `BinaryHeap` entry which pops the lowest `cost` first, with ties
+/// going to the lowest `item` so that searches always come out the same
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MinCost<T> {
+    pub cost: f64,
+    pub item: T,
+}
+
+impl<T: Ord> PartialEq for MinCost<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<T: Ord> Eq for MinCost<T> {}
+impl<T: Ord> PartialOrd for MinCost<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T: Ord> Ord for MinCost<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.item.cmp(&self.item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BinaryHeap;
+
+    #[test]
+    fn test_cheapest_first() {
+        let mut heap = BinaryHeap::new();
+        for (cost, item) in [(2.0, 1), (1.0, 3), (2.0, 0), (f64::INFINITY, 0), (1.0, 2)] {
+            heap.push(MinCost { cost, item });
+        }
+        let popped: Vec<(f64, i32)> = std::iter::from_fn(|| heap.pop())
+            .map(|m| (m.cost, m.item))
+            .collect();
+        assert_eq!(
+            popped,
+            vec![(1.0, 2), (1.0, 3), (2.0, 0), (2.0, 1), (f64::INFINITY, 0)]
+        );
+    }
+}
@@ -0,0 +1,669 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Instant;
+
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::calc::budget::SearchBudget;
+use crate::calc::heap::MinCost;
+use crate::calc::path::{successors, PathConstraints, PathOptimize, PathResult, SearchStats};
+use crate::data::*;
+use crate::units::Meters;
+
+/// Give up looking for a witness path after settling this many stars, and
+/// add the shortcut anyway. Extra shortcuts cost a little space, but never
+/// give wrong answers.
+const MAX_WITNESS_SETTLED: usize = 500;
+
+/// The settings a hierarchy is built for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HierarchyKey {
+    pub jump_distance: Meters,
+    pub optimize: PathOptimize,
+    pub use_smart_gates: bool,
+}
+
+impl HierarchyKey {
+    /// Can a hierarchy built like this answer a query with these settings?
+    /// Only if they are exactly the same, with nothing else to avoid.
+    pub fn matches(
+        &self,
+        jump_distance: Meters,
+        optimize: PathOptimize,
+        constraints: &PathConstraints,
+    ) -> bool {
+        self.jump_distance == jump_distance
+            && self.optimize == optimize
+            && *constraints == PathConstraints::with_smart_gates(self.use_smart_gates)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Via {
+    Connection(Connection),
+    /// Two edges joined together, skipping over the star between them
+    Shortcut(u32, u32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Edge {
+    from: StarIdx,
+    to: StarIdx,
+    cost: f64,
+    via: Via,
+}
+
+/// A contraction hierarchy: every star gets a rank, and shortcuts are
+/// added so that the cheapest path between any two stars can always go
+/// up in rank and then back down. A query then only needs to search
+/// upwards from both ends, which touches a tiny part of the map.
+///
+/// Building one takes a while, and it only works for one jump range,
+/// optimisation and smart gate setting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractionHierarchy {
+    pub key: HierarchyKey,
+    edges: Vec<Edge>,
+    /// Edges from each star to higher ranked stars
+    up: Vec<Vec<u32>>,
+    /// Edges into each star from higher ranked stars
+    down: Vec<Vec<u32>>,
+}
+
+/// The graph while it is being contracted. Only edges between stars which
+/// haven't been contracted yet are in `out` and `inc`.
+struct Builder {
+    edges: Vec<Edge>,
+    /// Edges which a cheaper one between the same stars has replaced.
+    /// They stay in `edges` since older shortcuts may be made of them,
+    /// but the finished hierarchy never searches them.
+    superseded: Vec<bool>,
+    /// The cheapest edge from each star to each neighbour
+    out: Vec<FxHashMap<StarIdx, u32>>,
+    /// The cheapest edge into each star from each neighbour
+    inc: Vec<FxHashMap<StarIdx, u32>>,
+    /// How many of each star's neighbours have been contracted
+    contracted_neighbours: Vec<i64>,
+    /// How many contractions deep each star's neighbourhood goes
+    depth: Vec<i64>,
+}
+
+impl Builder {
+    fn new(n: usize) -> Builder {
+        Builder {
+            edges: Vec::new(),
+            superseded: Vec::new(),
+            out: vec![FxHashMap::default(); n],
+            inc: vec![FxHashMap::default(); n],
+            contracted_neighbours: vec![0; n],
+            depth: vec![0; n],
+        }
+    }
+
+    fn add_edge(&mut self, edge: Edge) {
+        if let Some(&existing) = self.out[edge.from].get(&edge.to) {
+            if self.edges[existing as usize].cost <= edge.cost {
+                return;
+            }
+            self.superseded[existing as usize] = true;
+        }
+        let idx = self.edges.len() as u32;
+        self.out[edge.from].insert(edge.to, idx);
+        self.inc[edge.to].insert(edge.from, idx);
+        self.edges.push(edge);
+        self.superseded.push(false);
+    }
+
+    /// How soon to contract `star`, lowest first, and the shortcuts that
+    /// contracting it would need. The least important stars go first:
+    /// those which need fewer shortcuts than the edges they remove, and
+    /// whose neighbourhood hasn't been contracted much already, so that
+    /// the work is spread evenly over the map.
+    fn priority(&self, star: StarIdx) -> (i64, Vec<Edge>) {
+        let shortcuts = self.shortcuts(star);
+        let removed = (self.inc[star].len() + self.out[star].len()) as i64;
+        (
+            shortcuts.len() as i64 - removed + self.contracted_neighbours[star] + self.depth[star],
+            shortcuts,
+        )
+    }
+
+    /// Remove `star` from the graph, adding the shortcuts it needs
+    fn contract(&mut self, star: StarIdx, shortcuts: Vec<Edge>) {
+        for shortcut in shortcuts {
+            self.add_edge(shortcut);
+        }
+        let neighbours: Vec<StarIdx> = self.inc[star]
+            .keys()
+            .chain(self.out[star].keys())
+            .cloned()
+            .collect();
+        for neighbour in neighbours {
+            self.out[neighbour].remove(&star);
+            self.inc[neighbour].remove(&star);
+            self.contracted_neighbours[neighbour] += 1;
+            self.depth[neighbour] = self.depth[neighbour].max(self.depth[star] + 1);
+        }
+        self.inc[star].clear();
+        self.out[star].clear();
+    }
+
+    /// The shortcuts needed to remove `star` from the graph without making
+    /// any path between its neighbours more expensive
+    fn shortcuts(&self, star: StarIdx) -> Vec<Edge> {
+        let mut shortcuts = Vec::new();
+        for (&from, &first) in self.inc[star].iter() {
+            let first_cost = self.edges[first as usize].cost;
+            let targets: Vec<(StarIdx, u32, f64)> = self.out[star]
+                .iter()
+                .filter(|(&to, _)| to != from)
+                .map(|(&to, &second)| (to, second, first_cost + self.edges[second as usize].cost))
+                .collect();
+            let Some(max_cost) = targets.iter().map(|t| t.2).max_by(f64::total_cmp) else {
+                continue;
+            };
+            let witness = self.witness_search(from, star, max_cost);
+            for (to, second, cost) in targets {
+                if witness.get(&to).is_none_or(|&c| c > cost) {
+                    shortcuts.push(Edge {
+                        from,
+                        to,
+                        cost,
+                        via: Via::Shortcut(first, second),
+                    });
+                }
+            }
+        }
+        shortcuts
+    }
+
+    /// Sort the edges into those going up and down in `rank`
+    fn finish(self, key: HierarchyKey, rank: &[u32]) -> ContractionHierarchy {
+        let mut up = vec![Vec::new(); rank.len()];
+        let mut down = vec![Vec::new(); rank.len()];
+        for (idx, edge) in self.edges.iter().enumerate() {
+            if self.superseded[idx] {
+                continue;
+            }
+            if rank[edge.from] < rank[edge.to] {
+                up[edge.from].push(idx as u32);
+            } else {
+                down[edge.to].push(idx as u32);
+            }
+        }
+        ContractionHierarchy {
+            key,
+            edges: self.edges,
+            up,
+            down,
+        }
+    }
+
+    /// Cheapest costs from `from` without going through `skip`, up to
+    /// `max_cost`
+    fn witness_search(
+        &self,
+        from: StarIdx,
+        skip: StarIdx,
+        max_cost: f64,
+    ) -> FxHashMap<StarIdx, f64> {
+        let mut best: FxHashMap<StarIdx, f64> = FxHashMap::default();
+        let mut open = BinaryHeap::new();
+        best.insert(from, 0.0);
+        open.push(MinCost {
+            cost: 0.0,
+            item: from,
+        });
+        let mut settled = 0;
+        while let Some(MinCost {
+            cost,
+            item: star_idx,
+        }) = open.pop()
+        {
+            if cost > best[&star_idx] {
+                continue;
+            }
+            settled += 1;
+            if settled > MAX_WITNESS_SETTLED {
+                break;
+            }
+            for (&next, &edge) in self.out[star_idx].iter() {
+                let new_cost = cost + self.edges[edge as usize].cost;
+                if next == skip || new_cost > max_cost {
+                    continue;
+                }
+                if best.get(&next).is_none_or(|&c| new_cost < c) {
+                    best.insert(next, new_cost);
+                    open.push(MinCost {
+                        cost: new_cost,
+                        item: next,
+                    });
+                }
+            }
+        }
+        best
+    }
+}
+
+impl ContractionHierarchy {
+    pub fn build(universe: &Universe, key: HierarchyKey) -> ContractionHierarchy {
+        let n = universe.stars.len();
+        let constraints = PathConstraints::with_smart_gates(key.use_smart_gates);
+        let mut builder = Builder::new(n);
+        for from in 0..n {
+            for (conn, cost) in successors(
+                universe,
+                from,
+                key.jump_distance,
+                &key.optimize,
+                &constraints,
+            ) {
+                if conn.target != from {
+                    builder.add_edge(Edge {
+                        from,
+                        to: conn.target,
+                        cost,
+                        via: Via::Connection(conn),
+                    });
+                }
+            }
+        }
+
+        let mut queue: BinaryHeap<Reverse<(i64, StarIdx)>> = (0..n)
+            .into_par_iter()
+            .map(|star| Reverse((builder.priority(star).0, star)))
+            .collect();
+        let mut rank = vec![0u32; n];
+        let mut next_rank = 0;
+        while let Some(Reverse((_, star))) = queue.pop() {
+            // Priorities go stale as the neighbours get contracted, so
+            // check this star is still the best choice
+            let (priority, shortcuts) = builder.priority(star);
+            if let Some(Reverse((next, _))) = queue.peek() {
+                if priority > *next {
+                    queue.push(Reverse((priority, star)));
+                    continue;
+                }
+            }
+            builder.contract(star, shortcuts);
+            rank[star] = next_rank;
+            next_rank += 1;
+        }
+        builder.finish(key, &rank)
+    }
+
+    /// How many shortcuts were added on top of the real connections
+    pub fn shortcut_count(&self) -> usize {
+        self.edges
+            .iter()
+            .filter(|e| matches!(e.via, Via::Shortcut(..)))
+            .count()
+    }
+
+    /// The cheapest path from `start` to `end`. Both halves of the search
+    /// head up the hierarchy rather than towards the other end, so if the
+    /// budget runs out there is no closest approach to give back.
+    pub fn path(
+        &self,
+        start: StarIdx,
+        end: StarIdx,
+        budget: &SearchBudget,
+        stats: &mut SearchStats,
+    ) -> PathResult {
+        let mut forward = Upward::new(start, true);
+        let mut backward = Upward::new(end, false);
+        stats.pushed += 2;
+        let mut meet = (start == end).then_some((0.0, start));
+        // Every path worth finding goes up and then down, so once both
+        // sides are past the best meeting point, there's nothing better
+        loop {
+            let best = meet.map_or(f64::INFINITY, |(cost, _)| cost);
+            let f = forward.min_cost().filter(|&c| c < best);
+            let b = backward.min_cost().filter(|&c| c < best);
+            let within_budget = match (f, b) {
+                (Some(f), Some(b)) if b < f => {
+                    backward.step(self, &forward, &mut meet, budget, stats)
+                }
+                (Some(_), _) => forward.step(self, &backward, &mut meet, budget, stats),
+                (None, Some(_)) => backward.step(self, &forward, &mut meet, budget, stats),
+                (None, None) => break,
+            };
+            stats.peak_open = stats
+                .peak_open
+                .max(forward.open.len() + backward.open.len());
+            if !within_budget {
+                return PathResult::Timeout(Vec::new());
+            }
+        }
+        let Some((total, meet)) = meet else {
+            return PathResult::NotFound;
+        };
+        stats.final_g = total;
+
+        let t = Instant::now();
+        let mut first_half = Vec::new();
+        let mut star_idx = meet;
+        while let Some(edge) = forward.best[&star_idx].1 {
+            first_half.push(edge);
+            star_idx = self.edges[edge as usize].from;
+        }
+        let mut path = Vec::new();
+        for edge in first_half.into_iter().rev() {
+            self.unpack(edge, &mut path);
+        }
+        let mut star_idx = meet;
+        while let Some(edge) = backward.best[&star_idx].1 {
+            self.unpack(edge, &mut path);
+            star_idx = self.edges[edge as usize].to;
+        }
+        stats.path_time = t.elapsed();
+        PathResult::Found(path)
+    }
+
+    /// Expand an edge back into the connections it stands for
+    fn unpack(&self, edge: u32, path: &mut Vec<Connection>) {
+        match &self.edges[edge as usize].via {
+            Via::Connection(conn) => path.push(conn.clone()),
+            Via::Shortcut(first, second) => {
+                self.unpack(*first, path);
+                self.unpack(*second, path);
+            }
+        }
+    }
+}
+
+/// One half of a query: Dijkstra from one end, using only edges towards
+/// higher ranked stars
+struct Upward {
+    /// The cost to reach each star, and the last edge used to get there
+    best: FxHashMap<StarIdx, (f64, Option<u32>)>,
+    open: BinaryHeap<MinCost<StarIdx>>,
+    /// Searching forwards from the start along `up`, or backwards from the
+    /// end along `down`
+    forward: bool,
+}
+
+impl Upward {
+    fn new(from: StarIdx, forward: bool) -> Upward {
+        let mut best = FxHashMap::default();
+        best.insert(from, (0.0, None));
+        let mut open = BinaryHeap::new();
+        open.push(MinCost {
+            cost: 0.0,
+            item: from,
+        });
+        Upward {
+            best,
+            open,
+            forward,
+        }
+    }
+
+    /// The cost of the next star to settle, skipping stale heap entries
+    fn min_cost(&mut self) -> Option<f64> {
+        while let Some(top) = self.open.peek() {
+            if top.cost > self.best[&top.item].0 {
+                self.open.pop();
+            } else {
+                return Some(top.cost);
+            }
+        }
+        None
+    }
+
+    /// Settle the next star, and note any cheaper meeting with `other`.
+    /// Returns false if the budget has run out.
+    fn step(
+        &mut self,
+        ch: &ContractionHierarchy,
+        other: &Upward,
+        meet: &mut Option<(f64, StarIdx)>,
+        budget: &SearchBudget,
+        stats: &mut SearchStats,
+    ) -> bool {
+        let Some(MinCost {
+            cost,
+            item: star_idx,
+        }) = self.open.pop()
+        else {
+            return true;
+        };
+        let (adjacent, opposite) = match self.forward {
+            true => (&ch.up, &ch.down),
+            false => (&ch.down, &ch.up),
+        };
+        let ends = |edge: &Edge| match self.forward {
+            true => (edge.from, edge.to),
+            false => (edge.to, edge.from),
+        };
+
+        // If a higher ranked star reaches this one more cheaply, then we
+        // got here the long way round, and nothing past here is useful
+        let stalled = opposite[star_idx].iter().any(|&edge_idx| {
+            let edge = &ch.edges[edge_idx as usize];
+            let (higher, _) = ends(edge);
+            self.best
+                .get(&higher)
+                .is_some_and(|&(c, _)| c + edge.cost < cost)
+        });
+        if stalled {
+            return true;
+        }
+        if !budget.expand() {
+            return false;
+        }
+        stats.expanded += 1;
+
+        for &edge_idx in &adjacent[star_idx] {
+            let edge = &ch.edges[edge_idx as usize];
+            let (_, target) = ends(edge);
+            let new_cost = cost + edge.cost;
+            if self.best.get(&target).is_none_or(|&(c, _)| new_cost < c) {
+                self.best.insert(target, (new_cost, Some(edge_idx)));
+                self.open.push(MinCost {
+                    cost: new_cost,
+                    item: target,
+                });
+                stats.pushed += 1;
+                if let Some(&(other_cost, _)) = other.best.get(&target) {
+                    let total = new_cost + other_cost;
+                    if meet.is_none_or(|(c, _)| total < c) {
+                        *meet = Some((total, target));
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::budget::CancelToken;
+    use crate::calc::path::{calc_path, cost, PathAlgorithm};
+
+    fn key(jump_distance: f64, optimize: PathOptimize, use_smart_gates: bool) -> HierarchyKey {
+        HierarchyKey {
+            jump_distance: Meters::new(jump_distance),
+            optimize,
+            use_smart_gates,
+        }
+    }
+
+    #[test]
+    fn test_hierarchy_matches_astar() {
+        let universe = Universe::tiny_test();
+        for optimize in [
+            PathOptimize::Fuel,
+            PathOptimize::Distance,
+            PathOptimize::Hops,
+        ] {
+            for use_smart_gates in [false, true] {
+                for jump_distance in [5.0, 15.0, 25.0] {
+                    let key = key(jump_distance, optimize, use_smart_gates);
+                    let hierarchy = ContractionHierarchy::build(&universe, key);
+                    let constraints = PathConstraints::with_smart_gates(use_smart_gates);
+                    for start in 0..universe.stars.len() {
+                        for end in 0..universe.stars.len() {
+                            let expected = match calc_path(
+                                &universe,
+                                &universe.stars[start],
+                                &universe.stars[end],
+                                key.jump_distance,
+                                optimize,
+                                &constraints,
+                                PathAlgorithm::AStar,
+                                &SearchBudget::unlimited(),
+                            ) {
+                                PathResult::Found(path) => Some(path),
+                                _ => None,
+                            };
+                            let found = match hierarchy.path(
+                                start,
+                                end,
+                                &SearchBudget::unlimited(),
+                                &mut Default::default(),
+                            ) {
+                                PathResult::Found(path) => Some(path),
+                                PathResult::NotFound => None,
+                                PathResult::Timeout(_) => panic!("Path search timed out"),
+                            };
+                            let total = |path: &[Connection]| {
+                                path.iter().map(|c| cost(optimize, c)).sum::<f64>()
+                            };
+                            assert_eq!(
+                                found.as_deref().map(total),
+                                expected.as_deref().map(total),
+                                "{:?} from {} to {}",
+                                key,
+                                start,
+                                end
+                            );
+                            // The unpacked path joins up from start to end
+                            if let Some(path) = found {
+                                let mut here = start;
+                                for conn in &path {
+                                    assert!(universe
+                                        .connections(here, key.jump_distance)
+                                        .contains(conn));
+                                    here = conn.target;
+                                }
+                                assert_eq!(here, end);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hierarchy_skips_superseded_edges() {
+        let edge = |from, to, cost| Edge {
+            from,
+            to,
+            cost,
+            via: Via::Shortcut(0, 0),
+        };
+        let mut builder = Builder::new(3);
+        builder.add_edge(edge(0, 1, 10.0));
+        builder.add_edge(edge(1, 2, 10.0));
+        // A cheaper edge replaces the first, a dearer one is ignored
+        builder.add_edge(edge(0, 1, 5.0));
+        builder.add_edge(edge(1, 2, 20.0));
+        assert_eq!(builder.edges.len(), 3);
+
+        let hierarchy = builder.finish(key(1.0, PathOptimize::Hops, false), &[0, 1, 2]);
+        let costs = |edges: &[u32]| {
+            edges
+                .iter()
+                .map(|&e| hierarchy.edges[e as usize].cost)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(costs(&hierarchy.up[0]), vec![5.0]);
+        assert_eq!(costs(&hierarchy.up[1]), vec![10.0]);
+        assert!(hierarchy.down.iter().all(|d| d.is_empty()));
+    }
+
+    #[test]
+    fn test_hierarchy_only_used_for_its_own_settings() {
+        let mut universe = Universe::tiny_test();
+        universe.add_hierarchy(key(25.0, PathOptimize::Hops, true));
+        universe.add_hierarchy(key(25.0, PathOptimize::Hops, true));
+        assert_eq!(universe.hierarchies.len(), 1);
+
+        let jump = Meters::new(25.0);
+        let plain = PathConstraints::with_smart_gates(true);
+        assert!(universe
+            .hierarchy(jump, PathOptimize::Hops, &plain)
+            .is_some());
+        assert!(universe
+            .hierarchy(jump, PathOptimize::Fuel, &plain)
+            .is_none());
+        assert!(universe
+            .hierarchy(Meters::new(20.0), PathOptimize::Hops, &plain)
+            .is_none());
+        let no_gates = PathConstraints::with_smart_gates(false);
+        assert!(universe
+            .hierarchy(jump, PathOptimize::Hops, &no_gates)
+            .is_none());
+        let avoiding = PathConstraints {
+            avoid_systems: [universe.stars[0].id].into(),
+            ..plain.clone()
+        };
+        assert!(universe
+            .hierarchy(jump, PathOptimize::Hops, &avoiding)
+            .is_none());
+
+        // Falling back to A* still respects the constraints
+        let path = calc_path(
+            &universe,
+            &universe.stars[3],
+            &universe.stars[1],
+            jump,
+            PathOptimize::Hops,
+            &avoiding,
+            PathAlgorithm::Hierarchy,
+            &SearchBudget::unlimited(),
+        );
+        match path {
+            PathResult::Found(path) => assert!(path.iter().all(|c| c.target != 0)),
+            other => panic!("Expected a path, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_hierarchy_respects_budget() {
+        let mut universe = Universe::tiny_test();
+        universe.add_hierarchy(key(25.0, PathOptimize::Hops, true));
+        let path = |budget: &SearchBudget| {
+            calc_path(
+                &universe,
+                &universe.stars[1],
+                &universe.stars[3],
+                Meters::new(25.0),
+                PathOptimize::Hops,
+                &PathConstraints::with_smart_gates(true),
+                PathAlgorithm::Hierarchy,
+                budget,
+            )
+        };
+        assert!(matches!(
+            path(&SearchBudget::unlimited()),
+            PathResult::Found(_)
+        ));
+        assert_eq!(
+            path(&SearchBudget::unlimited().with_max_expansions(1)),
+            PathResult::Timeout(Vec::new())
+        );
+        let token = CancelToken::new();
+        token.cancel();
+        assert_eq!(
+            path(&SearchBudget::unlimited().with_cancel(token)),
+            PathResult::Timeout(Vec::new())
+        );
+    }
+}
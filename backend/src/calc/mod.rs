@@ -1,2 +1,12 @@
+pub mod alternatives;
+pub mod budget;
 pub mod exit;
+mod heap;
+pub mod hierarchy;
+pub mod nearest;
 pub mod path;
+pub mod rally;
+pub mod reach;
+pub mod route;
+pub mod ship;
+pub mod weighted;
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use crate::calc::budget::SearchBudget;
+use crate::calc::path::{CostModel, PathConstraints};
+use crate::calc::reach::{calc_reach_until, ReachLimits, ReachResult};
+use crate::data::*;
+use crate::units::Meters;
+
+/// Built-in kinds of system to look for. Anything else can be searched
+/// for by giving `calc_nearest` a predicate of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SystemFilter {
+    /// Has a smart gate leading out of it
+    SmartGate,
+    /// Is in this region
+    Region(RegionId),
+    /// Has an NPC gate, but isn't on the same network of NPC gates as
+    /// the start
+    OtherGateNetwork,
+    /// Is one of these systems
+    OneOf(HashSet<SolarSystemId>),
+}
+
+impl SystemFilter {
+    /// Which stars match, for a search starting at `start`
+    pub fn predicate<'a>(
+        &'a self,
+        universe: &'a Universe,
+        start: &Star,
+    ) -> Box<dyn Fn(StarIdx) -> bool + 'a> {
+        let has_gate = move |star_idx: StarIdx, conn_type: ConnType| {
+            universe.stars[star_idx]
+                .connections
+                .iter()
+                .any(|c| c.conn_type == conn_type)
+        };
+        match self {
+            SystemFilter::SmartGate => Box::new(move |i| has_gate(i, ConnType::SmartGate)),
+            SystemFilter::Region(region_id) => {
+                Box::new(move |i| universe.stars[i].region_id == *region_id)
+            }
+            SystemFilter::OtherGateNetwork => {
+                let network = gate_network(universe, universe.star_id_to_idx[&start.id]);
+                Box::new(move |i| !network[i] && has_gate(i, ConnType::NpcGate))
+            }
+            SystemFilter::OneOf(ids) => Box::new(move |i| ids.contains(&universe.stars[i].id)),
+        }
+    }
+}
+
+/// Which stars can be reached from `start_idx` by NPC gates alone
+fn gate_network(universe: &Universe, start_idx: StarIdx) -> Vec<bool> {
+    let mut network = vec![false; universe.stars.len()];
+    network[start_idx] = true;
+    let mut to_visit = vec![start_idx];
+    while let Some(current) = to_visit.pop() {
+        for conn in &universe.stars[current].connections {
+            if conn.conn_type == ConnType::NpcGate && !network[conn.target] {
+                network[conn.target] = true;
+                to_visit.push(conn.target);
+            }
+        }
+    }
+    network
+}
+
+/// A matching star, and the cheapest way there
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nearest {
+    pub star_idx: StarIdx,
+    pub cost: f64,
+    pub path: Vec<Connection>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NearestResult {
+    /// The cheapest matches to get to, cheapest first. There may be
+    /// fewer than asked for if there aren't that many within reach.
+    Found(Vec<Nearest>),
+    NotFound,
+    /// The budget ran out, holding any matches found before then
+    Timeout(Vec<Nearest>),
+}
+
+/// Find the `count` stars matching `matches` which are cheapest to get
+/// to from `start` - by real route cost, not straight line distance,
+/// since the nearest match as the crow flies may be a long way round.
+/// The start counts, if it matches.
+#[allow(clippy::too_many_arguments)]
+pub fn calc_nearest(
+    universe: &Universe,
+    start: &Star,
+    jump_distance: Meters,
+    model: &dyn CostModel,
+    constraints: &PathConstraints,
+    matches: &dyn Fn(StarIdx) -> bool,
+    count: usize,
+    budget: &SearchBudget,
+) -> NearestResult {
+    let mut found: Vec<StarIdx> = Vec::new();
+    let result = calc_reach_until(
+        universe,
+        start,
+        jump_distance,
+        model,
+        constraints,
+        &ReachLimits::default(),
+        budget,
+        &mut |star_idx| {
+            if matches(star_idx) {
+                found.push(star_idx);
+            }
+            found.len() >= count
+        },
+    );
+    let (reach, timed_out) = match result {
+        ReachResult::Found(reach) => (reach, false),
+        ReachResult::Timeout(reach) => (reach, true),
+    };
+    let nearest: Vec<Nearest> = found
+        .into_iter()
+        .filter_map(|star_idx| {
+            Some(Nearest {
+                star_idx,
+                cost: reach.get(star_idx)?.cost,
+                path: reach.path_to(star_idx)?,
+            })
+        })
+        .collect();
+    match (timed_out, nearest.is_empty()) {
+        (true, _) => NearestResult::Timeout(nearest),
+        (false, true) => NearestResult::NotFound,
+        (false, false) => NearestResult::Found(nearest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::path::PathOptimize;
+
+    fn nearest(start: StarIdx, filter: SystemFilter, count: usize) -> Vec<(StarIdx, f64)> {
+        let universe = Universe::tiny_test();
+        let start = &universe.stars[start];
+        let matches = filter.predicate(&universe, start);
+        let result = calc_nearest(
+            &universe,
+            start,
+            Meters::new(15.0),
+            &PathOptimize::Distance,
+            &PathConstraints::default(),
+            &matches,
+            count,
+            &SearchBudget::unlimited(),
+        );
+        match result {
+            NearestResult::Found(found) => found
+                .into_iter()
+                .map(|n| (n.star_idx, Meters::from_light_years(n.cost).get().round()))
+                .collect(),
+            NearestResult::NotFound => vec![],
+            NearestResult::Timeout(_) => panic!("Search timed out"),
+        }
+    }
+
+    #[test]
+    fn test_nearest_filters() {
+        // 2 and 3 have smart gates, and 2 is nearer by jumping via 1
+        assert_eq!(nearest(0, SystemFilter::SmartGate, 1), vec![(2, 20.0)]);
+        assert_eq!(
+            nearest(0, SystemFilter::SmartGate, 5),
+            vec![(2, 20.0), (3, 28.0)]
+        );
+        assert_eq!(nearest(2, SystemFilter::Region(1000), 2), vec![(0, 20.0)]);
+        // The start counts if it matches
+        assert_eq!(nearest(1, SystemFilter::Region(1001), 1), vec![(1, 0.0)]);
+        // Only 0 and 3 have NPC gates, and they're on the same network
+        assert_eq!(
+            nearest(1, SystemFilter::OtherGateNetwork, 5),
+            vec![(0, 10.0), (3, 38.0)]
+        );
+        assert_eq!(nearest(0, SystemFilter::OtherGateNetwork, 5), vec![]);
+        assert_eq!(
+            nearest(1, SystemFilter::OneOf([1002, 1003].into()), 5),
+            vec![(2, 10.0), (3, 38.0)]
+        );
+    }
+
+    #[test]
+    fn test_nearest_by_route_not_distance() {
+        // 2 is 20 from 0 in a straight line and 3 is 28, but with no jumps
+        // in range, 2 can only be reached through 3's smart gate
+        let universe = Universe::tiny_test();
+        let start = &universe.stars[0];
+        let filter = SystemFilter::OneOf([1002, 1003].into());
+        let found = calc_nearest(
+            &universe,
+            start,
+            Meters::new(9.0),
+            &PathOptimize::Distance,
+            &PathConstraints::with_smart_gates(true),
+            &filter.predicate(&universe, start),
+            2,
+            &SearchBudget::unlimited(),
+        );
+        let NearestResult::Found(found) = found else {
+            panic!("Should have found both");
+        };
+        let targets = |n: &Nearest| n.path.iter().map(|c| c.target).collect::<Vec<_>>();
+        assert_eq!(
+            found.iter().map(targets).collect::<Vec<_>>(),
+            vec![vec![3], vec![3, 2]]
+        );
+    }
+}
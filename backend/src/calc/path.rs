@@ -1,108 +1,633 @@
+use std::collections::{BinaryHeap, HashSet};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::calc::budget::SearchBudget;
+use crate::calc::heap::MinCost;
 use crate::data::*;
 use crate::units::Meters;
 
-#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PathOptimize {
     Fuel,
     Distance,
     Hops,
 }
 
-/// Given a connection, return a list of all possible next-connections,
+/// How much it costs to travel along a connection
+pub fn cost(optimize: PathOptimize, conn: &Connection) -> f64 {
+    let distance = match (optimize, &conn.conn_type) {
+        // For shortest path, we only care about the distance
+        // and don't care about the type of connection
+        (PathOptimize::Distance, _) => conn.distance,
+        // For fuel efficient, we only care about the distance
+        // if it's a jump
+        (PathOptimize::Fuel, ConnType::Jump) => conn.distance,
+        // Gate connections are free (-ish. It still takes a tiny
+        // amount of fuel to warp to a gate)
+        (PathOptimize::Fuel, ConnType::NpcGate) => Meters::new(1.0),
+        // Smart gates are slightly more expensive than NPC gates
+        (PathOptimize::Fuel, ConnType::SmartGate) => Meters::new(2.0),
+        // Treat all hops the same, we want to minimise the total
+        (PathOptimize::Hops, _) => Meters::new(1.0),
+    };
+    distance.to_light_years()
+}
+
+/// What a path search keeps as low as it can: the cost of each
+/// connection, plus a guess at the cost left from any star to guide A*.
+///
+/// `PathOptimize` and `PathWeights` are built in, and anything else (eg
+/// tolls, risk or travel time) can be searched with by implementing this
+/// and calling `calc_path_with_model`.
+pub trait CostModel {
+    /// How much it costs to travel along a connection. Must never be
+    /// negative.
+    fn cost(&self, conn: &Connection) -> f64;
+
+    /// Lower bounds on the cost from each star to `end`. These must never
+    /// be more than the actual cost, or the path will be suboptimal. The
+    /// default doesn't guess at all, which is always safe but turns A*
+    /// into plain Dijkstra.
+    fn heuristic<'a>(
+        &'a self,
+        _universe: &'a Universe,
+        _end: &'a Star,
+        _jump_distance: Meters,
+        _constraints: &PathConstraints,
+    ) -> Box<dyn Fn(StarIdx) -> f64 + 'a> {
+        Box::new(|_| 0.0)
+    }
+}
+
+impl CostModel for PathOptimize {
+    fn cost(&self, conn: &Connection) -> f64 {
+        cost(*self, conn)
+    }
+
+    fn heuristic<'a>(
+        &'a self,
+        universe: &'a Universe,
+        end: &'a Star,
+        jump_distance: Meters,
+        constraints: &PathConstraints,
+    ) -> Box<dyn Fn(StarIdx) -> f64 + 'a> {
+        let heuristic = Heuristic::new(
+            universe,
+            end,
+            jump_distance,
+            *self,
+            constraints.use_smart_gates,
+        );
+        Box::new(move |star_idx| heuristic.estimate(star_idx))
+    }
+}
+
+/// Which connections a path is allowed to use. The default allows jumps
+/// and NPC gates, but not smart gates.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathConstraints {
+    pub use_smart_gates: bool,
+    /// Never enter these systems - not even as the destination
+    pub avoid_systems: HashSet<SolarSystemId>,
+    /// Never enter any system in these regions
+    pub avoid_regions: HashSet<RegionId>,
+    /// Never take these smart gates, by item ID
+    pub avoid_smart_gates: HashSet<u32>,
+    /// Never take these specific connections
+    pub avoid_connections: HashSet<ConnectionId>,
+    pub no_npc_gates: bool,
+    /// Only travel by gate
+    pub no_jumps: bool,
+    /// Take at most this many jumps in total (gates don't count)
+    pub max_jumps: Option<usize>,
+}
+
+impl PathConstraints {
+    pub fn with_smart_gates(use_smart_gates: bool) -> PathConstraints {
+        PathConstraints {
+            use_smart_gates,
+            ..Default::default()
+        }
+    }
+
+    /// Can this connection ever be taken? (`max_jumps` depends on the
+    /// path so far, so that is up to the search to check)
+    pub fn allows(&self, universe: &Universe, conn: &Connection) -> bool {
+        let type_allowed = match conn.conn_type {
+            ConnType::Jump => !self.no_jumps,
+            ConnType::NpcGate => !self.no_npc_gates,
+            ConnType::SmartGate => {
+                self.use_smart_gates
+                    && universe
+                        .smart_gate_item_ids
+                        .get(&conn.id)
+                        .is_none_or(|item_id| !self.avoid_smart_gates.contains(item_id))
+            }
+        };
+        let target = &universe.stars[conn.target];
+        type_allowed
+            && !self.avoid_connections.contains(&conn.id)
+            && !self.avoid_systems.contains(&target.id)
+            && !self.avoid_regions.contains(&target.region_id)
+    }
+}
+
+/// Given a star, return a list of all possible connections out of it,
 /// and what each of those connections costs
 pub fn successors(
     universe: &Universe,
-    conn: &Connection,
+    star_idx: StarIdx,
     jump_distance: Meters,
-    optimize: PathOptimize,
-    use_smart_gates: bool,
+    model: &dyn CostModel,
+    constraints: &PathConstraints,
 ) -> Vec<(Connection, f64)> {
-    let star = universe
-        .stars
-        .get(conn.target)
-        .expect("Target star not found in universe");
-    star.connections
-        .iter()
-        // take gates and short jumps - stop searching after we
-        // find a long jump
-        .take_while(|c| c.conn_type != ConnType::Jump || c.distance <= jump_distance)
-        // If we're not using smart gates, skip them
-        .filter(|c| use_smart_gates || c.conn_type != ConnType::SmartGate)
+    // With no jumps allowed, don't waste time looking any up
+    let jump_distance = match constraints.no_jumps {
+        true => Meters::new(0.0),
+        false => jump_distance,
+    };
+    universe
+        .connections(star_idx, jump_distance)
+        .into_iter()
+        .filter(|c| constraints.allows(universe, c))
         // Turn the connection into a (connection, cost) tuple
         .map(|c| {
-            let distance = match (optimize, &c.conn_type) {
-                // For shortest path, we only care about the distance
-                // and don't care about the type of connection
-                (PathOptimize::Distance, _) => c.distance,
-                // For fuel efficient, we only care about the distance
-                // if it's a jump
-                (PathOptimize::Fuel, ConnType::Jump) => c.distance,
-                // Gate connections are free (-ish. It still takes a tiny
-                // amount of fuel to warp to a gate)
-                (PathOptimize::Fuel, ConnType::NpcGate) => Meters::new(1.0),
-                // Smart gates are slightly more expensive than NPC gates
-                (PathOptimize::Fuel, ConnType::SmartGate) => Meters::new(2.0),
-                // Treat all hops the same, we want to minimise the total
-                (PathOptimize::Hops, _) => Meters::new(1.0),
-            };
-            (c.clone(), distance.to_light_years())
+            let cost = model.cost(&c);
+            (c, cost)
         })
         .collect()
 }
 
-/// Heuristic function for A* pathfinding
-/// - Return an approximation of the cost from this connection to the end
-/// - Must not return greater than the actual cost, or the path will be suboptimal
-///   - Remember that in "optimise for fuel" mode, actual cost might be 1
-pub fn heuristic(universe: &Universe, conn: &Connection, end: &Star) -> f64 {
-    universe.stars[conn.target].distance(end).to_light_years()
+/// Lower bounds on the cost from a star to the end, for A*
+///
+/// These must never be more than the actual cost, or the path will be
+/// suboptimal. Straight-line distance is fine when optimising for
+/// distance, but in fuel and hops mode a gate crosses any distance for
+/// a tiny cost. So there we also consider the cheapest route via gates
+/// that could possibly exist: jump to the nearest gate, take a single
+/// gate to wherever is nearest the end, and jump from there.
+///
+/// (Landmark bounds over the gate network would be tighter, but every
+/// query has a different jump range, so they can't be precomputed.)
+pub struct Heuristic<'a> {
+    universe: &'a Universe,
+    end: &'a Star,
+    jump_distance: Meters,
+    optimize: PathOptimize,
+    use_smart_gates: bool,
+    /// How far the end is from the nearest gate, if there are any gates
+    end_to_gate: Option<Meters>,
+}
+
+impl<'a> Heuristic<'a> {
+    pub fn new(
+        universe: &'a Universe,
+        end: &'a Star,
+        jump_distance: Meters,
+        optimize: PathOptimize,
+        use_smart_gates: bool,
+    ) -> Heuristic<'a> {
+        Heuristic {
+            universe,
+            end,
+            jump_distance,
+            optimize,
+            use_smart_gates,
+            end_to_gate: universe.nearest_gate(&end.loc, use_smart_gates),
+        }
+    }
+
+    pub fn estimate(&self, star_idx: StarIdx) -> f64 {
+        let star = &self.universe.stars[star_idx];
+        let direct = star.distance(self.end);
+        match self.optimize {
+            PathOptimize::Distance => direct.to_light_years(),
+            PathOptimize::Fuel => {
+                let direct = direct.to_light_years();
+                let gate_cost = self.cheapest_gate();
+                match self.end_to_gate.map(|m| m.to_light_years()) {
+                    // Only look for the nearest gate if a gate could help
+                    Some(end_to_gate) if direct > gate_cost + end_to_gate => {
+                        let to_gate = self.to_gate(star).to_light_years();
+                        direct.min(to_gate + gate_cost + end_to_gate)
+                    }
+                    _ => direct,
+                }
+            }
+            PathOptimize::Hops => {
+                let hop = cost(PathOptimize::Hops, &gate(ConnType::NpcGate));
+                let direct = self.hops_to_cover(direct);
+                let hops = match self.end_to_gate.map(|m| self.hops_to_cover(m)) {
+                    Some(end_to_gate) if direct > 1.0 + end_to_gate => {
+                        let to_gate = self.hops_to_cover(self.to_gate(star));
+                        direct.min(to_gate + 1.0 + end_to_gate)
+                    }
+                    _ => direct,
+                };
+                hops * hop
+            }
+        }
+    }
+
+    /// The least any gate could cost
+    fn cheapest_gate(&self) -> f64 {
+        cost(self.optimize, &gate(ConnType::NpcGate))
+            .min(cost(self.optimize, &gate(ConnType::SmartGate)))
+    }
+
+    fn to_gate(&self, star: &Star) -> Meters {
+        self.universe
+            .nearest_gate(&star.loc, self.use_smart_gates)
+            .unwrap_or(Meters::new(f64::INFINITY))
+    }
+
+    /// The fewest jumps it could take to cross `distance`. Rounded down a
+    /// hair first, so floating point error can't make us overestimate.
+    fn hops_to_cover(&self, distance: Meters) -> f64 {
+        if distance.get() <= 0.0 {
+            return 0.0;
+        }
+        (distance.get() / self.jump_distance.get() * (1.0 - 1e-9)).ceil()
+    }
+}
+
+/// A gate of the given type, for asking what gates cost
+fn gate(conn_type: ConnType) -> Connection {
+    Connection {
+        id: 0,
+        conn_type,
+        distance: Meters::new(0.0),
+        target: 0,
+    }
+}
+
+/// How to search for a path. All find equally cheap paths, but which is
+/// faster depends on the route.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathAlgorithm {
+    /// A* from the start, guided by `Heuristic`
+    #[default]
+    #[value(name = "astar")]
+    AStar,
+    /// Dijkstra from both ends at once, meeting in the middle
+    Bidirectional,
+    /// A precomputed contraction hierarchy, if the universe has one for
+    /// these settings, otherwise A*
+    Hierarchy,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum PathResult {
     Found(Vec<Connection>),
     NotFound,
-    Timeout,
+    /// The budget ran out. Holds the path to whichever star the search
+    /// reached which is closest to the end, which may be an empty path.
+    Timeout(Vec<Connection>),
+}
+
+/// How a path search went, for working out why it was slow or surprising
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchStats {
+    /// The algorithm which actually ran, after any fallback
+    pub algorithm: PathAlgorithm,
+    /// Stars taken from the open set and expanded
+    pub expanded: usize,
+    /// Entries added to the open set
+    pub pushed: usize,
+    /// The most entries the open set held at once
+    pub peak_open: usize,
+    /// Cost so far of the last star taken from the open set - the end,
+    /// if a path was found
+    pub final_g: f64,
+    /// The heuristic's estimate of the cost left from that star
+    pub final_h: f64,
+    /// The heuristic's estimate from the start, to compare with the cost
+    /// of the path found
+    pub start_h: f64,
+    /// Time spent getting ready, eg building the heuristic
+    pub setup_time: Duration,
+    pub search_time: Duration,
+    /// Time spent turning the search's results into a path
+    pub path_time: Duration,
 }
 
+/// Find the cheapest path from `start` to `end`.
+///
+/// Limiting the number of jumps depends on the whole path, which the
+/// bidirectional search can't see, so that always uses A*. Hierarchies
+/// only work for the settings they were built with, so anything else
+/// falls back to A* too.
+#[allow(clippy::too_many_arguments)]
 pub fn calc_path(
     universe: &Universe,
     start: &Star,
     end: &Star,
     jump_distance: Meters,
     optimize: PathOptimize,
-    use_smart_gates: bool,
-    timeout: Option<u64>,
+    constraints: &PathConstraints,
+    algorithm: PathAlgorithm,
+    budget: &SearchBudget,
 ) -> PathResult {
-    let init_conn = Connection {
-        id: 0,
-        conn_type: ConnType::Jump,
-        distance: Meters::from_light_years(0.0),
-        target: universe.star_id_to_idx[&start.id],
-    };
+    calc_path_explained(
+        universe,
+        start,
+        end,
+        jump_distance,
+        optimize,
+        constraints,
+        algorithm,
+        budget,
+    )
+    .0
+}
+
+/// Like `calc_path`, but also say how the search went
+#[allow(clippy::too_many_arguments)]
+pub fn calc_path_explained(
+    universe: &Universe,
+    start: &Star,
+    end: &Star,
+    jump_distance: Meters,
+    optimize: PathOptimize,
+    constraints: &PathConstraints,
+    algorithm: PathAlgorithm,
+    budget: &SearchBudget,
+) -> (PathResult, SearchStats) {
+    let t = Instant::now();
+    let mut stats = SearchStats::default();
+    let start_idx = universe.star_id_to_idx[&start.id];
     let end_idx = universe.star_id_to_idx[&end.id];
+    if algorithm == PathAlgorithm::Hierarchy {
+        if let Some(hierarchy) = universe.hierarchy(jump_distance, optimize, constraints) {
+            stats.algorithm = PathAlgorithm::Hierarchy;
+            stats.setup_time = t.elapsed();
+            let result = hierarchy.path(start_idx, end_idx, budget, &mut stats);
+            stats.search_time = t.elapsed() - stats.setup_time - stats.path_time;
+            return (result, stats);
+        }
+    }
+    calc_path_with_model(
+        universe,
+        start,
+        end,
+        jump_distance,
+        &optimize,
+        constraints,
+        algorithm,
+        budget,
+    )
+}
+
+/// Like `calc_path_explained`, but with any cost model. There are no
+/// hierarchies for other models, so `PathAlgorithm::Hierarchy` is always
+/// A* here.
+#[allow(clippy::too_many_arguments)]
+pub fn calc_path_with_model(
+    universe: &Universe,
+    start: &Star,
+    end: &Star,
+    jump_distance: Meters,
+    model: &dyn CostModel,
+    constraints: &PathConstraints,
+    algorithm: PathAlgorithm,
+    budget: &SearchBudget,
+) -> (PathResult, SearchStats) {
+    let t = Instant::now();
+    let mut stats = SearchStats::default();
+    let start_idx = universe.star_id_to_idx[&start.id];
+    let end_idx = universe.star_id_to_idx[&end.id];
+    if algorithm == PathAlgorithm::Bidirectional && constraints.max_jumps.is_none() {
+        stats.algorithm = PathAlgorithm::Bidirectional;
+        stats.setup_time = t.elapsed();
+        let result = calc_path_bidirectional(
+            universe,
+            start_idx,
+            end_idx,
+            jump_distance,
+            model,
+            constraints,
+            budget,
+            &mut stats,
+        );
+        stats.search_time = t.elapsed() - stats.setup_time - stats.path_time;
+        return (result, stats);
+    }
+    let heuristic = model.heuristic(universe, end, jump_distance, constraints);
+    stats.start_h = heuristic(start_idx);
+    stats.setup_time = t.elapsed();
+    // Search over (star, jumps taken so far). The jump count only matters
+    // when it's limited, so otherwise leave it at zero to avoid visiting
+    // each star once per count.
     let path = pathfinding::astar(
-        &init_conn,
-        |conn| successors(universe, conn, jump_distance, optimize, use_smart_gates),
-        |conn| heuristic(universe, conn, end),
-        |conn| conn.target == end_idx,
-        timeout,
+        &(start_idx, 0),
+        |&(star_idx, jumps)| {
+            successors(universe, star_idx, jump_distance, model, constraints)
+                .into_iter()
+                .filter_map(|(next, cost)| match constraints.max_jumps {
+                    Some(max_jumps) if next.conn_type == ConnType::Jump => {
+                        (jumps < max_jumps).then_some(((next.target, jumps + 1), next, cost))
+                    }
+                    _ => Some(((next.target, jumps), next, cost)),
+                })
+                .collect::<Vec<_>>()
+        },
+        |&(star_idx, _)| heuristic(star_idx),
+        |&(star_idx, _)| star_idx == end_idx,
+        |&(star_idx, _)| universe.stars[star_idx].distance(end).get(),
+        budget,
+        &mut stats,
     );
+    stats.search_time = t.elapsed() - stats.setup_time - stats.path_time;
 
-    match path {
-        pathfinding::PathFindResult::Found((path, _)) => {
-            // The first connection is the one we invented
-            // to start the search, so we can skip it
-            PathResult::Found(path[1..].to_vec())
-        }
+    let result = match path {
+        pathfinding::PathFindResult::Found((path, _)) => PathResult::Found(path),
         pathfinding::PathFindResult::NotFound => PathResult::NotFound,
-        pathfinding::PathFindResult::Timeout => PathResult::Timeout,
+        pathfinding::PathFindResult::Timeout(path) => PathResult::Timeout(path),
+    };
+    (result, stats)
+}
+
+/// One side of a bidirectional search
+struct Frontier {
+    /// Cheapest known cost between each star and this side's end
+    cost: Vec<f64>,
+    /// The neighbouring star which that cost goes via, and the connection
+    /// between them
+    via: Vec<Option<(StarIdx, Connection)>>,
+    open: BinaryHeap<MinCost<StarIdx>>,
+}
+
+impl Frontier {
+    fn new(stars: usize, from: StarIdx) -> Frontier {
+        let mut cost = vec![f64::INFINITY; stars];
+        cost[from] = 0.0;
+        let mut open = BinaryHeap::new();
+        open.push(MinCost {
+            cost: 0.0,
+            item: from,
+        });
+        Frontier {
+            cost,
+            via: vec![None; stars],
+            open,
+        }
+    }
+
+    /// A lower bound on the cost of any star not yet expanded
+    fn min_cost(&self) -> Option<f64> {
+        self.open.peek().map(|o| o.cost)
+    }
+
+    /// The next star to expand, skipping entries which have since been
+    /// beaten
+    fn pop(&mut self) -> Option<StarIdx> {
+        while let Some(MinCost {
+            cost,
+            item: star_idx,
+        }) = self.open.pop()
+        {
+            if cost <= self.cost[star_idx] {
+                return Some(star_idx);
+            }
+        }
+        None
+    }
+
+    /// Record a new way to reach `star_idx`, if it's cheaper
+    fn relax(&mut self, star_idx: StarIdx, cost: f64, via: StarIdx, conn: Connection) -> bool {
+        if cost >= self.cost[star_idx] {
+            return false;
+        }
+        self.cost[star_idx] = cost;
+        self.via[star_idx] = Some((via, conn));
+        self.open.push(MinCost {
+            cost,
+            item: star_idx,
+        });
+        true
+    }
+
+    /// The connections from this side's end to `star_idx`, in the order
+    /// they'd be taken from the start
+    fn path_to(&self, mut star_idx: StarIdx) -> Vec<Connection> {
+        let mut path = Vec::new();
+        while let Some((prev, conn)) = &self.via[star_idx] {
+            path.push(conn.clone());
+            star_idx = *prev;
+        }
+        path.reverse();
+        path
     }
 }
 
+/// Dijkstra forwards from the start and backwards from the end, until
+/// neither side can find anything cheaper than the best place the two
+/// have met so far. There's no heuristic, but on long routes two small
+/// frontiers are much cheaper than one big one.
+#[allow(clippy::too_many_arguments)]
+fn calc_path_bidirectional(
+    universe: &Universe,
+    start_idx: StarIdx,
+    end_idx: StarIdx,
+    jump_distance: Meters,
+    model: &dyn CostModel,
+    constraints: &PathConstraints,
+    budget: &SearchBudget,
+    stats: &mut SearchStats,
+) -> PathResult {
+    let jump_distance = match constraints.no_jumps {
+        true => Meters::new(0.0),
+        false => jump_distance,
+    };
+    let n = universe.stars.len();
+    let mut forward = Frontier::new(n, start_idx);
+    let mut backward = Frontier::new(n, end_idx);
+    stats.pushed += 2;
+    // (total cost, star where the two sides meet)
+    let mut best = match start_idx == end_idx {
+        true => (0.0, start_idx),
+        false => (f64::INFINITY, start_idx),
+    };
+    // (distance to the end, star) of the forward side's closest approach
+    let end = &universe.stars[end_idx];
+    let mut closest = (universe.stars[start_idx].distance(end).get(), start_idx);
+
+    while let (Some(f), Some(b)) = (forward.min_cost(), backward.min_cost()) {
+        if f + b >= best.0 {
+            break;
+        }
+        stats.peak_open = stats
+            .peak_open
+            .max(forward.open.len() + backward.open.len());
+        if !budget.expand() {
+            let t = Instant::now();
+            let path = forward.path_to(closest.1);
+            stats.path_time = t.elapsed();
+            return PathResult::Timeout(path);
+        }
+        // Grow whichever side is smaller
+        if forward.open.len() <= backward.open.len() {
+            let Some(here) = forward.pop() else { continue };
+            stats.expanded += 1;
+            stats.final_g = forward.cost[here];
+            let distance = universe.stars[here].distance(end).get();
+            if distance < closest.0 {
+                closest = (distance, here);
+            }
+            for (conn, c) in successors(universe, here, jump_distance, model, constraints) {
+                let there = conn.target;
+                let new_cost = forward.cost[here] + c;
+                if forward.relax(there, new_cost, here, conn) {
+                    stats.pushed += 1;
+                    let total = new_cost + backward.cost[there];
+                    if total < best.0 {
+                        best = (total, there);
+                    }
+                }
+            }
+        } else {
+            let Some(here) = backward.pop() else { continue };
+            stats.expanded += 1;
+            stats.final_g = backward.cost[here];
+            for (there, conn) in universe.incoming(here, jump_distance) {
+                if !constraints.allows(universe, &conn) {
+                    continue;
+                }
+                let new_cost = backward.cost[here] + model.cost(&conn);
+                if backward.relax(there, new_cost, here, conn) {
+                    stats.pushed += 1;
+                    let total = new_cost + forward.cost[there];
+                    if total < best.0 {
+                        best = (total, there);
+                    }
+                }
+            }
+        }
+    }
+
+    let (total, meet) = best;
+    if total.is_infinite() {
+        return PathResult::NotFound;
+    }
+    stats.final_g = total;
+    let t = Instant::now();
+    let mut path = forward.path_to(meet);
+    let mut star_idx = meet;
+    while let Some((next, conn)) = &backward.via[star_idx] {
+        path.push(conn.clone());
+        star_idx = *next;
+    }
+    stats.path_time = t.elapsed();
+    PathResult::Found(path)
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::raw::tests::{gate, jump, system};
 
     fn call_calc_path(
         universe: &Universe,
@@ -118,12 +643,13 @@ mod tests {
             &universe.stars[end_idx],
             Meters::new(jump_distance),
             optimize,
-            use_smart_gates,
-            None,
+            &PathConstraints::with_smart_gates(use_smart_gates),
+            PathAlgorithm::AStar,
+            &SearchBudget::unlimited(),
         ) {
             PathResult::Found(path) => path,
             PathResult::NotFound => vec![],
-            PathResult::Timeout => panic!("Path search timed out"),
+            PathResult::Timeout(_) => panic!("Path search timed out"),
         }
     }
 
@@ -194,10 +720,545 @@ mod tests {
         assert_eq!(path[0].target, 2);
         assert_eq!(path[0].conn_type, ConnType::SmartGate);
     }
+
+    fn constrained_path(
+        start_idx: StarIdx,
+        end_idx: StarIdx,
+        jump_distance: f64,
+        optimize: PathOptimize,
+        constraints: PathConstraints,
+    ) -> Option<Vec<(StarIdx, ConnType)>> {
+        let universe = Universe::tiny_test();
+        match calc_path(
+            &universe,
+            &universe.stars[start_idx],
+            &universe.stars[end_idx],
+            Meters::new(jump_distance),
+            optimize,
+            &constraints,
+            PathAlgorithm::AStar,
+            &SearchBudget::unlimited(),
+        ) {
+            PathResult::Found(path) => {
+                Some(path.into_iter().map(|c| (c.target, c.conn_type)).collect())
+            }
+            PathResult::NotFound => None,
+            PathResult::Timeout(_) => panic!("Path search timed out"),
+        }
+    }
+
+    #[test]
+    fn test_path_avoid_systems_and_regions() {
+        // Normally 3 -> 0 by gate, then jump to 1
+        let avoid_0 = PathConstraints {
+            avoid_systems: HashSet::from([1000]),
+            ..Default::default()
+        };
+        assert_eq!(
+            constrained_path(3, 1, 25.0, PathOptimize::Fuel, avoid_0.clone()),
+            Some(vec![(1, ConnType::Jump)])
+        );
+        // The destination is avoided too
+        assert_eq!(
+            constrained_path(3, 0, 25.0, PathOptimize::Fuel, avoid_0),
+            None
+        );
+
+        // Normally 0 -> 3 by NPC gate, then 2 by smart gate
+        let avoid_region = PathConstraints {
+            use_smart_gates: true,
+            avoid_regions: HashSet::from([1003]),
+            ..Default::default()
+        };
+        let path = constrained_path(0, 2, 25.0, PathOptimize::Fuel, avoid_region)
+            .expect("Path should be found");
+        assert!(path.iter().all(|(target, _)| *target != 3));
+        assert_eq!(path.last(), Some(&(2, ConnType::Jump)));
+    }
+
+    #[test]
+    fn test_path_avoid_gates() {
+        // Both of the fixture's smart gates have item ID 12345, so both
+        // directions are avoided
+        let avoid_gate = PathConstraints {
+            use_smart_gates: true,
+            avoid_smart_gates: HashSet::from([12345]),
+            ..Default::default()
+        };
+        assert_eq!(
+            constrained_path(3, 2, 25.0, PathOptimize::Hops, avoid_gate.clone()),
+            Some(vec![(2, ConnType::Jump)])
+        );
+        assert_eq!(
+            constrained_path(2, 3, 25.0, PathOptimize::Hops, avoid_gate),
+            Some(vec![(3, ConnType::Jump)])
+        );
+        let avoid_other_gate = PathConstraints {
+            use_smart_gates: true,
+            avoid_smart_gates: HashSet::from([54321]),
+            ..Default::default()
+        };
+        assert_eq!(
+            constrained_path(2, 3, 25.0, PathOptimize::Hops, avoid_other_gate),
+            Some(vec![(3, ConnType::SmartGate)])
+        );
+
+        let no_npc_gates = PathConstraints {
+            no_npc_gates: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            constrained_path(0, 3, 25.0, PathOptimize::Fuel, no_npc_gates),
+            Some(vec![(1, ConnType::Jump), (3, ConnType::Jump)])
+        );
+    }
+
+    #[test]
+    fn test_path_budget() {
+        use crate::calc::budget::CancelToken;
+        use std::time::Duration;
+
+        let universe = Universe::tiny_test();
+        let search = |algorithm, budget: &SearchBudget| {
+            calc_path(
+                &universe,
+                &universe.stars[1],
+                &universe.stars[3],
+                Meters::new(25.0),
+                PathOptimize::Fuel,
+                &PathConstraints::default(),
+                algorithm,
+                budget,
+            )
+        };
+        for algorithm in [PathAlgorithm::AStar, PathAlgorithm::Bidirectional] {
+            // Stopped before we got anywhere
+            let token = CancelToken::new();
+            token.cancel();
+            let cancelled = SearchBudget::unlimited().with_cancel(token);
+            assert_eq!(search(algorithm, &cancelled), PathResult::Timeout(vec![]));
+            let no_time = SearchBudget::unlimited().with_time(Duration::ZERO);
+            assert_eq!(search(algorithm, &no_time), PathResult::Timeout(vec![]));
+
+            let plenty = SearchBudget::unlimited().with_max_expansions(1000);
+            assert!(matches!(search(algorithm, &plenty), PathResult::Found(_)));
+        }
+
+        // Stopping after one star still gets us one step closer - the
+        // cheapest looking next star is 2, which is nearer to 3 than 1 is
+        let PathResult::Timeout(partial) = calc_path(
+            &universe,
+            &universe.stars[1],
+            &universe.stars[3],
+            Meters::new(15.0),
+            PathOptimize::Distance,
+            &PathConstraints::default(),
+            PathAlgorithm::AStar,
+            &SearchBudget::unlimited().with_max_expansions(1),
+        ) else {
+            panic!("Search should have run out of budget");
+        };
+        assert_eq!(partial.len(), 1);
+        assert_eq!(partial[0].target, 2);
+        let end = &universe.stars[3];
+        assert!(universe.stars[partial[0].target].distance(end) < universe.stars[1].distance(end));
+    }
+
+    #[test]
+    fn test_custom_cost_model() {
+        // Distance, but entering `star_idx` costs an extra toll
+        struct Toll {
+            star_idx: StarIdx,
+            toll: f64,
+        }
+        impl CostModel for Toll {
+            fn cost(&self, conn: &Connection) -> f64 {
+                let toll = match conn.target == self.star_idx {
+                    true => self.toll,
+                    false => 0.0,
+                };
+                conn.distance.to_light_years() + toll
+            }
+        }
+
+        let universe = Universe::tiny_test();
+        let path = |toll: f64| {
+            let model = Toll { star_idx: 1, toll };
+            [PathAlgorithm::AStar, PathAlgorithm::Bidirectional].map(|algorithm| {
+                match calc_path_with_model(
+                    &universe,
+                    &universe.stars[0],
+                    &universe.stars[2],
+                    Meters::new(15.0),
+                    &model,
+                    &PathConstraints::with_smart_gates(true),
+                    algorithm,
+                    &SearchBudget::unlimited(),
+                )
+                .0
+                {
+                    PathResult::Found(path) => path.iter().map(|c| c.target).collect::<Vec<_>>(),
+                    _ => panic!("Should have found a path"),
+                }
+            })
+        };
+        // 0 -> 1 -> 2 is 20, and 0 -> 3 -> 2 by gates is 48, so a toll of
+        // 20 isn't enough to go the long way round, but 30 is
+        assert_eq!(path(0.0), [vec![1, 2], vec![1, 2]]);
+        assert_eq!(
+            path(Meters::new(20.0).to_light_years()),
+            [vec![1, 2], vec![1, 2]]
+        );
+        assert_eq!(
+            path(Meters::new(30.0).to_light_years()),
+            [vec![3, 2], vec![3, 2]]
+        );
+    }
+
+    #[test]
+    fn test_path_stats() {
+        let mut universe = Universe::tiny_test();
+        let explain = |universe: &Universe, algorithm, constraints: &PathConstraints| {
+            calc_path_explained(
+                universe,
+                &universe.stars[1],
+                &universe.stars[3],
+                Meters::new(25.0),
+                PathOptimize::Distance,
+                constraints,
+                algorithm,
+                &SearchBudget::unlimited(),
+            )
+        };
+        let plain = PathConstraints::default();
+
+        // Asking for a hierarchy we don't have falls back to A*, and so
+        // does limiting jumps in a bidirectional search
+        let (_, stats) = explain(&universe, PathAlgorithm::Hierarchy, &plain);
+        assert_eq!(stats.algorithm, PathAlgorithm::AStar);
+        let limited = PathConstraints {
+            max_jumps: Some(3),
+            ..Default::default()
+        };
+        let (_, stats) = explain(&universe, PathAlgorithm::Bidirectional, &limited);
+        assert_eq!(stats.algorithm, PathAlgorithm::AStar);
+
+        universe.add_hierarchy(crate::calc::hierarchy::HierarchyKey {
+            jump_distance: Meters::new(25.0),
+            optimize: PathOptimize::Distance,
+            use_smart_gates: false,
+        });
+        for algorithm in [
+            PathAlgorithm::AStar,
+            PathAlgorithm::Bidirectional,
+            PathAlgorithm::Hierarchy,
+        ] {
+            let (result, stats) = explain(&universe, algorithm, &plain);
+            let PathResult::Found(path) = result else {
+                panic!("Expected a path with {:?}", algorithm);
+            };
+            assert_eq!(stats.algorithm, algorithm);
+            assert!(stats.expanded > 0);
+            assert!(stats.pushed >= stats.expanded);
+            assert!(stats.peak_open > 0);
+            let total: f64 = path.iter().map(|c| cost(PathOptimize::Distance, c)).sum();
+            assert!((stats.final_g - total).abs() < 1e-20);
+            assert_eq!(stats.final_h, 0.0);
+            assert!(stats.start_h <= stats.final_g);
+        }
+    }
+
+    #[test]
+    fn test_path_limit_jumps() {
+        let no_jumps = PathConstraints {
+            use_smart_gates: true,
+            no_jumps: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            constrained_path(0, 2, 25.0, PathOptimize::Distance, no_jumps.clone()),
+            Some(vec![(3, ConnType::NpcGate), (2, ConnType::SmartGate)])
+        );
+        assert_eq!(
+            constrained_path(1, 3, 25.0, PathOptimize::Distance, no_jumps),
+            None
+        );
+
+        // 2 -> 0 takes two short jumps, or one long one
+        let max_jumps = |max_jumps| PathConstraints {
+            max_jumps: Some(max_jumps),
+            ..Default::default()
+        };
+        assert_eq!(
+            constrained_path(2, 0, 15.0, PathOptimize::Fuel, max_jumps(2)),
+            Some(vec![(1, ConnType::Jump), (0, ConnType::Jump)])
+        );
+        assert_eq!(
+            constrained_path(2, 0, 15.0, PathOptimize::Fuel, max_jumps(1)),
+            None
+        );
+        assert_eq!(
+            constrained_path(2, 0, 25.0, PathOptimize::Fuel, max_jumps(1)),
+            Some(vec![(0, ConnType::Jump)])
+        );
+    }
+
+    /// A small random universe, with stars scattered across a 100 LY cube
+    /// and random NPC and smart gates between them
+    pub(crate) fn random_universe(seed: u64, jump_edges: JumpEdges) -> Universe {
+        let mut state = seed;
+        let mut next = move |n: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+        let stars = 40;
+        let solar_systems = (0..stars)
+            .map(|id| {
+                let center = [0, 1, 2].map(|_| Meters::from_light_years(next(100) as f64).get());
+                system(id as SolarSystemId, Some(&format!("S{}", id)), center)
+            })
+            .collect();
+        let jumps = (0..15)
+            .map(|_| jump(next(stars) as SolarSystemId, next(stars) as SolarSystemId))
+            .collect();
+        let smart_gates = (0..8)
+            .map(|item_id| {
+                gate(
+                    item_id,
+                    next(stars) as SolarSystemId,
+                    next(stars) as SolarSystemId,
+                )
+            })
+            .collect();
+        Universe::build_from_raw(
+            crate::raw::RawStarMap {
+                solar_systems,
+                jumps,
+            },
+            smart_gates,
+            jump_edges,
+        )
+        .expect("Failed to build random universe")
+    }
+
+    /// The cheapest cost from `start` to `end`, by plain Dijkstra over stars
+    pub(crate) fn dijkstra(
+        universe: &Universe,
+        start: StarIdx,
+        end: StarIdx,
+        jump_distance: Meters,
+        optimize: PathOptimize,
+        constraints: &PathConstraints,
+    ) -> Option<f64> {
+        let mut best = vec![f64::INFINITY; universe.stars.len()];
+        let mut open = std::collections::BinaryHeap::new();
+        best[start] = 0.0;
+        open.push(MinCost {
+            cost: 0.0,
+            item: start,
+        });
+        while let Some(MinCost {
+            cost: cost_so_far,
+            item: idx,
+        }) = open.pop()
+        {
+            if idx == end {
+                return Some(cost_so_far);
+            }
+            if cost_so_far > best[idx] {
+                continue;
+            }
+            for (conn, c) in successors(universe, idx, jump_distance, &optimize, constraints) {
+                let new_cost = cost_so_far + c;
+                if new_cost < best[conn.target] {
+                    best[conn.target] = new_cost;
+                    open.push(MinCost {
+                        cost: new_cost,
+                        item: conn.target,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    // Every search finds paths exactly as cheap as Dijkstra, which never
+    // guesses, and only uses connections which really exist
+    #[test]
+    fn test_path_optimal_on_random_universes() {
+        for seed in 0..20 {
+            let jump_edges = match seed % 2 {
+                0 => JumpEdges::OnDemand,
+                _ => JumpEdges::Precomputed(Meters::from_light_years(40.0)),
+            };
+            let mut universe = random_universe(seed, jump_edges);
+            // Hierarchies for some of the settings below, so that others
+            // have to fall back to A*
+            for optimize in [PathOptimize::Fuel, PathOptimize::Hops] {
+                for use_smart_gates in [false, true] {
+                    for jump_distance in [15.0, 25.0] {
+                        universe.add_hierarchy(crate::calc::hierarchy::HierarchyKey {
+                            jump_distance: Meters::from_light_years(jump_distance),
+                            optimize,
+                            use_smart_gates,
+                        });
+                    }
+                }
+            }
+            let all_constraints = [
+                PathConstraints::with_smart_gates(false),
+                PathConstraints::with_smart_gates(true),
+                PathConstraints {
+                    use_smart_gates: true,
+                    avoid_systems: (10..20).collect(),
+                    no_npc_gates: true,
+                    ..Default::default()
+                },
+            ];
+            for (start, end) in [(0, 1), (2, 3), (4, 5), (6, 7), (8, 9)] {
+                for optimize in [
+                    PathOptimize::Fuel,
+                    PathOptimize::Distance,
+                    PathOptimize::Hops,
+                ] {
+                    for constraints in &all_constraints {
+                        for jump_distance in [15.0, 25.0, 40.0] {
+                            let jump_distance = Meters::from_light_years(jump_distance);
+                            let expected = dijkstra(
+                                &universe,
+                                start,
+                                end,
+                                jump_distance,
+                                optimize,
+                                constraints,
+                            );
+                            for algorithm in [
+                                PathAlgorithm::AStar,
+                                PathAlgorithm::Bidirectional,
+                                PathAlgorithm::Hierarchy,
+                            ] {
+                                let found = match calc_path(
+                                    &universe,
+                                    &universe.stars[start],
+                                    &universe.stars[end],
+                                    jump_distance,
+                                    optimize,
+                                    constraints,
+                                    algorithm,
+                                    &SearchBudget::unlimited(),
+                                ) {
+                                    PathResult::Found(path) => {
+                                        let mut here = start;
+                                        for conn in &path {
+                                            assert!(universe
+                                                .connections(here, jump_distance)
+                                                .iter()
+                                                .any(
+                                                    |c| c.id == conn.id && c.target == conn.target
+                                                ));
+                                            here = conn.target;
+                                        }
+                                        assert_eq!(here, end);
+                                        Some(path.iter().map(|c| cost(optimize, c)).sum::<f64>())
+                                    }
+                                    PathResult::NotFound => None,
+                                    PathResult::Timeout(_) => panic!("Path search timed out"),
+                                };
+                                match (found, expected) {
+                                    (Some(found), Some(expected)) => assert!(
+                                        (found - expected).abs() <= expected * 1e-9,
+                                        "seed {} {}->{} {:?} {:?} {:?} jump={:?}: \
+                                         found {} but best is {}",
+                                        seed,
+                                        start,
+                                        end,
+                                        optimize,
+                                        algorithm,
+                                        constraints,
+                                        jump_distance,
+                                        found,
+                                        expected
+                                    ),
+                                    (found, expected) => assert_eq!(found, expected),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_path_bidirectional_one_way_gates() {
+        let universe = Universe::tiny_test();
+        let path = |start: StarIdx, end: StarIdx, constraints: &PathConstraints| match calc_path(
+            &universe,
+            &universe.stars[start],
+            &universe.stars[end],
+            Meters::new(25.0),
+            PathOptimize::Hops,
+            constraints,
+            PathAlgorithm::Bidirectional,
+            &SearchBudget::unlimited(),
+        ) {
+            PathResult::Found(path) => path
+                .iter()
+                .map(|c| (c.target, c.conn_type.clone()))
+                .collect(),
+            _ => vec![],
+        };
+        let avoid_gate = PathConstraints {
+            use_smart_gates: true,
+            avoid_smart_gates: HashSet::from([12345]),
+            ..Default::default()
+        };
+        assert_eq!(path(3, 2, &avoid_gate), vec![(2, ConnType::Jump)]);
+        assert_eq!(path(2, 3, &avoid_gate), vec![(3, ConnType::Jump)]);
+        assert_eq!(path(1, 1, &avoid_gate), vec![]);
+    }
+
+    // Looking up jumps on demand finds paths as good as precomputed jumps
+    #[test]
+    fn test_path_on_demand_matches_precomputed() {
+        let precomputed = Universe::tiny_test();
+        let on_demand = Universe::tiny_test_on_demand();
+        for start in 0..4 {
+            for end in 0..4 {
+                for optimize in [
+                    PathOptimize::Fuel,
+                    PathOptimize::Distance,
+                    PathOptimize::Hops,
+                ] {
+                    for use_smart_gates in [false, true] {
+                        for jump_distance in [0.1, 15.0, 25.0] {
+                            let total = |universe: &Universe| -> Option<f64> {
+                                let path = call_calc_path(
+                                    universe,
+                                    start,
+                                    end,
+                                    jump_distance,
+                                    optimize,
+                                    use_smart_gates,
+                                );
+                                (start == end || !path.is_empty())
+                                    .then(|| path.iter().map(|c| cost(optimize, c)).sum())
+                            };
+                            assert_eq!(total(&precomputed), total(&on_demand));
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
 // https://docs.rs/crate/pathfinding/latest/source/src/directed/astar.rs
-// modified to return both nodes and edges
+// modified to remember the edge used to reach each node, return the
+// edges along the path, and give up with a partial path when the budget
+// runs out
 mod pathfinding {
     use indexmap::map::Entry::{Occupied, Vacant};
     use num_traits::Zero;
@@ -206,54 +1267,68 @@ mod pathfinding {
     use std::hash::Hash;
     use std::time::Instant;
 
+    use super::{SearchBudget, SearchStats};
+
     use indexmap::IndexMap;
     use rustc_hash::FxHasher;
     use std::hash::BuildHasherDefault;
 
     type FxIndexMap<K, V> = IndexMap<K, V, BuildHasherDefault<FxHasher>>;
 
-    pub enum PathFindResult<N, C> {
-        Found((Vec<N>, C)),
+    pub enum PathFindResult<E, C> {
+        Found((Vec<E>, C)),
         NotFound,
-        Timeout,
+        /// The path to the expanded node with the lowest `remaining`
+        Timeout(Vec<E>),
     }
 
-    pub fn astar<N, C, FN, IN, FH, FS>(
+    pub fn astar<N, E, C, FN, IN, FH, FS, FR>(
         start: &N,
         mut successors: FN,
         mut heuristic: FH,
         mut success: FS,
-        timeout: Option<u64>,
-    ) -> PathFindResult<N, C>
+        mut remaining: FR,
+        budget: &SearchBudget,
+        stats: &mut SearchStats,
+    ) -> PathFindResult<E, C>
     where
         N: Eq + Hash + Clone,
-        C: Zero + PartialOrd + Copy,
+        E: Clone,
+        C: Zero + PartialOrd + Copy + Into<f64>,
         FN: FnMut(&N) -> IN,
-        IN: IntoIterator<Item = (N, C)>,
+        IN: IntoIterator<Item = (N, E, C)>,
         FH: FnMut(&N) -> C,
         FS: FnMut(&N) -> bool,
+        FR: FnMut(&N) -> f64,
     {
-        let start_time = Instant::now();
-        let mut to_see = BinaryHeap::new();
+        // (remaining, index) of the node which got closest to the goal
+        let mut closest = (remaining(start), 0);
+        let mut to_see: BinaryHeap<SmallestCostHolder<C>> = BinaryHeap::new();
         to_see.push(SmallestCostHolder {
             estimated_cost: Zero::zero(),
             cost: Zero::zero(),
             index: 0,
         });
-        let mut parents: FxIndexMap<N, (usize, C)> = FxIndexMap::default();
-        parents.insert(start.clone(), (usize::MAX, Zero::zero()));
-        while let Some(SmallestCostHolder { cost, index, .. }) = to_see.pop() {
-            if let Some(timeout) = timeout {
-                if start_time.elapsed().as_secs() >= timeout {
-                    return PathFindResult::Timeout;
-                }
-            }
+        stats.pushed += 1;
+        // node -> (parent index, edge from parent, cost)
+        let mut parents: FxIndexMap<N, (usize, Option<E>, C)> = FxIndexMap::default();
+        parents.insert(start.clone(), (usize::MAX, None, Zero::zero()));
+        while let Some(SmallestCostHolder {
+            estimated_cost,
+            cost,
+            index,
+        }) = to_see.pop()
+        {
             let successors = {
-                let (node, &(_, c)) = parents
+                let (node, &(_, _, c)) = parents
                     .get_index(index)
                     .expect("Can't find index which we inserted earlier");
                 if success(node) {
-                    let path = reverse_path(&parents, |&(p, _)| p, index);
+                    stats.final_g = cost.into();
+                    stats.final_h = estimated_cost.into() - cost.into();
+                    let t = Instant::now();
+                    let path = reverse_path(&parents, index);
+                    stats.path_time = t.elapsed();
                     return PathFindResult::Found((path, cost));
                 }
                 // We may have inserted a node several time into the binary heap if we found
@@ -262,9 +1337,22 @@ mod pathfinding {
                 if cost > c {
                     continue;
                 }
+                stats.final_g = cost.into();
+                stats.final_h = estimated_cost.into() - cost.into();
+                let r = remaining(node);
+                if r < closest.0 {
+                    closest = (r, index);
+                }
+                if !budget.expand() {
+                    let t = Instant::now();
+                    let path = reverse_path(&parents, closest.1);
+                    stats.path_time = t.elapsed();
+                    return PathFindResult::Timeout(path);
+                }
+                stats.expanded += 1;
                 successors(node)
             };
-            for (successor, move_cost) in successors {
+            for (successor, edge, move_cost) in successors {
                 let new_cost = cost + move_cost;
                 let h; // heuristic(&successor)
                 let n; // index for successor
@@ -272,13 +1360,13 @@ mod pathfinding {
                     Vacant(e) => {
                         h = heuristic(e.key());
                         n = e.index();
-                        e.insert((index, new_cost));
+                        e.insert((index, Some(edge), new_cost));
                     }
                     Occupied(mut e) => {
-                        if e.get().1 > new_cost {
+                        if e.get().2 > new_cost {
                             h = heuristic(e.key());
                             n = e.index();
-                            e.insert((index, new_cost));
+                            e.insert((index, Some(edge), new_cost));
                         } else {
                             continue;
                         }
@@ -290,28 +1378,27 @@ mod pathfinding {
                     cost: new_cost,
                     index: n,
                 });
+                stats.pushed += 1;
+                stats.peak_open = stats.peak_open.max(to_see.len());
             }
         }
         PathFindResult::NotFound
     }
 
-    #[allow(clippy::needless_collect)]
-    fn reverse_path<N, V, F>(parents: &FxIndexMap<N, V>, mut parent: F, start: usize) -> Vec<N>
+    /// The edges taken to reach the node at `end`, starting from the start
+    fn reverse_path<N, E, C>(parents: &FxIndexMap<N, (usize, Option<E>, C)>, end: usize) -> Vec<E>
     where
         N: Eq + Hash + Clone,
-        F: FnMut(&V) -> usize,
+        E: Clone,
     {
-        let mut i = start;
-        let path = std::iter::from_fn(|| {
-            parents.get_index(i).map(|(node, value)| {
-                i = parent(value);
-                node
-            })
-        })
-        .collect::<Vec<&N>>();
-        // Collecting the going through the vector is needed to revert the path because the
-        // unfold iterator is not double-ended due to its iterative nature.
-        path.into_iter().rev().cloned().collect()
+        let mut path = Vec::new();
+        let mut i = end;
+        while let Some((_, (parent, Some(edge), _))) = parents.get_index(i) {
+            path.push(edge.clone());
+            i = *parent;
+        }
+        path.reverse();
+        path
     }
 
     struct SmallestCostHolder<K> {
@@ -0,0 +1,298 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calc::budget::SearchBudget;
+use crate::calc::path::{CostModel, PathConstraints};
+use crate::calc::reach::{calc_reach, Reach, ReachLimits, ReachResult};
+use crate::calc::ship::ShipProfile;
+use crate::data::*;
+use crate::error::{Error, Result};
+use crate::units::Meters;
+
+/// How far one member of a fleet can get
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemberRange {
+    /// Any number of jumps, each up to this far
+    Jump(Meters),
+    /// Jumps burn this ship's fuel, so the total jumped is limited too
+    Ship(ShipProfile),
+}
+
+impl MemberRange {
+    /// The longest single jump this member could make
+    pub fn max_jump(&self) -> Meters {
+        match self {
+            MemberRange::Jump(jump_distance) => *jump_distance,
+            MemberRange::Ship(ship) => ship.range(ship.current_fuel),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FleetMember<'a> {
+    pub start: &'a Star,
+    pub range: MemberRange,
+}
+
+/// What makes one rally point better than another
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RallyObjective {
+    /// Whoever has furthest to go has as little as possible, so the
+    /// fleet gathers soonest
+    #[default]
+    Max,
+    /// The fleet as a whole spends as little as possible
+    Sum,
+}
+
+/// A place for the fleet to meet, and how each member gets there
+#[derive(Debug, Clone, PartialEq)]
+pub struct RallyPoint {
+    pub star_idx: StarIdx,
+    /// The worst or total of `costs`, depending on the objective
+    pub score: f64,
+    /// Each member's cost to get here, in the same order as the members
+    pub costs: Vec<f64>,
+    /// Each member's route here
+    pub paths: Vec<Vec<Connection>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RallyResult {
+    /// The best rally points, best first. There may be fewer than asked
+    /// for if there aren't that many places everyone can reach.
+    Found(Vec<RallyPoint>),
+    NotFound,
+    Timeout,
+}
+
+/// Find the `count` best places for a fleet to meet, by flooding out
+/// from every member's start with `calc_reach` and scoring each star
+/// that all of them can get to.
+pub fn calc_rally(
+    universe: &Universe,
+    members: &[FleetMember],
+    model: &dyn CostModel,
+    constraints: &PathConstraints,
+    objective: RallyObjective,
+    count: usize,
+    budget: &SearchBudget,
+) -> Result<RallyResult> {
+    if members.is_empty() {
+        return Err(Error::InvalidParameter {
+            name: "members",
+            message: "a fleet needs at least one member".to_string(),
+        });
+    }
+    if count == 0 {
+        return Err(Error::InvalidParameter {
+            name: "count",
+            message: "must be at least 1".to_string(),
+        });
+    }
+    for member in members {
+        if let MemberRange::Ship(ship) = &member.range {
+            ship.validate()?;
+        }
+    }
+
+    let mut reaches: Vec<Reach> = Vec::new();
+    for member in members {
+        let limits = match &member.range {
+            MemberRange::Jump(_) => ReachLimits::default(),
+            MemberRange::Ship(ship) => ReachLimits {
+                max_jump_ly: Some(ship.range(ship.current_fuel).to_light_years()),
+                ..Default::default()
+            },
+        };
+        match calc_reach(
+            universe,
+            member.start,
+            member.range.max_jump(),
+            model,
+            constraints,
+            &limits,
+            budget,
+        ) {
+            ReachResult::Found(reach) => reaches.push(reach),
+            ReachResult::Timeout(_) => return Ok(RallyResult::Timeout),
+        }
+    }
+
+    let score = |costs: &[f64], objective| match objective {
+        RallyObjective::Max => costs.iter().cloned().fold(0.0, f64::max),
+        RallyObjective::Sum => costs.iter().sum(),
+    };
+    let other = match objective {
+        RallyObjective::Max => RallyObjective::Sum,
+        RallyObjective::Sum => RallyObjective::Max,
+    };
+    // (score, the other score to break ties, star, costs)
+    let mut candidates: Vec<(f64, f64, StarIdx, Vec<f64>)> = reaches[0]
+        .stars()
+        .into_iter()
+        .filter_map(|r| {
+            let costs = reaches
+                .iter()
+                .map(|reach| reach.get(r.star_idx).map(|o| o.cost))
+                .collect::<Option<Vec<f64>>>()?;
+            Some((
+                score(&costs, objective),
+                score(&costs, other),
+                r.star_idx,
+                costs,
+            ))
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Ok(RallyResult::NotFound);
+    }
+    candidates.sort_by(|a, b| {
+        a.0.total_cmp(&b.0)
+            .then(a.1.total_cmp(&b.1))
+            .then(a.2.cmp(&b.2))
+    });
+    candidates.truncate(count);
+
+    Ok(RallyResult::Found(
+        candidates
+            .into_iter()
+            .map(|(score, _, star_idx, costs)| RallyPoint {
+                star_idx,
+                score,
+                costs,
+                paths: reaches
+                    .iter()
+                    .map(|reach| reach.path_to(star_idx).unwrap_or_default())
+                    .collect(),
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::path::PathOptimize;
+
+    fn rally(members: &[(StarIdx, MemberRange)], objective: RallyObjective) -> Vec<(StarIdx, f64)> {
+        let universe = Universe::tiny_test_on_demand();
+        let members: Vec<FleetMember> = members
+            .iter()
+            .map(|&(start, range)| FleetMember {
+                start: &universe.stars[start],
+                range,
+            })
+            .collect();
+        match calc_rally(
+            &universe,
+            &members,
+            &PathOptimize::Distance,
+            &PathConstraints::default(),
+            objective,
+            10,
+            &SearchBudget::unlimited(),
+        )
+        .expect("Members should be valid")
+        {
+            RallyResult::Found(points) => {
+                // Every member's route ends at the rally point
+                for point in &points {
+                    for (path, member) in point.paths.iter().zip(&members) {
+                        let start = universe.star_id_to_idx[&member.start.id];
+                        assert_eq!(path.last().map_or(start, |c| c.target), point.star_idx);
+                    }
+                }
+                points
+                    .into_iter()
+                    .map(|p| (p.star_idx, Meters::from_light_years(p.score).get().round()))
+                    .collect()
+            }
+            RallyResult::NotFound => vec![],
+            RallyResult::Timeout => panic!("Rally timed out"),
+        }
+    }
+
+    #[test]
+    fn test_rally_objectives() {
+        let jump = MemberRange::Jump(Meters::new(15.0));
+        // From 0 and 2, 1 is 10 for both, while 0 and 2 are 20 for
+        // whoever isn't already there. 3 is 28 by gate from 0, but 48
+        // from 2 since that has to go through 0.
+        assert_eq!(
+            rally(&[(0, jump), (2, jump)], RallyObjective::Max),
+            vec![(1, 10.0), (0, 20.0), (2, 20.0), (3, 48.0)]
+        );
+        // 0 and 3 are both 28 for one member and nothing for the other
+        assert_eq!(
+            rally(&[(0, jump), (3, jump)], RallyObjective::Max),
+            vec![(0, 28.0), (3, 28.0), (1, 38.0), (2, 48.0)]
+        );
+        // With another member at 1, the total is 10 + 0 + 10 there
+        assert_eq!(
+            rally(&[(0, jump), (1, jump), (2, jump)], RallyObjective::Sum),
+            vec![(1, 20.0), (0, 30.0), (2, 30.0), (3, 115.0)]
+        );
+    }
+
+    #[test]
+    fn test_rally_ship_range() {
+        // A ship which can jump 15 in total can get from 2 to 1, but not
+        // on to 0
+        let ship = ShipProfile {
+            hull_mass: 1e7,
+            fittings_mass: 0.0,
+            cargo_mass: 0.0,
+            tank_size: 100.0,
+            current_fuel: Meters::new(15.0).to_light_years(),
+            fuel_efficiency: 1.0,
+        };
+        assert_eq!(ship.range(ship.current_fuel).get().round(), 15.0);
+        let jump = MemberRange::Jump(Meters::new(15.0));
+        assert_eq!(
+            rally(
+                &[(0, jump), (2, MemberRange::Ship(ship))],
+                RallyObjective::Max
+            ),
+            vec![(1, 10.0), (2, 20.0)]
+        );
+    }
+
+    #[test]
+    fn test_rally_needs_members() {
+        let universe = Universe::tiny_test();
+        assert!(matches!(
+            calc_rally(
+                &universe,
+                &[],
+                &PathOptimize::Distance,
+                &PathConstraints::default(),
+                RallyObjective::Max,
+                10,
+                &SearchBudget::unlimited(),
+            ),
+            Err(Error::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rally_needs_count() {
+        let universe = Universe::tiny_test();
+        let members = [FleetMember {
+            start: &universe.stars[0],
+            range: MemberRange::Jump(Meters::new(15.0)),
+        }];
+        assert!(matches!(
+            calc_rally(
+                &universe,
+                &members,
+                &PathOptimize::Distance,
+                &PathConstraints::default(),
+                RallyObjective::Max,
+                0,
+                &SearchBudget::unlimited(),
+            ),
+            Err(Error::InvalidParameter { name: "count", .. })
+        ));
+    }
+}
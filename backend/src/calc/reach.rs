@@ -0,0 +1,457 @@
+use std::collections::BinaryHeap;
+
+use crate::calc::budget::SearchBudget;
+use crate::calc::heap::MinCost;
+use crate::calc::path::{successors, CostModel, PathConstraints};
+use crate::data::*;
+use crate::error::{Error, Result};
+use crate::units::Meters;
+
+/// How far to flood out from the start. Stars which can only be reached
+/// by going past either limit are left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReachLimits {
+    /// The most a route may cost, in the same units as `cost`
+    pub max_cost: Option<f64>,
+    /// The most connections a route may take, gates included
+    pub max_hops: Option<usize>,
+    /// The most light years a route may jump in total, eg as far as a
+    /// ship's fuel will take it
+    pub max_jump_ly: Option<f64>,
+}
+
+impl ReachLimits {
+    pub fn validate(&self) -> Result<()> {
+        for (name, limit) in [
+            ("max_cost", self.max_cost),
+            ("max_jump_ly", self.max_jump_ly),
+        ] {
+            if let Some(limit) = limit {
+                if !limit.is_finite() || limit < 0.0 {
+                    return Err(Error::InvalidParameter {
+                        name,
+                        message: format!("{} is not a valid limit", limit),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The cheapest way to get to one star
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reachable {
+    pub star_idx: StarIdx,
+    pub cost: f64,
+    pub hops: usize,
+    /// The star before this one on the way, and the connection from it.
+    /// `None` for the start. With hops or jumps limited, the way to that
+    /// star can differ from its own entry, so use `Reach::path_to` for
+    /// whole routes rather than following these back.
+    pub via: Option<(StarIdx, Connection)>,
+}
+
+#[derive(Debug, Clone)]
+struct Label {
+    star_idx: StarIdx,
+    cost: f64,
+    hops: usize,
+    jumps: usize,
+    /// Light years jumped so far
+    jumped: f64,
+    parent: Option<(usize, Connection)>,
+}
+
+/// Every star reached by a flood, with the cheapest route to each
+#[derive(Debug, Clone)]
+pub struct Reach {
+    labels: Vec<Label>,
+    /// The cheapest label at each star, if it was reached
+    best: Vec<Option<usize>>,
+    /// Those labels again, in the order they were reached, which is
+    /// cheapest first
+    order: Vec<usize>,
+}
+
+impl Reach {
+    fn reachable(&self, label: usize) -> Reachable {
+        let l = &self.labels[label];
+        Reachable {
+            star_idx: l.star_idx,
+            cost: l.cost,
+            hops: l.hops,
+            via: l
+                .parent
+                .as_ref()
+                .map(|(parent, conn)| (self.labels[*parent].star_idx, conn.clone())),
+        }
+    }
+
+    /// All the stars reached, cheapest first, starting with the start
+    pub fn stars(&self) -> Vec<Reachable> {
+        self.order.iter().map(|&l| self.reachable(l)).collect()
+    }
+
+    /// How to get to `star_idx`, if it was reached
+    pub fn get(&self, star_idx: StarIdx) -> Option<Reachable> {
+        self.best[star_idx].map(|l| self.reachable(l))
+    }
+
+    /// The connections to take from the start to get to `star_idx`.
+    ///
+    /// With hop or jump limits the cheapest route to a star can go
+    /// through a more expensive route to an earlier star than that
+    /// star's own entry, so always use this rather than following `via`.
+    pub fn path_to(&self, star_idx: StarIdx) -> Option<Vec<Connection>> {
+        let mut label = self.best[star_idx]?;
+        let mut path = Vec::new();
+        while let Some((parent, conn)) = &self.labels[label].parent {
+            path.push(conn.clone());
+            label = *parent;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// How many stars were reached, including the start
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum ReachResult {
+    Found(Reach),
+    /// Time ran out - the stars reached so far are all correct, but
+    /// there may be more
+    Timeout(Reach),
+}
+
+/// Find everywhere reachable from `start`, and the cheapest way to get
+/// to each, with Dijkstra's algorithm.
+///
+/// If hops or jumps are limited, a star reached cheaply but with too
+/// many hops used up may still be worth reaching again more expensively
+/// with fewer, so then each star can be expanded once for each
+/// trade-off between cost and hops left.
+pub fn calc_reach(
+    universe: &Universe,
+    start: &Star,
+    jump_distance: Meters,
+    model: &dyn CostModel,
+    constraints: &PathConstraints,
+    limits: &ReachLimits,
+    budget: &SearchBudget,
+) -> ReachResult {
+    calc_reach_until(
+        universe,
+        start,
+        jump_distance,
+        model,
+        constraints,
+        limits,
+        budget,
+        &mut |_| false,
+    )
+}
+
+/// Like `calc_reach`, but stop early once `until` returns true. It's
+/// called with each star as it's reached, cheapest first.
+#[allow(clippy::too_many_arguments)]
+pub fn calc_reach_until(
+    universe: &Universe,
+    start: &Star,
+    jump_distance: Meters,
+    model: &dyn CostModel,
+    constraints: &PathConstraints,
+    limits: &ReachLimits,
+    budget: &SearchBudget,
+    until: &mut dyn FnMut(StarIdx) -> bool,
+) -> ReachResult {
+    let start_idx = universe.star_id_to_idx[&start.id];
+    let stars = universe.stars.len();
+    let mut reach = Reach {
+        labels: vec![Label {
+            star_idx: start_idx,
+            cost: 0.0,
+            hops: 0,
+            jumps: 0,
+            jumped: 0.0,
+            parent: None,
+        }],
+        best: vec![None; stars],
+        order: Vec::new(),
+    };
+    // Hops, jumps and distance jumped only matter if they're limited
+    let key = |label: &Label| {
+        (
+            limits.max_hops.map_or(0, |_| label.hops),
+            constraints.max_jumps.map_or(0, |_| label.jumps),
+            limits.max_jump_ly.map_or(0.0, |_| label.jumped),
+        )
+    };
+    let unlimited = limits.max_hops.is_none()
+        && constraints.max_jumps.is_none()
+        && limits.max_jump_ly.is_none();
+    // With nothing limited, the cheapest cost pushed for each star, so
+    // that more expensive ways there needn't be pushed at all
+    let mut cheapest = vec![f64::INFINITY; stars];
+    cheapest[start_idx] = 0.0;
+    // The (hops, jumps, jumped) of each label expanded at each star
+    let mut expanded: Vec<Vec<(usize, usize, f64)>> = vec![Vec::new(); stars];
+    let mut open = BinaryHeap::new();
+    open.push(MinCost { cost: 0.0, item: 0 });
+
+    while let Some(MinCost { item: label, .. }) = open.pop() {
+        let (star_idx, cost_so_far, hops, jumps, jumped) = {
+            let l = &reach.labels[label];
+            (l.star_idx, l.cost, l.hops, l.jumps, l.jumped)
+        };
+        // Skip labels which are no better than one already expanded here
+        let (h, j, ly) = key(&reach.labels[label]);
+        if expanded[star_idx]
+            .iter()
+            .any(|&(eh, ej, ely)| eh <= h && ej <= j && ely <= ly)
+        {
+            continue;
+        }
+        if !budget.expand() {
+            return ReachResult::Timeout(reach);
+        }
+        expanded[star_idx].push((h, j, ly));
+        if reach.best[star_idx].is_none() {
+            reach.best[star_idx] = Some(label);
+            reach.order.push(label);
+            if until(star_idx) {
+                break;
+            }
+        }
+
+        if limits.max_hops.is_some_and(|max| hops >= max) {
+            continue;
+        }
+        let jump_distance = match (constraints.max_jumps, limits.max_jump_ly) {
+            (Some(max_jumps), _) if jumps >= max_jumps => Meters::new(0.0),
+            (_, Some(max_jump_ly)) => {
+                jump_distance.min(Meters::from_light_years((max_jump_ly - jumped).max(0.0)))
+            }
+            _ => jump_distance,
+        };
+        for (conn, cost) in successors(universe, star_idx, jump_distance, model, constraints) {
+            let new_cost = cost_so_far + cost;
+            if limits.max_cost.is_some_and(|max| new_cost > max) {
+                continue;
+            }
+            if unlimited {
+                if new_cost >= cheapest[conn.target] {
+                    continue;
+                }
+                cheapest[conn.target] = new_cost;
+            }
+            let (new_jumps, new_jumped) = match conn.conn_type {
+                ConnType::Jump => (jumps + 1, jumped + conn.distance.to_light_years()),
+                ConnType::NpcGate | ConnType::SmartGate => (jumps, jumped),
+            };
+            if limits.max_jump_ly.is_some_and(|max| new_jumped > max) {
+                continue;
+            }
+            let new_label = reach.labels.len();
+            reach.labels.push(Label {
+                star_idx: conn.target,
+                cost: new_cost,
+                hops: hops + 1,
+                jumps: new_jumps,
+                jumped: new_jumped,
+                parent: Some((label, conn)),
+            });
+            open.push(MinCost {
+                cost: new_cost,
+                item: new_label,
+            });
+        }
+    }
+    ReachResult::Found(reach)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::path::PathOptimize;
+
+    fn reach(
+        start: StarIdx,
+        jump_distance: f64,
+        constraints: PathConstraints,
+        limits: ReachLimits,
+    ) -> Vec<(StarIdx, f64, usize, Option<StarIdx>)> {
+        let universe = Universe::tiny_test();
+        match calc_reach(
+            &universe,
+            &universe.stars[start],
+            Meters::new(jump_distance),
+            &PathOptimize::Distance,
+            &constraints,
+            &limits,
+            &SearchBudget::unlimited(),
+        ) {
+            ReachResult::Found(reach) => reach
+                .stars()
+                .into_iter()
+                .map(|r| {
+                    (
+                        r.star_idx,
+                        Meters::from_light_years(r.cost).get().round(),
+                        r.hops,
+                        r.via.map(|(prev, _)| prev),
+                    )
+                })
+                .collect(),
+            ReachResult::Timeout(_) => panic!("Reach timed out"),
+        }
+    }
+
+    #[test]
+    fn test_reach_everything() {
+        // 0 -> 1 (10) -> 2 (20), and 0 -> 3 by gate (28)
+        assert_eq!(
+            reach(0, 15.0, PathConstraints::default(), ReachLimits::default()),
+            vec![
+                (0, 0.0, 0, None),
+                (1, 10.0, 1, Some(0)),
+                (2, 20.0, 2, Some(1)),
+                (3, 28.0, 1, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reach_limits() {
+        let max_cost = ReachLimits {
+            max_cost: Some(Meters::new(25.0).to_light_years()),
+            ..Default::default()
+        };
+        assert_eq!(
+            reach(0, 15.0, PathConstraints::default(), max_cost),
+            vec![
+                (0, 0.0, 0, None),
+                (1, 10.0, 1, Some(0)),
+                (2, 20.0, 2, Some(1))
+            ]
+        );
+
+        let max_hops = ReachLimits {
+            max_hops: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            reach(0, 15.0, PathConstraints::default(), max_hops),
+            vec![
+                (0, 0.0, 0, None),
+                (1, 10.0, 1, Some(0)),
+                (3, 28.0, 1, Some(0))
+            ]
+        );
+
+        // 0 -> 1 -> 2 jumps 20 in all, and there's no other way to 2
+        let max_jump_ly = |ly: f64| ReachLimits {
+            max_jump_ly: Some(Meters::new(ly).to_light_years()),
+            ..Default::default()
+        };
+        assert_eq!(
+            reach(0, 15.0, PathConstraints::default(), max_jump_ly(15.0)),
+            vec![
+                (0, 0.0, 0, None),
+                (1, 10.0, 1, Some(0)),
+                (3, 28.0, 1, Some(0))
+            ]
+        );
+        assert_eq!(
+            reach(0, 15.0, PathConstraints::default(), max_jump_ly(20.0)).len(),
+            4
+        );
+
+        // Only one jump allowed, so 0 has to be reached the long way
+        // round by gate, rather than by jumping through 1
+        let max_jumps = PathConstraints {
+            use_smart_gates: true,
+            max_jumps: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            reach(2, 15.0, max_jumps, ReachLimits::default()),
+            vec![
+                (2, 0.0, 0, None),
+                (1, 10.0, 1, Some(2)),
+                (3, 20.0, 1, Some(2)),
+                (0, 48.0, 2, Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reach_limits_validate() {
+        assert!(ReachLimits::default().validate().is_ok());
+        assert!(ReachLimits {
+            max_cost: Some(0.0),
+            max_jump_ly: Some(100.0),
+            ..Default::default()
+        }
+        .validate()
+        .is_ok());
+        for limits in [
+            ReachLimits {
+                max_cost: Some(-1.0),
+                ..Default::default()
+            },
+            ReachLimits {
+                max_jump_ly: Some(f64::NAN),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                limits.validate(),
+                Err(Error::InvalidParameter { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reach_path_to() {
+        let universe = Universe::tiny_test();
+        let ReachResult::Found(reach) = calc_reach(
+            &universe,
+            &universe.stars[1],
+            Meters::new(15.0),
+            &PathOptimize::Distance,
+            &PathConstraints::with_smart_gates(true),
+            &ReachLimits::default(),
+            &SearchBudget::unlimited(),
+        ) else {
+            panic!("Reach timed out");
+        };
+        assert_eq!(reach.len(), 4);
+        let targets = |path: Vec<Connection>| path.iter().map(|c| c.target).collect::<Vec<_>>();
+        assert_eq!(reach.path_to(1).map(targets), Some(vec![]));
+        assert_eq!(reach.path_to(3).map(targets), Some(vec![2, 3]));
+        assert_eq!(reach.get(3).map(|r| r.hops), Some(2));
+
+        let ReachResult::Timeout(reach) = calc_reach(
+            &universe,
+            &universe.stars[1],
+            Meters::new(15.0),
+            &PathOptimize::Distance,
+            &PathConstraints::default(),
+            &ReachLimits::default(),
+            &SearchBudget::unlimited().with_max_expansions(2),
+        ) else {
+            panic!("Reach should have run out of expansions");
+        };
+        assert_eq!(reach.len(), 2);
+        assert_eq!(reach.path_to(3), None);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::calc::budget::SearchBudget;
+use crate::calc::path::{
+    calc_path, cost, PathAlgorithm, PathConstraints, PathOptimize, PathResult,
+};
+use crate::data::*;
+use crate::error::{Error, Result};
+use crate::units::Meters;
+
+/// Held-Karp is O(2^n n^2), so don't let people ask for silly sizes
+pub const MAX_REORDERED_WAYPOINTS: usize = 12;
+
+/// Which order to visit the waypoints in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaypointOrder {
+    /// In the order given
+    #[default]
+    AsGiven,
+    /// In whatever order is cheapest, starting from the first waypoint and
+    /// (if `fixed_end`) finishing at the last
+    Optimize { fixed_end: bool },
+}
+
+/// The path between two consecutive waypoints
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leg {
+    pub from: StarIdx,
+    pub to: StarIdx,
+    pub path: Vec<Connection>,
+    pub cost: f64,
+    pub distance: Meters,
+}
+
+impl Leg {
+    fn new(from: StarIdx, to: StarIdx, path: Vec<Connection>, optimize: PathOptimize) -> Leg {
+        Leg {
+            from,
+            to,
+            cost: path.iter().map(|c| cost(optimize, c)).sum(),
+            distance: Meters::new(path.iter().map(|c| c.distance.get()).sum()),
+            path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    /// The waypoints, in the order they are visited
+    pub order: Vec<StarIdx>,
+    pub legs: Vec<Leg>,
+}
+
+impl Route {
+    pub fn cost(&self) -> f64 {
+        self.legs.iter().map(|l| l.cost).sum()
+    }
+
+    pub fn distance(&self) -> Meters {
+        Meters::new(self.legs.iter().map(|l| l.distance.get()).sum())
+    }
+
+    /// Every leg joined together into one path
+    pub fn path(&self) -> Vec<Connection> {
+        self.legs
+            .iter()
+            .flat_map(|l| l.path.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RouteResult {
+    Found(Route),
+    /// There is no way to get from one waypoint to another
+    NotFound {
+        from: StarIdx,
+        to: StarIdx,
+    },
+    Timeout,
+}
+
+/// Find a path which visits every waypoint, either in the order given or
+/// in the cheapest order
+pub fn calc_route(
+    universe: &Universe,
+    waypoints: &[&Star],
+    jump_distance: Meters,
+    optimize: PathOptimize,
+    constraints: &PathConstraints,
+    order: WaypointOrder,
+    budget: &SearchBudget,
+) -> Result<RouteResult> {
+    if waypoints.len() < 2 {
+        return Err(Error::InvalidParameter {
+            name: "waypoints",
+            message: "a route needs at least two waypoints".to_string(),
+        });
+    }
+    let stars: Vec<StarIdx> = waypoints
+        .iter()
+        .map(|s| universe.star_id_to_idx[&s.id])
+        .collect();
+    let leg = |from: usize, to: usize| -> Option<Option<Leg>> {
+        match calc_path(
+            universe,
+            waypoints[from],
+            waypoints[to],
+            jump_distance,
+            optimize,
+            constraints,
+            PathAlgorithm::default(),
+            budget,
+        ) {
+            PathResult::Found(path) => Some(Some(Leg::new(stars[from], stars[to], path, optimize))),
+            PathResult::NotFound => Some(None),
+            PathResult::Timeout(_) => None,
+        }
+    };
+
+    let fixed_end = match order {
+        WaypointOrder::AsGiven => {
+            let mut legs = Vec::new();
+            for i in 1..stars.len() {
+                match leg(i - 1, i) {
+                    Some(Some(leg)) => legs.push(leg),
+                    Some(None) => {
+                        return Ok(RouteResult::NotFound {
+                            from: stars[i - 1],
+                            to: stars[i],
+                        })
+                    }
+                    None => return Ok(RouteResult::Timeout),
+                }
+            }
+            return Ok(RouteResult::Found(Route { order: stars, legs }));
+        }
+        WaypointOrder::Optimize { fixed_end } => fixed_end,
+    };
+
+    if waypoints.len() > MAX_REORDERED_WAYPOINTS {
+        return Err(Error::InvalidParameter {
+            name: "waypoints",
+            message: format!(
+                "can only reorder up to {} waypoints",
+                MAX_REORDERED_WAYPOINTS
+            ),
+        });
+    }
+
+    // Find the path between every pair of waypoints, then pick the order
+    let n = waypoints.len();
+    let mut legs: Vec<Vec<Option<Leg>>> = vec![vec![None; n]; n];
+    for (from, row) in legs.iter_mut().enumerate() {
+        for (to, cell) in row.iter_mut().enumerate() {
+            if from != to && to != 0 {
+                match leg(from, to) {
+                    Some(leg) => *cell = leg,
+                    None => return Ok(RouteResult::Timeout),
+                }
+            }
+        }
+    }
+    let costs: Vec<Vec<f64>> = legs
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|l| l.as_ref().map(|l| l.cost).unwrap_or(f64::INFINITY))
+                .collect()
+        })
+        .collect();
+
+    let Some(visit) = cheapest_order(&costs, fixed_end) else {
+        // No order works, so the order given doesn't either - report
+        // the first leg of that which is impossible
+        let (from, to) = (1..n)
+            .map(|i| (i - 1, i))
+            .find(|&(from, to)| costs[from][to].is_infinite())
+            .unwrap_or((0, 1));
+        return Ok(RouteResult::NotFound {
+            from: stars[from],
+            to: stars[to],
+        });
+    };
+    let route_legs = visit
+        .windows(2)
+        .map(|pair| {
+            legs[pair[0]][pair[1]]
+                .clone()
+                .expect("Cheapest order only uses legs which exist")
+        })
+        .collect();
+    Ok(RouteResult::Found(Route {
+        order: visit.iter().map(|&i| stars[i]).collect(),
+        legs: route_legs,
+    }))
+}
+
+/// Solve the travelling salesman problem with Held-Karp: starting at node
+/// 0, visit every node once, finishing at the last node if `fixed_end`.
+/// Returns the order to visit the nodes in, or `None` if there's no order
+/// which avoids infinite costs.
+pub fn cheapest_order(costs: &[Vec<f64>], fixed_end: bool) -> Option<Vec<usize>> {
+    let n = costs.len();
+    let end = if fixed_end && n > 1 {
+        Some(n - 1)
+    } else {
+        None
+    };
+    // The nodes we're free to reorder
+    let free: Vec<usize> = (1..n).filter(|&i| Some(i) != end).collect();
+    let m = free.len();
+
+    // best[mask][j] = cheapest way to start at 0, visit the free nodes in
+    // `mask`, and finish at free[j]
+    let mut best = vec![vec![f64::INFINITY; m]; 1 << m];
+    let mut prev = vec![vec![usize::MAX; m]; 1 << m];
+    for j in 0..m {
+        best[1 << j][j] = costs[0][free[j]];
+    }
+    for mask in 1..(1usize << m) {
+        for j in 0..m {
+            if mask & (1 << j) == 0 || best[mask][j].is_infinite() {
+                continue;
+            }
+            for k in 0..m {
+                if mask & (1 << k) != 0 {
+                    continue;
+                }
+                let next = mask | (1 << k);
+                let c = best[mask][j] + costs[free[j]][free[k]];
+                if c < best[next][k] {
+                    best[next][k] = c;
+                    prev[next][k] = j;
+                }
+            }
+        }
+    }
+
+    let finish = |j: usize| match end {
+        Some(end) => costs[free[j]][end],
+        None => 0.0,
+    };
+    let full = (1 << m) - 1;
+    let mut order = match (0..m)
+        .map(|j| (best[full][j] + finish(j), j))
+        .filter(|(c, _)| c.is_finite())
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+    {
+        Some((_, mut j)) => {
+            let mut order = Vec::with_capacity(n);
+            let mut mask = full;
+            while j != usize::MAX {
+                order.push(free[j]);
+                let p = prev[mask][j];
+                mask &= !(1 << j);
+                j = p;
+            }
+            order.push(0);
+            order.reverse();
+            order
+        }
+        // Nothing to reorder, so it's just start -> end
+        None if m == 0 => vec![0],
+        None => return None,
+    };
+    if let Some(end) = end {
+        if costs[*order.last()?][end].is_infinite() {
+            return None;
+        }
+        order.push(end);
+    }
+    Some(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cheapest_order() {
+        // Four points on a line: 0 . 2 . 1 . 3
+        let pos = [0.0, 2.0, 1.0, 3.0];
+        let costs: Vec<Vec<f64>> = pos
+            .iter()
+            .map(|a| pos.iter().map(|b| f64::abs(a - b)).collect())
+            .collect();
+        assert_eq!(cheapest_order(&costs, false), Some(vec![0, 2, 1, 3]));
+        assert_eq!(cheapest_order(&costs, true), Some(vec![0, 2, 1, 3]));
+
+        // Having to finish in the middle means going past it first
+        let costs: Vec<Vec<f64>> = [0, 3, 2, 1]
+            .iter()
+            .map(|&a| [0, 3, 2, 1].iter().map(|&b| costs[a][b]).collect())
+            .collect();
+        assert_eq!(cheapest_order(&costs, true), Some(vec![0, 2, 1, 3]));
+
+        // Trivial cases
+        assert_eq!(cheapest_order(&[vec![0.0]], false), Some(vec![0]));
+        assert_eq!(
+            cheapest_order(&[vec![0.0, 1.0], vec![1.0, 0.0]], true),
+            Some(vec![0, 1])
+        );
+        let inf = f64::INFINITY;
+        assert_eq!(
+            cheapest_order(&[vec![0.0, inf], vec![inf, 0.0]], true),
+            None
+        );
+    }
+
+    fn find_route(waypoints: &[StarIdx], jump_distance: f64, order: WaypointOrder) -> RouteResult {
+        let universe = Universe::tiny_test();
+        let stars: Vec<&Star> = waypoints.iter().map(|&i| &universe.stars[i]).collect();
+        calc_route(
+            &universe,
+            &stars,
+            Meters::new(jump_distance),
+            PathOptimize::Distance,
+            &PathConstraints::default(),
+            order,
+            &SearchBudget::unlimited(),
+        )
+        .expect("Route should be valid")
+    }
+
+    #[test]
+    fn test_route_as_given() {
+        let RouteResult::Found(route) = find_route(&[1, 0, 2], 15.0, WaypointOrder::AsGiven) else {
+            panic!("Route should be found");
+        };
+        assert_eq!(route.order, vec![1, 0, 2]);
+        assert_eq!(route.legs.len(), 2);
+        assert_eq!(route.legs[0].distance, Meters::new(10.0));
+        assert_eq!(route.legs[1].distance, Meters::new(20.0));
+        assert_eq!(route.distance(), Meters::new(30.0));
+        let targets: Vec<StarIdx> = route.path().iter().map(|c| c.target).collect();
+        assert_eq!(targets, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_route_optimized() {
+        // Visiting 1 on the way to 2 is shorter than doubling back
+        let free = WaypointOrder::Optimize { fixed_end: false };
+        let RouteResult::Found(route) = find_route(&[0, 2, 1], 15.0, free) else {
+            panic!("Route should be found");
+        };
+        assert_eq!(route.order, vec![0, 1, 2]);
+        assert_eq!(route.distance(), Meters::new(20.0));
+
+        // Unless we have to finish at 1
+        let fixed = WaypointOrder::Optimize { fixed_end: true };
+        let RouteResult::Found(route) = find_route(&[0, 2, 1], 15.0, fixed) else {
+            panic!("Route should be found");
+        };
+        assert_eq!(route.order, vec![0, 2, 1]);
+        assert_eq!(route.distance(), Meters::new(30.0));
+    }
+
+    #[test]
+    fn test_route_not_found() {
+        // With no jumps, 0 and 3 are joined by an NPC gate and 1 is alone
+        assert_eq!(
+            find_route(&[0, 3, 1], 5.0, WaypointOrder::AsGiven),
+            RouteResult::NotFound { from: 3, to: 1 }
+        );
+        assert_eq!(
+            find_route(
+                &[0, 3, 1],
+                5.0,
+                WaypointOrder::Optimize { fixed_end: false }
+            ),
+            RouteResult::NotFound { from: 3, to: 1 }
+        );
+    }
+}
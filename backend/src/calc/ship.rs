@@ -0,0 +1,399 @@
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::calc::budget::SearchBudget;
+use crate::calc::heap::MinCost;
+use crate::calc::path::{cost, Heuristic, PathConstraints, PathOptimize};
+use crate::data::*;
+use crate::error::{Error, Result};
+use crate::units::Meters;
+
+/// A ship's mass and fuel, which together decide how far it can jump
+#[derive(clap::Args, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ShipProfile {
+    /// Mass of the empty hull, in kg
+    #[clap(long)]
+    pub hull_mass: f64,
+    /// Mass of fitted modules, in kg
+    #[clap(long, default_value = "0")]
+    pub fittings_mass: f64,
+    /// Mass of cargo, in kg
+    #[clap(long, default_value = "0")]
+    pub cargo_mass: f64,
+    /// Fuel tank capacity
+    #[clap(long)]
+    pub tank_size: f64,
+    /// Fuel currently in the tank
+    #[clap(long)]
+    pub current_fuel: f64,
+    /// Efficiency of the fuel type, eg 0.4 for D1
+    #[clap(long)]
+    pub fuel_efficiency: f64,
+}
+
+impl ShipProfile {
+    pub fn mass(&self) -> f64 {
+        self.hull_mass + self.fittings_mass + self.cargo_mass
+    }
+
+    /// Fuel burned by jumping `distance`
+    pub fn fuel_for(&self, distance: Meters) -> f64 {
+        distance.to_light_years() * self.mass() / (self.fuel_efficiency * 1e7)
+    }
+
+    /// How far we can jump with `fuel` in the tank
+    pub fn range(&self, fuel: f64) -> Meters {
+        Meters::from_light_years(fuel / self.mass() * self.fuel_efficiency * 1e7)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| {
+            Err(Error::InvalidParameter {
+                name: "ship",
+                message: message.to_string(),
+            })
+        };
+        let values = [
+            self.hull_mass,
+            self.fittings_mass,
+            self.cargo_mass,
+            self.tank_size,
+            self.current_fuel,
+            self.fuel_efficiency,
+        ];
+        if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+            return invalid("masses, fuel and efficiency must be positive numbers");
+        }
+        if self.mass() <= 0.0 {
+            return invalid("ship must have some mass");
+        }
+        if self.fuel_efficiency <= 0.0 {
+            return invalid("fuel efficiency must be more than zero");
+        }
+        if self.current_fuel > self.tank_size {
+            return invalid("current fuel doesn't fit in the tank");
+        }
+        Ok(())
+    }
+}
+
+/// One step of a route, and the fuel situation after taking it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShipStep {
+    pub conn: Connection,
+    pub fuel_used: f64,
+    pub fuel_left: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ShipPathResult {
+    Found(Vec<ShipStep>),
+    NotFound,
+    Timeout,
+}
+
+/// A way of arriving at a star, with what it cost, how much fuel is left
+/// and how many jumps it took
+struct Label {
+    conn: Connection,
+    cost: f64,
+    fuel_left: f64,
+    jumps: usize,
+    parent: Option<usize>,
+    dominated: bool,
+}
+
+/// Like `calc_path`, but for a specific ship: every jump burns fuel, and
+/// what's left in the tank limits how far the next jump can go. Gates
+/// don't burn any fuel.
+///
+/// Since the cheapest way to reach a star may leave less fuel than a
+/// pricier one, each star keeps every arrival which isn't beaten on both
+/// cost and fuel left (and jumps taken, if they're limited) by some other
+/// arrival.
+pub fn calc_ship_path(
+    universe: &Universe,
+    start: &Star,
+    end: &Star,
+    ship: &ShipProfile,
+    optimize: PathOptimize,
+    constraints: &PathConstraints,
+    budget: &SearchBudget,
+) -> Result<ShipPathResult> {
+    ship.validate()?;
+    let end_idx = universe.star_id_to_idx[&end.id];
+    let max_range = ship.range(ship.current_fuel);
+    let heuristic = Heuristic::new(
+        universe,
+        end,
+        max_range,
+        optimize,
+        constraints.use_smart_gates,
+    );
+
+    let mut labels: Vec<Label> = vec![Label {
+        conn: Connection {
+            id: 0,
+            conn_type: ConnType::Jump,
+            distance: Meters::new(0.0),
+            target: universe.star_id_to_idx[&start.id],
+        },
+        cost: 0.0,
+        fuel_left: ship.current_fuel,
+        jumps: 0,
+        parent: None,
+        dominated: false,
+    }];
+    let mut at_star: Vec<Vec<usize>> = vec![Vec::new(); universe.stars.len()];
+    at_star[labels[0].conn.target].push(0);
+    let mut open = BinaryHeap::new();
+    open.push(MinCost {
+        cost: heuristic.estimate(labels[0].conn.target),
+        item: 0,
+    });
+
+    while let Some(MinCost { item: label, .. }) = open.pop() {
+        if labels[label].dominated {
+            continue;
+        }
+        if !budget.expand() {
+            return Ok(ShipPathResult::Timeout);
+        }
+        let (star_idx, cost_so_far, fuel_left, jumps) = (
+            labels[label].conn.target,
+            labels[label].cost,
+            labels[label].fuel_left,
+            labels[label].jumps,
+        );
+        if star_idx == end_idx {
+            return Ok(ShipPathResult::Found(steps(&labels, label)));
+        }
+
+        // Allow for rounding in both directions between fuel and range
+        let slack = 1.0 + 1e-12;
+        let range = match constraints.max_jumps {
+            Some(max_jumps) if jumps >= max_jumps => Meters::new(0.0),
+            _ => ship.range(fuel_left * slack),
+        };
+        for conn in universe.connections(star_idx, range) {
+            if !constraints.allows(universe, &conn) {
+                continue;
+            }
+            let (fuel_used, new_jumps) = match conn.conn_type {
+                ConnType::Jump => (ship.fuel_for(conn.distance), jumps + 1),
+                ConnType::NpcGate | ConnType::SmartGate => (0.0, jumps),
+            };
+            if fuel_used > fuel_left * slack
+                || constraints.max_jumps.is_some_and(|max| new_jumps > max)
+            {
+                continue;
+            }
+            let new_cost = cost_so_far + cost(optimize, &conn);
+            let new_fuel = (fuel_left - fuel_used).max(0.0);
+            let target = conn.target;
+
+            // Jumps taken only matter if they're limited
+            let fewer_jumps = |a: usize, b: usize| constraints.max_jumps.is_none() || a <= b;
+            let others = &at_star[target];
+            if others.iter().any(|&other| {
+                labels[other].cost <= new_cost
+                    && labels[other].fuel_left >= new_fuel
+                    && fewer_jumps(labels[other].jumps, new_jumps)
+            }) {
+                continue;
+            }
+            for &other in others {
+                if new_cost <= labels[other].cost
+                    && new_fuel >= labels[other].fuel_left
+                    && fewer_jumps(new_jumps, labels[other].jumps)
+                {
+                    labels[other].dominated = true;
+                }
+            }
+            at_star[target].retain(|&other| !labels[other].dominated);
+
+            let new_label = labels.len();
+            labels.push(Label {
+                conn,
+                cost: new_cost,
+                fuel_left: new_fuel,
+                jumps: new_jumps,
+                parent: Some(label),
+                dominated: false,
+            });
+            at_star[target].push(new_label);
+            open.push(MinCost {
+                cost: new_cost + heuristic.estimate(target),
+                item: new_label,
+            });
+        }
+    }
+    Ok(ShipPathResult::NotFound)
+}
+
+/// Walk back from the final label to build the list of steps, skipping
+/// the connection we invented to start the search
+fn steps(labels: &[Label], last: usize) -> Vec<ShipStep> {
+    let mut steps = Vec::new();
+    let mut current = last;
+    while let Some(parent) = labels[current].parent {
+        let label = &labels[current];
+        steps.push(ShipStep {
+            conn: label.conn.clone(),
+            fuel_used: labels[parent].fuel_left - label.fuel_left,
+            fuel_left: label.fuel_left,
+        });
+        current = parent;
+    }
+    steps.reverse();
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ship which burns one unit of fuel per meter, to match the
+    /// distances in the tiny test universe
+    fn ship(current_fuel: f64) -> ShipProfile {
+        ShipProfile {
+            hull_mass: Meters::from_light_years(1e7).get(),
+            fittings_mass: 0.0,
+            cargo_mass: 0.0,
+            tank_size: 100.0,
+            current_fuel,
+            fuel_efficiency: 1.0,
+        }
+    }
+
+    fn call_calc_ship_path(
+        start: StarIdx,
+        end: StarIdx,
+        current_fuel: f64,
+        optimize: PathOptimize,
+        use_smart_gates: bool,
+    ) -> Option<Vec<(StarIdx, ConnType, i64)>> {
+        let universe = Universe::tiny_test();
+        match calc_ship_path(
+            &universe,
+            &universe.stars[start],
+            &universe.stars[end],
+            &ship(current_fuel),
+            optimize,
+            &PathConstraints::with_smart_gates(use_smart_gates),
+            &SearchBudget::unlimited(),
+        )
+        .expect("Ship should be valid")
+        {
+            ShipPathResult::Found(steps) => Some(
+                steps
+                    .into_iter()
+                    .map(|s| (s.conn.target, s.conn.conn_type, s.fuel_left.round() as i64))
+                    .collect(),
+            ),
+            ShipPathResult::NotFound => None,
+            ShipPathResult::Timeout => panic!("Path search timed out"),
+        }
+    }
+
+    #[test]
+    fn test_fuel_and_range() {
+        let ship = ShipProfile {
+            hull_mass: 20_000_000.0,
+            fittings_mass: 5_000_000.0,
+            cargo_mass: 3_000_000.0,
+            tank_size: 539.0,
+            current_fuel: 539.0,
+            fuel_efficiency: 0.4,
+        };
+        // Same as the frontend's jump range calculator
+        assert_eq!(ship.range(539.0).to_light_years().round(), 77.0);
+        assert!((ship.fuel_for(ship.range(100.0)) - 100.0).abs() < 1e-9);
+    }
+
+    // Gates are free, so use them
+    #[test]
+    fn test_ship_path_gates_burn_nothing() {
+        assert_eq!(
+            call_calc_ship_path(0, 2, 25.0, PathOptimize::Fuel, true),
+            Some(vec![
+                (3, ConnType::NpcGate, 25),
+                (2, ConnType::SmartGate, 25)
+            ])
+        );
+    }
+
+    // Every route needs 20 fuel
+    #[test]
+    fn test_ship_path_runs_dry() {
+        assert_eq!(
+            call_calc_ship_path(0, 2, 15.0, PathOptimize::Fuel, false),
+            None
+        );
+        let path = call_calc_ship_path(0, 2, 20.0, PathOptimize::Fuel, false).expect("Enough fuel");
+        assert_eq!(path.last().map(|s| s.2), Some(0));
+    }
+
+    // Fewest hops is one long jump, if we have the fuel for it
+    #[test]
+    fn test_ship_path_hops_limited_by_fuel() {
+        assert_eq!(
+            call_calc_ship_path(1, 3, 25.0, PathOptimize::Hops, false),
+            Some(vec![(3, ConnType::Jump, 3)])
+        );
+        assert_eq!(
+            call_calc_ship_path(1, 3, 21.0, PathOptimize::Hops, false),
+            Some(vec![(0, ConnType::Jump, 11), (3, ConnType::NpcGate, 11)])
+        );
+    }
+
+    #[test]
+    fn test_ship_path_constraints() {
+        let universe = Universe::tiny_test();
+        let path =
+            |start: StarIdx, end: StarIdx, constraints: PathConstraints| match calc_ship_path(
+                &universe,
+                &universe.stars[start],
+                &universe.stars[end],
+                &ship(25.0),
+                PathOptimize::Hops,
+                &constraints,
+                &SearchBudget::unlimited(),
+            )
+            .expect("Ship should be valid")
+            {
+                ShipPathResult::Found(steps) => Some(steps.len()),
+                ShipPathResult::NotFound => None,
+                ShipPathResult::Timeout => panic!("Path search timed out"),
+            };
+        let no_jumps = PathConstraints {
+            no_jumps: true,
+            ..Default::default()
+        };
+        assert_eq!(path(1, 3, no_jumps), None);
+        let max_jumps = PathConstraints {
+            max_jumps: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(path(0, 3, max_jumps.clone()), Some(1));
+        assert_eq!(path(0, 1, max_jumps), None);
+    }
+
+    #[test]
+    fn test_invalid_ship() {
+        let universe = Universe::tiny_test();
+        let mut overfull = ship(10.0);
+        overfull.current_fuel = 1000.0;
+        let result = calc_ship_path(
+            &universe,
+            &universe.stars[0],
+            &universe.stars[1],
+            &overfull,
+            PathOptimize::Fuel,
+            &PathConstraints::default(),
+            &SearchBudget::unlimited(),
+        );
+        assert!(matches!(result, Err(Error::InvalidParameter { .. })));
+    }
+}
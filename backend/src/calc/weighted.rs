@@ -0,0 +1,431 @@
+use std::collections::BinaryHeap;
+
+use serde::Serialize;
+
+use crate::calc::budget::SearchBudget;
+use crate::calc::heap::MinCost;
+use crate::calc::path::{
+    calc_path_with_model, cost, successors, CostModel, Heuristic, PathAlgorithm, PathConstraints,
+    PathOptimize, PathResult,
+};
+use crate::data::*;
+use crate::error::{Error, Result};
+use crate::units::Meters;
+
+/// What each part of a route costs. A route's cost is the sum of every
+/// weight times how much of that thing it does, so eg `jump_ly = 1` and
+/// `hops = 20` will take an extra hop to save more than 20 ly of jumping.
+/// Only made through `new`, so the weights always make sense.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PathWeights {
+    jump_ly: f64,
+    total_ly: f64,
+    hops: f64,
+    smart_gates: f64,
+}
+
+impl PathWeights {
+    /// `jump_ly` is the cost of each light year jumped (gates count as a
+    /// hair, like when optimizing for fuel), `total_ly` of each light year
+    /// travelled by jump or gate, `hops` of each jump or gate taken and
+    /// `smart_gates` the extra cost of each smart gate. They must all be
+    /// positive, and at least one more than zero.
+    pub fn new(jump_ly: f64, total_ly: f64, hops: f64, smart_gates: f64) -> Result<PathWeights> {
+        let invalid = |message: &str| {
+            Err(Error::InvalidParameter {
+                name: "weights",
+                message: message.to_string(),
+            })
+        };
+        let values = [jump_ly, total_ly, hops, smart_gates];
+        if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+            return invalid("weights must be positive numbers");
+        }
+        if values.iter().all(|v| *v == 0.0) {
+            return invalid("at least one weight must be more than zero");
+        }
+        Ok(PathWeights {
+            jump_ly,
+            total_ly,
+            hops,
+            smart_gates,
+        })
+    }
+}
+
+impl CostModel for PathWeights {
+    fn cost(&self, conn: &Connection) -> f64 {
+        let smart_gate = match conn.conn_type {
+            ConnType::SmartGate => self.smart_gates,
+            ConnType::Jump | ConnType::NpcGate => 0.0,
+        };
+        self.jump_ly * cost(PathOptimize::Fuel, conn)
+            + self.total_ly * conn.distance.to_light_years()
+            + self.hops
+            + smart_gate
+    }
+
+    /// Adds up lower bounds for each part of the cost. A smart gate is
+    /// never needed, so that part has no lower bound.
+    fn heuristic<'a>(
+        &'a self,
+        universe: &'a Universe,
+        end: &'a Star,
+        jump_distance: Meters,
+        constraints: &PathConstraints,
+    ) -> Box<dyn Fn(StarIdx) -> f64 + 'a> {
+        let heuristic = |optimize| {
+            Heuristic::new(
+                universe,
+                end,
+                jump_distance,
+                optimize,
+                constraints.use_smart_gates,
+            )
+        };
+        let (fuel, hops) = (heuristic(PathOptimize::Fuel), heuristic(PathOptimize::Hops));
+        Box::new(move |star_idx| {
+            let direct = universe.stars[star_idx].distance(end);
+            self.jump_ly * fuel.estimate(star_idx)
+                + self.total_ly * direct.to_light_years()
+                + self.hops * hop_count(&hops, star_idx) as f64
+        })
+    }
+}
+
+/// The fewest hops `heuristic` (which must be for `PathOptimize::Hops`)
+/// thinks it could take from `star_idx`
+fn hop_count(heuristic: &Heuristic, star_idx: StarIdx) -> usize {
+    (heuristic.estimate(star_idx) / Meters::new(1.0).to_light_years()).round() as usize
+}
+
+/// Like `calc_path`, but with the cost of each connection made up from
+/// `weights` instead of one of the fixed `PathOptimize` modes. Always
+/// uses A*, since hierarchies are only built for the fixed modes.
+pub fn calc_weighted_path(
+    universe: &Universe,
+    start: &Star,
+    end: &Star,
+    jump_distance: Meters,
+    weights: &PathWeights,
+    constraints: &PathConstraints,
+    budget: &SearchBudget,
+) -> PathResult {
+    calc_path_with_model(
+        universe,
+        start,
+        end,
+        jump_distance,
+        weights,
+        constraints,
+        PathAlgorithm::AStar,
+        budget,
+    )
+    .0
+}
+
+/// A route which no other route beats on both fuel and hops
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParetoRoute {
+    pub path: Vec<Connection>,
+    /// As `PathOptimize::Fuel` counts it
+    pub fuel: f64,
+    pub hops: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParetoResult {
+    /// Least fuel first, so each route takes fewer hops than the one
+    /// before
+    Found(Vec<ParetoRoute>),
+    NotFound,
+    /// The budget ran out. Holds the routes found so far, which are all
+    /// on the front, but the front may go on to fewer hops.
+    Timeout(Vec<ParetoRoute>),
+}
+
+/// A way of arriving at a star
+struct Label {
+    star_idx: StarIdx,
+    fuel: f64,
+    hops: usize,
+    jumps: usize,
+    parent: Option<(usize, Connection)>,
+}
+
+/// Find every route from `start` to `end` which isn't beaten on both
+/// fuel and hops by some other route - from the most fuel efficient to
+/// the fewest hops, and every useful compromise in between.
+///
+/// Arrivals are taken in order of estimated fuel, so at any one star
+/// each arrival uses at least as much fuel as the ones before, and is
+/// only worth following if it took fewer hops (or jumps, if they're
+/// limited). Likewise each route found uses at least as much fuel as
+/// the last one, so it's only on the front if it takes fewer hops.
+pub fn calc_pareto_front(
+    universe: &Universe,
+    start: &Star,
+    end: &Star,
+    jump_distance: Meters,
+    constraints: &PathConstraints,
+    budget: &SearchBudget,
+) -> ParetoResult {
+    let end_idx = universe.star_id_to_idx[&end.id];
+    let heuristic = |optimize| {
+        Heuristic::new(
+            universe,
+            end,
+            jump_distance,
+            optimize,
+            constraints.use_smart_gates,
+        )
+    };
+    let (fuel_heuristic, hops_heuristic) =
+        (heuristic(PathOptimize::Fuel), heuristic(PathOptimize::Hops));
+
+    let mut labels = vec![Label {
+        star_idx: universe.star_id_to_idx[&start.id],
+        fuel: 0.0,
+        hops: 0,
+        jumps: 0,
+        parent: None,
+    }];
+    // The (hops, jumps) of each arrival expanded at each star, with jumps
+    // left at zero if they're not limited
+    let mut expanded: Vec<Vec<(usize, usize)>> = vec![Vec::new(); universe.stars.len()];
+    let mut routes: Vec<ParetoRoute> = Vec::new();
+    let mut open = BinaryHeap::new();
+    // Ordered by estimated fuel, then estimated hops
+    open.push(MinCost {
+        cost: fuel_heuristic.estimate(labels[0].star_idx),
+        item: (hop_count(&hops_heuristic, labels[0].star_idx), 0),
+    });
+
+    while let Some(MinCost {
+        item: (_, label), ..
+    }) = open.pop()
+    {
+        let (star_idx, fuel, hops, jumps) = {
+            let l = &labels[label];
+            (l.star_idx, l.fuel, l.hops, l.jumps)
+        };
+        let jumps_key = constraints.max_jumps.map_or(0, |_| jumps);
+        if expanded[star_idx]
+            .iter()
+            .any(|&(h, j)| h <= hops && j <= jumps_key)
+        {
+            continue;
+        }
+        if routes
+            .last()
+            .is_some_and(|r| r.hops <= hops + hop_count(&hops_heuristic, star_idx))
+        {
+            continue;
+        }
+        if !budget.expand() {
+            return ParetoResult::Timeout(routes);
+        }
+        expanded[star_idx].push((hops, jumps_key));
+        if star_idx == end_idx {
+            routes.push(ParetoRoute {
+                path: path_to(&labels, label),
+                fuel,
+                hops,
+            });
+            continue;
+        }
+
+        let jump_distance = match constraints.max_jumps {
+            Some(max_jumps) if jumps >= max_jumps => Meters::new(0.0),
+            _ => jump_distance,
+        };
+        for (conn, cost) in successors(
+            universe,
+            star_idx,
+            jump_distance,
+            &PathOptimize::Fuel,
+            constraints,
+        ) {
+            let target = conn.target;
+            let new_jumps = match conn.conn_type {
+                ConnType::Jump => jumps + 1,
+                ConnType::NpcGate | ConnType::SmartGate => jumps,
+            };
+            let new_label = labels.len();
+            labels.push(Label {
+                star_idx: target,
+                fuel: fuel + cost,
+                hops: hops + 1,
+                jumps: new_jumps,
+                parent: Some((label, conn)),
+            });
+            open.push(MinCost {
+                cost: fuel + cost + fuel_heuristic.estimate(target),
+                item: (hops + 1 + hop_count(&hops_heuristic, target), new_label),
+            });
+        }
+    }
+    match routes.is_empty() {
+        true => ParetoResult::NotFound,
+        false => ParetoResult::Found(routes),
+    }
+}
+
+fn path_to(labels: &[Label], mut label: usize) -> Vec<Connection> {
+    let mut path = Vec::new();
+    while let Some((parent, conn)) = &labels[label].parent {
+        path.push(conn.clone());
+        label = *parent;
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weighted_path(start: StarIdx, end: StarIdx, weights: PathWeights) -> Option<Vec<StarIdx>> {
+        let universe = Universe::tiny_test();
+        match calc_weighted_path(
+            &universe,
+            &universe.stars[start],
+            &universe.stars[end],
+            Meters::new(15.0),
+            &weights,
+            &PathConstraints::with_smart_gates(true),
+            &SearchBudget::unlimited(),
+        ) {
+            PathResult::Found(path) => Some(path.iter().map(|c| c.target).collect()),
+            PathResult::NotFound => None,
+            PathResult::Timeout(_) => panic!("Path search timed out"),
+        }
+    }
+
+    #[test]
+    fn test_weighted_path() {
+        // 1 -> 2 -> 3 is 30 in total, via 0 is 38, but 2 -> 3 is a smart gate
+        let total = PathWeights::new(0.0, 1.0, 0.0, 0.0).expect("Weights should be valid");
+        assert_eq!(weighted_path(1, 3, total), Some(vec![2, 3]));
+        let avoid_smart = PathWeights::new(0.0, 1.0, 0.0, Meters::new(10.0).to_light_years())
+            .expect("Weights should be valid");
+        assert_eq!(weighted_path(1, 3, avoid_smart), Some(vec![0, 3]));
+    }
+
+    #[test]
+    fn test_weights_validated() {
+        let invalid =
+            |weights: Result<PathWeights>| matches!(weights, Err(Error::InvalidParameter { .. }));
+        assert!(invalid(PathWeights::new(0.0, 0.0, 0.0, 0.0)));
+        assert!(invalid(PathWeights::new(-1.0, 0.0, 1.0, 0.0)));
+        assert!(invalid(PathWeights::new(0.0, f64::NAN, 1.0, 0.0)));
+        assert!(invalid(PathWeights::new(0.0, 0.0, 1.0, f64::INFINITY)));
+        assert!(!invalid(PathWeights::new(0.0, 0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn test_pareto_front() {
+        let universe = Universe::tiny_test();
+        let front = |jump_distance: f64| match calc_pareto_front(
+            &universe,
+            &universe.stars[1],
+            &universe.stars[3],
+            Meters::new(jump_distance),
+            &PathConstraints::with_smart_gates(true),
+            &SearchBudget::unlimited(),
+        ) {
+            ParetoResult::Found(routes) => routes
+                .into_iter()
+                .map(|r| {
+                    let targets: Vec<StarIdx> = r.path.iter().map(|c| c.target).collect();
+                    (
+                        targets,
+                        Meters::from_light_years(r.fuel).get().round(),
+                        r.hops,
+                    )
+                })
+                .collect(),
+            ParetoResult::NotFound => vec![],
+            ParetoResult::Timeout(_) => panic!("Pareto search timed out"),
+        };
+        // Jumping 10 to 0 and taking the NPC gate (1) uses the least fuel,
+        // but jumping 22 straight to 3 takes one hop
+        assert_eq!(front(25.0), vec![(vec![0, 3], 11.0, 2), (vec![3], 22.0, 1)]);
+        // Without the range to jump straight there, there's only one
+        // choice
+        assert_eq!(front(15.0), vec![(vec![0, 3], 11.0, 2)]);
+    }
+
+    // The weighted search agrees with Dijkstra when the weights match a
+    // fixed mode, and the front runs from the cheapest fuel route to the
+    // fewest hops
+    #[test]
+    fn test_weighted_on_random_universes() {
+        use crate::calc::path::tests::{dijkstra, random_universe};
+
+        let hop = Meters::new(1.0).to_light_years();
+        for seed in 0..10 {
+            let universe = random_universe(seed, JumpEdges::OnDemand);
+            let constraints = PathConstraints::with_smart_gates(true);
+            for (start, end) in [(0, 1), (2, 3), (4, 5), (6, 7)] {
+                for jump_distance in [15.0, 25.0] {
+                    let jump_distance = Meters::from_light_years(jump_distance);
+                    let expected = |optimize| {
+                        dijkstra(&universe, start, end, jump_distance, optimize, &constraints)
+                    };
+                    let fuel =
+                        PathWeights::new(1.0, 0.0, 0.0, 0.0).expect("Weights should be valid");
+                    let found = calc_weighted_path(
+                        &universe,
+                        &universe.stars[start],
+                        &universe.stars[end],
+                        jump_distance,
+                        &fuel,
+                        &constraints,
+                        &SearchBudget::unlimited(),
+                    );
+                    let found = match found {
+                        PathResult::Found(path) => {
+                            Some(path.iter().map(|c| fuel.cost(c)).sum::<f64>())
+                        }
+                        PathResult::NotFound => None,
+                        PathResult::Timeout(_) => panic!("Path search timed out"),
+                    };
+                    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+                    match (found, expected(PathOptimize::Fuel)) {
+                        (Some(f), Some(e)) => assert!(close(f, e), "{} != {}", f, e),
+                        (f, e) => assert_eq!(f, e),
+                    }
+
+                    let front = calc_pareto_front(
+                        &universe,
+                        &universe.stars[start],
+                        &universe.stars[end],
+                        jump_distance,
+                        &constraints,
+                        &SearchBudget::unlimited(),
+                    );
+                    match front {
+                        ParetoResult::Found(routes) => {
+                            let (first, last) = (&routes[0], &routes[routes.len() - 1]);
+                            let fuel = expected(PathOptimize::Fuel).expect("Should be a path");
+                            let hops = expected(PathOptimize::Hops).expect("Should be a path");
+                            assert!(close(first.fuel, fuel), "{} != {}", first.fuel, fuel);
+                            assert_eq!(last.hops, (hops / hop).round() as usize);
+                            for pair in routes.windows(2) {
+                                assert!(pair[0].fuel < pair[1].fuel);
+                                assert!(pair[0].hops > pair[1].hops);
+                            }
+                            for route in &routes {
+                                assert_eq!(route.hops, route.path.len());
+                                assert_eq!(route.path.last().map(|c| c.target), Some(end));
+                            }
+                        }
+                        ParetoResult::NotFound => assert_eq!(found, None),
+                        ParetoResult::Timeout(_) => panic!("Pareto search timed out"),
+                    }
+                }
+            }
+        }
+    }
+}
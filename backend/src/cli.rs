@@ -1,7 +1,20 @@
-use std::time::Instant;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
 use clap::{Parser, Subcommand};
+use eftb::calc::alternatives::AlternativesResult;
+use eftb::calc::budget::SearchBudget;
+use eftb::calc::hierarchy::HierarchyKey;
+use eftb::calc::nearest::{NearestResult, SystemFilter};
+use eftb::calc::path::{CostModel, PathAlgorithm, PathConstraints, PathOptimize, SearchStats};
+use eftb::calc::rally::{FleetMember, MemberRange, RallyObjective, RallyResult};
+use eftb::calc::reach::{ReachLimits, ReachResult};
+use eftb::calc::route::{RouteResult, WaypointOrder};
+use eftb::calc::ship::ShipProfile;
+use eftb::calc::weighted::{ParetoResult, PathWeights};
 use eftb::data;
+use eftb::source::UniverseSource;
 use eftb::units::Meters;
 use log::{info, warn};
 
@@ -11,14 +24,182 @@ use log::{info, warn};
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// Directory containing starmap.json and smartgates.json
+    #[clap(long, global = true, default_value = "data")]
+    data_dir: PathBuf,
+    /// Use this starmap instead of the one in --data-dir
+    #[clap(long, global = true)]
+    starmap: Option<PathBuf>,
+    /// Use this smart gate list instead of the one in --data-dir
+    #[clap(long, global = true)]
+    smartgates: Option<PathBuf>,
+    /// Cache the built universe here instead of in --data-dir
+    #[clap(long, global = true)]
+    snapshot: Option<PathBuf>,
+}
+
+impl Cli {
+    fn source(&self) -> UniverseSource {
+        UniverseSource::with_overrides(
+            &self.data_dir,
+            self.starmap.clone(),
+            self.smartgates.clone(),
+            self.snapshot.clone(),
+        )
+    }
+}
+
+/// What each part of a route costs, for `weighted-path`
+#[derive(clap::Args)]
+struct WeightArgs {
+    /// Cost of each light year jumped (gates count as a hair, like when
+    /// optimizing for fuel)
+    #[clap(long, default_value = "0")]
+    jump_ly: f64,
+    /// Cost of each light year travelled, whether by jump or by gate
+    #[clap(long, default_value = "0")]
+    total_ly: f64,
+    /// Cost of each jump or gate taken
+    #[clap(long, default_value = "0")]
+    hops: f64,
+    /// Extra cost of each smart gate taken
+    #[clap(long, default_value = "0")]
+    smart_gates: f64,
+}
+
+impl WeightArgs {
+    fn weights(&self) -> eftb::Result<PathWeights> {
+        PathWeights::new(self.jump_ly, self.total_ly, self.hops, self.smart_gates)
+    }
+}
+
+/// Ways to keep a path away from trouble
+#[derive(clap::Args)]
+struct AvoidArgs {
+    /// Never pass through this system (can be given more than once)
+    #[clap(long = "avoid", value_name = "NAME")]
+    avoid_systems: Vec<String>,
+    /// Never pass through this region, by ID (can be given more than once)
+    #[clap(long = "avoid-region", value_name = "ID")]
+    avoid_regions: Vec<data::RegionId>,
+    /// Never take this smart gate, by item ID (can be given more than once)
+    #[clap(long = "avoid-gate", value_name = "ID")]
+    avoid_smart_gates: Vec<u32>,
+    /// Don't take NPC gates
+    #[clap(long)]
+    no_npc_gates: bool,
+    /// Only travel by gate
+    #[clap(long)]
+    no_jumps: bool,
+    /// Take at most this many jumps
+    #[clap(long)]
+    max_jumps: Option<usize>,
+}
+
+impl AvoidArgs {
+    fn constraints(
+        &self,
+        universe: &data::Universe,
+        use_smart_gates: bool,
+    ) -> eftb::Result<PathConstraints> {
+        Ok(PathConstraints {
+            use_smart_gates,
+            avoid_systems: self
+                .avoid_systems
+                .iter()
+                .map(|name| universe.star_by_name(name).map(|s| s.id))
+                .collect::<eftb::Result<_>>()?,
+            avoid_regions: self.avoid_regions.iter().cloned().collect(),
+            avoid_smart_gates: self.avoid_smart_gates.iter().cloned().collect(),
+            no_npc_gates: self.no_npc_gates,
+            no_jumps: self.no_jumps,
+            max_jumps: self.max_jumps,
+            ..Default::default()
+        })
+    }
+}
+
+/// Limits on how much work a search can do
+#[derive(clap::Args)]
+struct BudgetArgs {
+    /// Give up after this many seconds
+    #[clap(long, value_name = "SECS", default_value = "30")]
+    timeout: f64,
+    /// Give up after expanding this many stars
+    #[clap(long, value_name = "N")]
+    max_expansions: Option<usize>,
+}
+
+impl BudgetArgs {
+    fn budget(&self) -> eftb::Result<SearchBudget> {
+        let time = Duration::try_from_secs_f64(self.timeout).map_err(|_| {
+            eftb::Error::InvalidParameter {
+                name: "timeout",
+                message: format!("{} is not a valid number of seconds", self.timeout),
+            }
+        })?;
+        let budget = SearchBudget::unlimited().with_time(time);
+        Ok(match self.max_expansions {
+            Some(max_expansions) => budget.with_max_expansions(max_expansions),
+            None => budget,
+        })
+    }
+}
+
+/// What kind of system to look for
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+struct FilterArgs {
+    /// A system with a smart gate
+    #[clap(long)]
+    smart_gate: bool,
+    /// A system in this region, by ID
+    #[clap(long, value_name = "ID")]
+    region: Option<data::RegionId>,
+    /// A system on a different NPC gate network
+    #[clap(long)]
+    other_network: bool,
+    /// One of these systems (can be given more than once)
+    #[clap(long, value_name = "NAME")]
+    one_of: Vec<String>,
+}
+
+impl FilterArgs {
+    fn filter(&self, universe: &data::Universe) -> eftb::Result<SystemFilter> {
+        Ok(if self.smart_gate {
+            SystemFilter::SmartGate
+        } else if let Some(region) = self.region {
+            SystemFilter::Region(region)
+        } else if self.other_network {
+            SystemFilter::OtherGateNetwork
+        } else {
+            SystemFilter::OneOf(
+                self.one_of
+                    .iter()
+                    .map(|name| universe.star_by_name(name).map(|s| s.id))
+                    .collect::<eftb::Result<_>>()?,
+            )
+        })
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Build the starmap from star_data.json and star_names.json
+    /// Build the starmap from starmap.json and smartgates.json, and save
+    /// a snapshot of it for the other commands to load
     Build {
         #[clap(default_value = "500.0")]
         max_jump_distance: f64,
+        /// Also build a contraction hierarchy for this jump range, to make
+        /// `path --algorithm hierarchy` fast (can be given more than once)
+        #[clap(long = "hierarchy", value_name = "LY")]
+        hierarchy_jump_distances: Vec<f64>,
+        /// What the hierarchies should optimize for
+        #[clap(long, default_value = "hops")]
+        hierarchy_optimize: PathOptimize,
+        /// Whether the hierarchies should use smart gates
+        #[clap(long)]
+        hierarchy_smart_gates: bool,
     },
     /// Find the direct distance between two stars
     Dist {
@@ -35,6 +216,143 @@ enum Commands {
         optimize: eftb::calc::path::PathOptimize,
         #[clap(short, long)]
         use_smart_gates: bool,
+        #[command(flatten)]
+        avoid: AvoidArgs,
+        /// Show this many of the best routes, instead of just the best one
+        #[clap(long, value_name = "N")]
+        alternatives: Option<usize>,
+        /// Show every route which isn't beaten on both fuel and hops by
+        /// another, instead of just the best one
+        #[clap(long, conflicts_with = "alternatives")]
+        pareto: bool,
+        #[clap(long, default_value = "astar")]
+        algorithm: PathAlgorithm,
+        #[command(flatten)]
+        budget: BudgetArgs,
+        /// Show what the search did, to see why it was slow
+        #[clap(long)]
+        explain: bool,
+    },
+    /// Find a path which visits several stars
+    Route {
+        #[clap(required = true, num_args = 2..)]
+        waypoints: Vec<String>,
+        #[clap(short, long, default_value = "100.0")]
+        jump_distance: f64,
+        #[clap(short, long, default_value = "fuel")]
+        optimize: eftb::calc::path::PathOptimize,
+        #[clap(short, long)]
+        use_smart_gates: bool,
+        /// Visit the waypoints in whichever order is cheapest, always
+        /// starting from the first one
+        #[clap(long)]
+        optimize_order: bool,
+        /// When optimizing the order, always finish at the last waypoint
+        #[clap(long, requires = "optimize_order")]
+        fixed_end: bool,
+        #[command(flatten)]
+        avoid: AvoidArgs,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+    /// Find the cheapest path, with the cost made up of several weighted
+    /// parts
+    WeightedPath {
+        start_name: String,
+        end_name: String,
+        #[clap(short, long, default_value = "100.0")]
+        jump_distance: f64,
+        #[command(flatten)]
+        weights: WeightArgs,
+        #[clap(short, long)]
+        use_smart_gates: bool,
+        #[command(flatten)]
+        avoid: AvoidArgs,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+    /// Find the shortest path for a specific ship, tracking its fuel
+    ShipPath {
+        start_name: String,
+        end_name: String,
+        #[command(flatten)]
+        ship: eftb::calc::ship::ShipProfile,
+        #[clap(short, long, default_value = "fuel")]
+        optimize: eftb::calc::path::PathOptimize,
+        #[clap(short, long)]
+        use_smart_gates: bool,
+        #[command(flatten)]
+        avoid: AvoidArgs,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+    /// List every system reachable from a star, and the cheapest way to
+    /// each
+    Reach {
+        start_name: String,
+        #[clap(short, long, default_value = "100.0")]
+        jump_distance: f64,
+        #[clap(short, long, default_value = "fuel")]
+        optimize: eftb::calc::path::PathOptimize,
+        #[clap(short, long)]
+        use_smart_gates: bool,
+        /// Only systems which cost at most this much to reach (in ly, when
+        /// optimizing for fuel or distance)
+        #[clap(long, value_name = "LY")]
+        max_cost: Option<f64>,
+        /// Only systems at most this many hops away, gates included
+        #[clap(long, value_name = "N")]
+        max_hops: Option<usize>,
+        /// Only systems reachable by jumping at most this far in total,
+        /// eg as far as a tank of fuel goes
+        #[clap(long, value_name = "LY")]
+        max_jump_ly: Option<f64>,
+        #[command(flatten)]
+        avoid: AvoidArgs,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+    /// Find the best places for a fleet to meet
+    Rally {
+        /// Where each member is, as NAME, NAME=LY to give them their own
+        /// jump range, or NAME=FILE for a JSON file describing their ship
+        #[clap(required = true, num_args = 1..)]
+        members: Vec<String>,
+        /// Jump range for members who don't have their own
+        #[clap(short, long, default_value = "100.0")]
+        jump_distance: f64,
+        #[clap(short, long, default_value = "fuel")]
+        optimize: eftb::calc::path::PathOptimize,
+        #[clap(short, long)]
+        use_smart_gates: bool,
+        #[clap(long, default_value = "max")]
+        objective: RallyObjective,
+        /// Show this many of the best places
+        #[clap(long, default_value = "5")]
+        count: usize,
+        #[command(flatten)]
+        avoid: AvoidArgs,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+    /// Find the systems of some kind which are cheapest to get to
+    Nearest {
+        start_name: String,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[clap(short, long, default_value = "100.0")]
+        jump_distance: f64,
+        #[clap(short, long, default_value = "fuel")]
+        optimize: eftb::calc::path::PathOptimize,
+        #[clap(short, long)]
+        use_smart_gates: bool,
+        /// Show this many of the nearest
+        #[clap(long, default_value = "1")]
+        count: usize,
+        #[command(flatten)]
+        avoid: AvoidArgs,
+        #[command(flatten)]
+        budget: BudgetArgs,
     },
     /// Find the exits from a given point
     Exits {
@@ -50,20 +368,152 @@ enum Commands {
     },
     /// Constellation
     Constellation { name: String },
+    /// Show the systems in a region, given a region ID or a system in it
+    Region { region: String },
+    /// Check the starmap and smart gate data for problems
+    Validate {
+        /// Output the findings as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Show what changed between an older data directory and the current data
+    Diff {
+        old_data_dir: PathBuf,
+        /// Output the changes as JSON
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+/// Split a rally member into a system name and how far they can go
+fn parse_member(member: &str, jump_distance: Meters) -> eftb::Result<(&str, MemberRange)> {
+    let Some((name, range)) = member.split_once('=') else {
+        return Ok((member, MemberRange::Jump(jump_distance)));
+    };
+    if let Ok(ly) = range.parse::<f64>() {
+        if !ly.is_finite() || ly < 0.0 {
+            return Err(eftb::Error::InvalidParameter {
+                name: "member",
+                message: format!("{} is not a valid jump distance", ly),
+            });
+        }
+        return Ok((name, MemberRange::Jump(Meters::from_light_years(ly))));
+    }
+    let json = std::fs::read_to_string(range).map_err(|e| eftb::Error::io(range, e))?;
+    let ship: ShipProfile =
+        serde_json::from_str(&json).map_err(|e| eftb::Error::parse(range, e))?;
+    // The fleet's longest range decides which jumps get built, so a ship
+    // with no mass mustn't get that far
+    ship.validate()?;
+    Ok((name, MemberRange::Ship(ship)))
+}
+
+/// `validate` found errors in the data, which it has already listed
+#[derive(Debug)]
+struct ValidationFailed;
+
+impl std::fmt::Display for ValidationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Validation found errors")
+    }
 }
 
-fn main() -> anyhow::Result<()> {
+impl std::error::Error for ValidationFailed {}
+
+/// Library errors get their own exit codes, so that scripts can tell
+/// "you typed the name wrong" apart from "the data files are broken"
+fn exit_code(err: &anyhow::Error) -> u8 {
+    if err.is::<ValidationFailed>() {
+        return 7;
+    }
+    match err.downcast_ref::<eftb::Error>() {
+        Some(eftb::Error::InvalidParameter { .. }) => 2,
+        Some(eftb::Error::UnknownStar { .. }) => 3,
+        Some(eftb::Error::Io { .. } | eftb::Error::Snapshot { .. }) => 4,
+        Some(eftb::Error::Parse { .. }) => 5,
+        Some(eftb::Error::Timeout) => 6,
+        None => 1,
+    }
+}
+
+/// What a search did and where the time went, for `--explain`
+fn print_stats(stats: &SearchStats) {
+    println!(
+        "Searched with {:?}: expanded {} stars, pushed {}, at most {} open at once",
+        stats.algorithm, stats.expanded, stats.pushed, stats.peak_open
+    );
+    println!(
+        "Estimated {:.3} from the start, finished at g = {:.3}, h = {:.3}",
+        stats.start_h, stats.final_g, stats.final_h
+    );
+    println!(
+        "Took {:.3}s to set up, {:.3}s to search, {:.3}s to build the path",
+        stats.setup_time.as_secs_f64(),
+        stats.search_time.as_secs_f64(),
+        stats.path_time.as_secs_f64()
+    );
+}
+
+/// One line per hop of `path`, starting from `start_idx`
+fn print_path(
+    universe: &data::Universe,
+    start_idx: data::StarIdx,
+    path: &[data::Connection],
+    indent: &str,
+) {
+    let mut last_idx = start_idx;
+    for conn in path {
+        println!(
+            "{}{} -> {} ({:?}, {} ly)",
+            indent,
+            universe.stars[last_idx].name,
+            universe.stars[conn.target].name,
+            conn.conn_type,
+            conn.distance.to_light_years() as i32
+        );
+        last_idx = conn.target;
+    }
+}
+
+fn main() -> ExitCode {
     use env_logger::Env;
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let cli = Cli::parse();
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let source = cli.source();
 
     match &cli.command {
-        Some(Commands::Build { max_jump_distance }) => {
+        Some(Commands::Build {
+            max_jump_distance,
+            hierarchy_jump_distances,
+            hierarchy_optimize,
+            hierarchy_smart_gates,
+        }) => {
             info!("Building star map");
             let now = Instant::now();
             let max_jump_dist: Meters = Meters::from_light_years(*max_jump_distance);
-            data::Universe::build(max_jump_dist)?;
+            let hierarchies: Vec<HierarchyKey> = hierarchy_jump_distances
+                .iter()
+                .map(|&jump_distance| HierarchyKey {
+                    jump_distance: Meters::from_light_years(jump_distance),
+                    optimize: *hierarchy_optimize,
+                    use_smart_gates: *hierarchy_smart_gates,
+                })
+                .collect();
+            data::Universe::build_snapshot(
+                &source,
+                data::JumpEdges::Precomputed(max_jump_dist),
+                &hierarchies,
+            )?;
             info!("Built in {:.2} seconds", now.elapsed().as_secs_f64());
         }
         Some(Commands::Dist {
@@ -71,7 +521,7 @@ fn main() -> anyhow::Result<()> {
             end_name,
         }) => {
             info!("Loading star map");
-            let universe = data::Universe::build(Meters::new(0.0))?;
+            let universe = data::Universe::build(&source, data::JumpEdges::OnDemand)?;
             info!("Loaded star map");
 
             let start = universe.star_by_name(start_name)?;
@@ -90,57 +540,548 @@ fn main() -> anyhow::Result<()> {
             jump_distance,
             optimize,
             use_smart_gates,
+            avoid,
+            alternatives,
+            pareto,
+            algorithm,
+            budget,
+            explain,
         }) => {
             info!("Loading star map");
             let now = Instant::now();
             let jump_distance: Meters = Meters::from_light_years(*jump_distance);
-            let universe = data::Universe::build(jump_distance)?;
+            let universe =
+                data::Universe::build(&source, data::JumpEdges::Precomputed(jump_distance))?;
             let start = universe.star_by_name(start_name)?;
             let end = universe.star_by_name(end_name)?;
+            let constraints = avoid.constraints(&universe, *use_smart_gates)?;
             info!("Loaded star map in {:.3}", now.elapsed().as_secs_f64());
 
+            if let Some(count) = alternatives {
+                info!("Finding {} routes", count);
+                let routes = eftb::calc::alternatives::calc_alternatives(
+                    &universe,
+                    start,
+                    end,
+                    jump_distance,
+                    *optimize,
+                    &constraints,
+                    *count,
+                    &budget.budget()?,
+                )?;
+                match routes {
+                    AlternativesResult::Found(routes) => {
+                        let best_hops = routes[0].path.len();
+                        for (n, route) in routes.iter().enumerate() {
+                            let extra = match optimize {
+                                PathOptimize::Hops => {
+                                    format!("+{} hops", route.path.len() - best_hops)
+                                }
+                                PathOptimize::Fuel | PathOptimize::Distance => {
+                                    format!("+{:.1} ly", route.extra_cost)
+                                }
+                            };
+                            println!(
+                                "Route {} from {} to {} ({} ly, {} hops, {}):",
+                                n + 1,
+                                start.name,
+                                end.name,
+                                route.distance().to_light_years() as i32,
+                                route.path.len(),
+                                extra
+                            );
+                            print_path(
+                                &universe,
+                                universe.star_id_to_idx[&start.id],
+                                &route.path,
+                                "  ",
+                            );
+                        }
+                    }
+                    AlternativesResult::NotFound => {
+                        warn!("No path found");
+                    }
+                    AlternativesResult::Timeout => {
+                        return Err(eftb::Error::Timeout.into());
+                    }
+                }
+                return Ok(());
+            }
+
+            if *pareto {
+                info!("Finding the fuel / hops trade-offs");
+                let routes = eftb::calc::weighted::calc_pareto_front(
+                    &universe,
+                    start,
+                    end,
+                    jump_distance,
+                    &constraints,
+                    &budget.budget()?,
+                );
+                let routes = match routes {
+                    ParetoResult::Found(routes) => routes,
+                    ParetoResult::NotFound => {
+                        warn!("No path found");
+                        return Ok(());
+                    }
+                    ParetoResult::Timeout(routes) if routes.is_empty() => {
+                        return Err(eftb::Error::Timeout.into());
+                    }
+                    ParetoResult::Timeout(routes) => {
+                        warn!("Ran out of time, there may be routes with fewer hops");
+                        routes
+                    }
+                };
+                for route in &routes {
+                    println!(
+                        "Route from {} to {} ({:.1} ly of fuel, {} hops):",
+                        start.name, end.name, route.fuel, route.hops
+                    );
+                    print_path(
+                        &universe,
+                        universe.star_id_to_idx[&start.id],
+                        &route.path,
+                        "  ",
+                    );
+                }
+                return Ok(());
+            }
+
             info!("Finding path");
             let now = Instant::now();
-            let path = eftb::calc_path(
+            let (path, stats) = eftb::calc::path::calc_path_explained(
                 &universe,
                 start,
                 end,
                 jump_distance,
                 *optimize,
-                *use_smart_gates,
-                Some(30),
+                &constraints,
+                *algorithm,
+                &budget.budget()?,
             );
             info!("Found path in {:.3}", now.elapsed().as_secs_f64());
+            if *explain {
+                print_stats(&stats);
+            }
+            let start_idx = universe.star_id_to_idx[&start.id];
             match path {
                 eftb::calc::path::PathResult::Found(path) => {
                     println!("Path from {} to {}:", start_name, end_name);
-                    let mut last_idx = universe.star_id_to_idx[&start.id];
-                    for conn in path {
+                    print_path(&universe, start_idx, &path, "");
+                }
+                eftb::calc::path::PathResult::NotFound => {
+                    warn!("No path found");
+                }
+                eftb::calc::path::PathResult::Timeout(partial) => {
+                    if !partial.is_empty() {
                         println!(
-                            "{} -> {} ({:?}, {} ly)",
-                            universe.stars[last_idx].name,
-                            universe.stars[conn.target].name,
-                            conn.conn_type,
-                            conn.distance.to_light_years() as i32
+                            "Gave up on the way from {} to {}, closest approach:",
+                            start_name, end_name
+                        );
+                        print_path(&universe, start_idx, &partial, "");
+                    }
+                    return Err(eftb::Error::Timeout.into());
+                }
+            }
+        }
+        Some(Commands::Route {
+            waypoints,
+            jump_distance,
+            optimize,
+            use_smart_gates,
+            optimize_order,
+            fixed_end,
+            avoid,
+            budget,
+        }) => {
+            info!("Loading star map");
+            let jump_distance: Meters = Meters::from_light_years(*jump_distance);
+            let universe =
+                data::Universe::build(&source, data::JumpEdges::Precomputed(jump_distance))?;
+            let waypoints = waypoints
+                .iter()
+                .map(|name| universe.star_by_name(name))
+                .collect::<eftb::Result<Vec<_>>>()?;
+            let constraints = avoid.constraints(&universe, *use_smart_gates)?;
+            info!("Loaded star map");
+
+            let order = match optimize_order {
+                true => WaypointOrder::Optimize {
+                    fixed_end: *fixed_end,
+                },
+                false => WaypointOrder::AsGiven,
+            };
+            info!("Finding route");
+            let route = eftb::calc::route::calc_route(
+                &universe,
+                &waypoints,
+                jump_distance,
+                *optimize,
+                &constraints,
+                order,
+                &budget.budget()?,
+            )?;
+            match route {
+                RouteResult::Found(route) => {
+                    for leg in &route.legs {
+                        println!(
+                            "{} to {} ({} ly, {} hops):",
+                            universe.stars[leg.from].name,
+                            universe.stars[leg.to].name,
+                            leg.distance.to_light_years() as i32,
+                            leg.path.len()
                         );
-                        last_idx = conn.target;
+                        print_path(&universe, leg.from, &leg.path, "  ");
                     }
+                    println!(
+                        "Total: {} ly, {} hops",
+                        route.distance().to_light_years() as i32,
+                        route.path().len()
+                    );
+                }
+                RouteResult::NotFound { from, to } => {
+                    warn!(
+                        "No path found from {} to {}",
+                        universe.stars[from].name, universe.stars[to].name
+                    );
+                }
+                RouteResult::Timeout => {
+                    return Err(eftb::Error::Timeout.into());
+                }
+            }
+        }
+        Some(Commands::WeightedPath {
+            start_name,
+            end_name,
+            jump_distance,
+            weights,
+            use_smart_gates,
+            avoid,
+            budget,
+        }) => {
+            let weights = weights.weights()?;
+            info!("Loading star map");
+            let jump_distance: Meters = Meters::from_light_years(*jump_distance);
+            let universe =
+                data::Universe::build(&source, data::JumpEdges::Precomputed(jump_distance))?;
+            let start = universe.star_by_name(start_name)?;
+            let end = universe.star_by_name(end_name)?;
+            let constraints = avoid.constraints(&universe, *use_smart_gates)?;
+            info!("Loaded star map");
+
+            info!("Finding path");
+            let path = eftb::calc::weighted::calc_weighted_path(
+                &universe,
+                start,
+                end,
+                jump_distance,
+                &weights,
+                &constraints,
+                &budget.budget()?,
+            );
+            match path {
+                eftb::calc::path::PathResult::Found(path) => {
+                    let cost: f64 = path.iter().map(|c| weights.cost(c)).sum();
+                    println!(
+                        "Path from {} to {} (cost {:.1}):",
+                        start.name, end.name, cost
+                    );
+                    print_path(&universe, universe.star_id_to_idx[&start.id], &path, "");
                 }
                 eftb::calc::path::PathResult::NotFound => {
                     warn!("No path found");
                 }
-                eftb::calc::path::PathResult::Timeout => {
-                    warn!("Path search timed out");
+                eftb::calc::path::PathResult::Timeout(_) => {
+                    return Err(eftb::Error::Timeout.into());
+                }
+            }
+        }
+        Some(Commands::ShipPath {
+            start_name,
+            end_name,
+            ship,
+            optimize,
+            use_smart_gates,
+            avoid,
+            budget,
+        }) => {
+            // The range is only meaningful for a sensible ship, and a huge
+            // one would precompute jumps between every pair of stars
+            ship.validate()?;
+            info!("Loading star map");
+            let range = ship.range(ship.current_fuel);
+            let universe = data::Universe::build(&source, data::JumpEdges::Precomputed(range))?;
+            let start = universe.star_by_name(start_name)?;
+            let end = universe.star_by_name(end_name)?;
+            let constraints = avoid.constraints(&universe, *use_smart_gates)?;
+            info!("Loaded star map");
+
+            info!("Finding path");
+            let path = eftb::calc::ship::calc_ship_path(
+                &universe,
+                start,
+                end,
+                ship,
+                *optimize,
+                &constraints,
+                &budget.budget()?,
+            )?;
+            match path {
+                eftb::calc::ship::ShipPathResult::Found(steps) => {
+                    println!("Path from {} to {}:", start.name, end.name);
+                    let mut last_idx = universe.star_id_to_idx[&start.id];
+                    for step in steps {
+                        println!(
+                            "{} -> {} ({:?}, {} ly, {:.1} fuel used, {:.1} left)",
+                            universe.stars[last_idx].name,
+                            universe.stars[step.conn.target].name,
+                            step.conn.conn_type,
+                            step.conn.distance.to_light_years() as i32,
+                            step.fuel_used,
+                            step.fuel_left
+                        );
+                        last_idx = step.conn.target;
+                    }
+                }
+                eftb::calc::ship::ShipPathResult::NotFound => {
+                    warn!("No path found with the fuel available");
+                }
+                eftb::calc::ship::ShipPathResult::Timeout => {
+                    return Err(eftb::Error::Timeout.into());
+                }
+            }
+        }
+        Some(Commands::Reach {
+            start_name,
+            jump_distance,
+            optimize,
+            use_smart_gates,
+            max_cost,
+            max_hops,
+            max_jump_ly,
+            avoid,
+            budget,
+        }) => {
+            let limits = ReachLimits {
+                max_cost: *max_cost,
+                max_hops: *max_hops,
+                max_jump_ly: *max_jump_ly,
+            };
+            limits.validate()?;
+            info!("Loading star map");
+            let jump_distance: Meters = Meters::from_light_years(*jump_distance);
+            let universe =
+                data::Universe::build(&source, data::JumpEdges::Precomputed(jump_distance))?;
+            let start = universe.star_by_name(start_name)?;
+            let constraints = avoid.constraints(&universe, *use_smart_gates)?;
+            info!("Loaded star map");
+
+            info!("Finding reachable systems");
+            let now = Instant::now();
+            let result = eftb::calc::reach::calc_reach(
+                &universe,
+                start,
+                jump_distance,
+                optimize,
+                &constraints,
+                &limits,
+                &budget.budget()?,
+            );
+            info!(
+                "Found reachable systems in {:.3}",
+                now.elapsed().as_secs_f64()
+            );
+            let (reach, complete) = match result {
+                ReachResult::Found(reach) => (reach, true),
+                ReachResult::Timeout(reach) => (reach, false),
+            };
+            match complete {
+                true => println!("{} systems reachable from {}:", reach.len(), start.name),
+                false => println!(
+                    "Gave up after reaching {} systems from {}:",
+                    reach.len(),
+                    start.name
+                ),
+            }
+            for r in reach.stars().iter().skip(1) {
+                let cost = match optimize {
+                    PathOptimize::Hops => format!("{} hops", r.hops),
+                    PathOptimize::Fuel | PathOptimize::Distance => {
+                        format!("{:.1} ly, {} hops", r.cost, r.hops)
+                    }
+                };
+                // With hops or jumps limited, the way here can differ from
+                // the way to the star before, so give the whole route
+                let route: Vec<&str> = std::iter::once(start.name.as_str())
+                    .chain(
+                        reach
+                            .path_to(r.star_idx)
+                            .unwrap_or_default()
+                            .iter()
+                            .map(|c| universe.stars[c.target].name.as_str()),
+                    )
+                    .collect();
+                println!(
+                    "{} ({}): {}",
+                    universe.stars[r.star_idx].name,
+                    cost,
+                    route.join(" -> ")
+                );
+            }
+            if !complete {
+                return Err(eftb::Error::Timeout.into());
+            }
+        }
+        Some(Commands::Rally {
+            members,
+            jump_distance,
+            optimize,
+            use_smart_gates,
+            objective,
+            count,
+            avoid,
+            budget,
+        }) => {
+            let jump_distance = Meters::from_light_years(*jump_distance);
+            let members = members
+                .iter()
+                .map(|member| parse_member(member, jump_distance))
+                .collect::<eftb::Result<Vec<_>>>()?;
+            let max_jump = members
+                .iter()
+                .map(|(_, range)| range.max_jump())
+                .max()
+                .unwrap_or(jump_distance);
+
+            info!("Loading star map");
+            let universe = data::Universe::build(&source, data::JumpEdges::Precomputed(max_jump))?;
+            let fleet = members
+                .iter()
+                .map(|(name, range)| {
+                    Ok(FleetMember {
+                        start: universe.star_by_name(&name.to_string())?,
+                        range: *range,
+                    })
+                })
+                .collect::<eftb::Result<Vec<_>>>()?;
+            let constraints = avoid.constraints(&universe, *use_smart_gates)?;
+            info!("Loaded star map");
+
+            info!("Finding rally points");
+            let result = eftb::calc::rally::calc_rally(
+                &universe,
+                &fleet,
+                optimize,
+                &constraints,
+                *objective,
+                *count,
+                &budget.budget()?,
+            )?;
+            let points = match result {
+                RallyResult::Found(points) => points,
+                RallyResult::NotFound => {
+                    warn!("Nowhere that everyone can reach");
+                    return Ok(());
+                }
+                RallyResult::Timeout => return Err(eftb::Error::Timeout.into()),
+            };
+            for (n, point) in points.iter().enumerate() {
+                println!("{}. {}", n + 1, universe.stars[point.star_idx].name);
+                for (member, (cost, path)) in fleet.iter().zip(point.costs.iter().zip(&point.paths))
+                {
+                    let distance: f64 = path.iter().map(|c| c.distance.to_light_years()).sum();
+                    let cost = match optimize {
+                        PathOptimize::Hops => format!("{} hops", path.len()),
+                        PathOptimize::Fuel | PathOptimize::Distance => {
+                            format!("{:.1} ly cost, {} hops", cost, path.len())
+                        }
+                    };
+                    println!(
+                        "  from {} ({}, {} ly travelled)",
+                        member.start.name, cost, distance as i32
+                    );
+                    print_path(
+                        &universe,
+                        universe.star_id_to_idx[&member.start.id],
+                        path,
+                        "    ",
+                    );
                 }
             }
         }
+        Some(Commands::Nearest {
+            start_name,
+            filter,
+            jump_distance,
+            optimize,
+            use_smart_gates,
+            count,
+            avoid,
+            budget,
+        }) => {
+            if *count == 0 {
+                return Err(eftb::Error::InvalidParameter {
+                    name: "count",
+                    message: "must be at least 1".to_string(),
+                }
+                .into());
+            }
+            info!("Loading star map");
+            let jump_distance: Meters = Meters::from_light_years(*jump_distance);
+            let universe =
+                data::Universe::build(&source, data::JumpEdges::Precomputed(jump_distance))?;
+            let start = universe.star_by_name(start_name)?;
+            let constraints = avoid.constraints(&universe, *use_smart_gates)?;
+            let filter = filter.filter(&universe)?;
+            info!("Loaded star map");
+
+            info!("Finding nearest systems");
+            let matches = filter.predicate(&universe, start);
+            let result = eftb::calc::nearest::calc_nearest(
+                &universe,
+                start,
+                jump_distance,
+                optimize,
+                &constraints,
+                &matches,
+                *count,
+                &budget.budget()?,
+            );
+            let (found, complete) = match result {
+                NearestResult::Found(found) => (found, true),
+                NearestResult::NotFound => {
+                    warn!("No matching system found");
+                    return Ok(());
+                }
+                NearestResult::Timeout(found) => (found, false),
+            };
+            for nearest in &found {
+                let cost = match optimize {
+                    PathOptimize::Hops => format!("{} hops", nearest.path.len()),
+                    PathOptimize::Fuel | PathOptimize::Distance => {
+                        format!("{:.1} ly cost, {} hops", nearest.cost, nearest.path.len())
+                    }
+                };
+                println!("{} ({}):", universe.stars[nearest.star_idx].name, cost);
+                print_path(
+                    &universe,
+                    universe.star_id_to_idx[&start.id],
+                    &nearest.path,
+                    "  ",
+                );
+            }
+            if !complete {
+                return Err(eftb::Error::Timeout.into());
+            }
+        }
         Some(Commands::Exits {
             start_name,
             jump_distance,
         }) => {
             info!("Loading star map");
             let jump_distance: Meters = Meters::from_light_years(*jump_distance);
-            let universe = data::Universe::build(jump_distance)?;
+            let universe =
+                data::Universe::build(&source, data::JumpEdges::Precomputed(jump_distance))?;
             info!("Loaded star map");
 
             let start = universe.star_by_name(start_name)?;
@@ -161,16 +1102,15 @@ fn main() -> anyhow::Result<()> {
             jump_distance,
         }) => {
             info!("Loading star map");
-            let max_jump_distance: Meters = if let Some(jd) = *jump_distance {
-                Meters::from_light_years(jd)
-            } else {
-                Meters::new(0.0)
-            };
-            let universe = data::Universe::build(max_jump_distance)?;
+            let universe = data::Universe::build(&source, data::JumpEdges::OnDemand)?;
             info!("Loaded star map");
 
             let star = universe.star_by_name(name)?;
             println!("{} ({}):", star.name, star.id);
+            println!("  Region: {}", star.region_id);
+            if let Some(constellation_id) = star.constellation_id {
+                println!("  Constellation: {}", constellation_id);
+            }
             println!("  Connections:");
             for conn in &star.connections {
                 if conn.conn_type != data::ConnType::Jump {
@@ -184,17 +1124,22 @@ fn main() -> anyhow::Result<()> {
             }
             if let Some(jump_distance) = jump_distance {
                 println!("  Nearby stars:");
-                for conn in &star.connections {
-                    let d = conn.distance.to_light_years();
-                    if conn.conn_type == data::ConnType::Jump && d < *jump_distance {
-                        println!("    {} ({} ly)", universe.stars[conn.target].name, d as i32);
+                let star_idx = universe.star_id_to_idx[&star.id];
+                for conn in universe.connections(star_idx, Meters::from_light_years(*jump_distance))
+                {
+                    if conn.conn_type == data::ConnType::Jump {
+                        println!(
+                            "    {} ({} ly)",
+                            universe.stars[conn.target].name,
+                            conn.distance.to_light_years() as i32
+                        );
                     }
                 }
             }
         }
         Some(Commands::Constellation { name }) => {
             info!("Loading star map");
-            let universe = data::Universe::build(Meters::new(0.0))?;
+            let universe = data::Universe::build(&source, data::JumpEdges::OnDemand)?;
             info!("Loaded star map");
 
             let star = universe.star_by_name(name)?;
@@ -214,6 +1159,58 @@ fn main() -> anyhow::Result<()> {
                 }
             }
         }
+        Some(Commands::Region { region }) => {
+            info!("Loading star map");
+            let universe = data::Universe::build(&source, data::JumpEdges::OnDemand)?;
+            info!("Loaded star map");
+
+            let region_id = match region.parse::<data::RegionId>() {
+                Ok(id) => id,
+                Err(_) => universe.star_by_name(region)?.region_id,
+            };
+            let (Some((min, max)), Some(centroid)) = (
+                universe.region_bounds(region_id),
+                universe.region_centroid(region_id),
+            ) else {
+                anyhow::bail!("Region not found: {}", region_id);
+            };
+            let ly = |p: data::Point3D| p.map(|v| Meters::new(v).to_light_years() as i64);
+            println!("Region {}:", region_id);
+            println!("  Bounds: {:?} to {:?} ly", ly(min), ly(max));
+            println!("  Centroid: {:?} ly", ly(centroid));
+            println!("  Systems:");
+            for idx in universe.stars_in_region(region_id) {
+                println!("    {}", universe.stars[*idx].name);
+            }
+        }
+        Some(Commands::Validate { json }) => {
+            let (raw_star_data, raw_smart_gates) = source.read_raw()?;
+            let report = data::Universe::validate(&raw_star_data, &raw_smart_gates);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{}", report);
+            }
+            if report.has_errors() {
+                return Err(ValidationFailed.into());
+            }
+        }
+        Some(Commands::Diff { old_data_dir, json }) => {
+            let (old_star_data, old_smart_gates) =
+                UniverseSource::from_data_dir(old_data_dir).read_raw()?;
+            let (new_star_data, new_smart_gates) = source.read_raw()?;
+            let diff = eftb::diff::diff(
+                &old_star_data,
+                &old_smart_gates,
+                &new_star_data,
+                &new_smart_gates,
+            );
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                println!("{}", diff);
+            }
+        }
         None => {
             warn!("No command specified");
         }
@@ -221,3 +1218,84 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the CLI as if from the command line, with data files which
+    /// don't exist, so anything which tries to load the map fails
+    fn run_args(args: &[&str]) -> anyhow::Result<()> {
+        let data_dir = std::env::temp_dir().join(format!("eftb-cli-{}", std::process::id()));
+        let data_dir = data_dir.to_str().expect("Temp dir should be UTF-8");
+        let cli = Cli::try_parse_from(["cli", "--data-dir", data_dir].iter().chain(args))
+            .expect("Arguments should parse");
+        run(cli)
+    }
+
+    #[test]
+    fn test_ship_path_checks_ship_first() {
+        let err = run_args(&[
+            "ship-path",
+            "SS1000",
+            "SS1003",
+            "--hull-mass",
+            "0",
+            "--tank-size",
+            "100",
+            "--current-fuel",
+            "100",
+            "--fuel-efficiency",
+            "0.4",
+        ])
+        .expect_err("A ship with no mass should be rejected");
+        assert_eq!(exit_code(&err), 2, "{}", err);
+    }
+
+    #[test]
+    fn test_rally_checks_ships_first() {
+        let ship = std::env::temp_dir().join(format!("eftb-ship-{}.json", std::process::id()));
+        std::fs::write(
+            &ship,
+            r#"{"hull_mass": 0, "fittings_mass": 0, "cargo_mass": 0, "tank_size": 100,
+                "current_fuel": 100, "fuel_efficiency": 0.4}"#,
+        )
+        .expect("Failed to write ship");
+        let member = format!("SS1003={}", ship.display());
+        let result = run_args(&["rally", "SS1000", &member]);
+        std::fs::remove_file(&ship).expect("Failed to remove ship");
+        let err = result.expect_err("A ship with no mass should be rejected");
+        assert_eq!(exit_code(&err), 2, "{}", err);
+    }
+
+    #[test]
+    fn test_validate_errors_exit_code() {
+        let smartgates =
+            std::env::temp_dir().join(format!("eftb-gates-{}.json", std::process::id()));
+        let smartgates_arg = smartgates.to_str().expect("Temp dir should be UTF-8");
+        let validate = || {
+            run_args(&[
+                "--starmap",
+                "data_fixtures/starmap.json",
+                "--smartgates",
+                smartgates_arg,
+                "validate",
+            ])
+        };
+
+        // A duplicate item ID is only a warning
+        std::fs::copy("data_fixtures/smartgates.json", &smartgates).expect("Failed to copy gates");
+        assert!(validate().is_ok());
+
+        // A gate to nowhere is an error
+        std::fs::write(
+            &smartgates,
+            r#"[{"id": "x", "itemId": 1, "name": "Gate", "from": 1000, "to": 9999}]"#,
+        )
+        .expect("Failed to write gates");
+        let result = validate();
+        std::fs::remove_file(&smartgates).expect("Failed to remove gates");
+        let err = result.expect_err("A dangling gate should fail validation");
+        assert_eq!(exit_code(&err), 7, "{}", err);
+    }
+}
@@ -1,24 +1,35 @@
 use std::collections::HashMap;
 
-use indicatif::ProgressIterator;
+use indicatif::ParallelProgressIterator;
 use log::{info, warn};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use crate::calc::hierarchy::{ContractionHierarchy, HierarchyKey};
+use crate::calc::path::{PathConstraints, PathOptimize};
+use crate::error::{Error, Result};
+use crate::names::NameIndex;
 use crate::raw;
+use crate::snapshot::{self, SnapshotHeader};
+use crate::source::UniverseSource;
+use crate::spatial::{self, SpatialIndex};
 use crate::units::Meters;
+use crate::validate::{self, ValidationReport};
 
-pub type ConnectionId = u32;
+pub type ConnectionId = u64;
 pub type SolarSystemId = u32;
+pub type RegionId = u32;
+pub type ConstellationId = u32;
 pub type StarIdx = usize;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum ConnType {
     NpcGate,
     SmartGate,
     Jump,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Connection {
     pub id: ConnectionId,
     pub conn_type: ConnType,
@@ -51,48 +62,40 @@ impl Ord for Connection {
 
 pub type Point3D = [f64; 3];
 
-#[derive(Clone, Default)]
+/// How jump connections between stars are stored
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JumpEdges {
+    /// Every jump shorter than this distance is stored in `Star::connections`
+    Precomputed(Meters),
+    /// `Star::connections` only holds gates, and jumps are looked up from
+    /// the spatial index when they are needed
+    OnDemand,
+}
+impl JumpEdges {
+    /// Can a universe built like this answer every query that one built
+    /// like `other` could?
+    pub fn covers(&self, other: &JumpEdges) -> bool {
+        match (self, other) {
+            (JumpEdges::OnDemand, _) => true,
+            (JumpEdges::Precomputed(a), JumpEdges::Precomputed(b)) => a >= b,
+            (JumpEdges::Precomputed(_), JumpEdges::OnDemand) => false,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Star {
     pub name: String,
     pub id: SolarSystemId,
+    pub region_id: RegionId,
+    pub constellation_id: Option<ConstellationId>,
     pub loc: Point3D,
     pub connections: Vec<Connection>,
 }
 
 impl Star {
     pub fn distance(&self, other: &Star) -> Meters {
-        Meters::new(
-            ((self.loc[0] - other.loc[0]).powi(2)
-                + (self.loc[1] - other.loc[1]).powi(2)
-                + (self.loc[2] - other.loc[2]).powi(2))
-            .sqrt(),
-        )
-    }
-
-    pub fn bucket(&self, bucket_size: Meters) -> String {
-        format!(
-            "{}-{}-{}",
-            (self.loc[0] / bucket_size.get()).floor() as i64,
-            (self.loc[1] / bucket_size.get()).floor() as i64,
-            (self.loc[2] / bucket_size.get()).floor() as i64,
-        )
-    }
-
-    pub fn nearby_buckets(&self, bucket_size: Meters) -> Vec<String> {
-        let mut buckets = Vec::new();
-        let base_x = (self.loc[0] / bucket_size.get()).floor() as i64;
-        let base_y = (self.loc[1] / bucket_size.get()).floor() as i64;
-        let base_z = (self.loc[2] / bucket_size.get()).floor() as i64;
-
-        for dx in -1..=1 {
-            for dy in -1..=1 {
-                for dz in -1..=1 {
-                    buckets.push(format!("{}-{}-{}", base_x + dx, base_y + dy, base_z + dz));
-                }
-            }
-        }
-
-        buckets
+        spatial::distance(&self.loc, &other.loc)
     }
 }
 impl std::fmt::Debug for Star {
@@ -112,53 +115,173 @@ impl std::hash::Hash for Star {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Universe {
     pub stars: Vec<Star>,
     pub star_id_to_idx: HashMap<SolarSystemId, StarIdx>,
     pub star_name_to_idx: HashMap<String, StarIdx>,
+    /// Normalized names for forgiving lookups
+    pub names: NameIndex,
+    pub region_to_stars: HashMap<RegionId, Vec<StarIdx>>,
+    pub spatial: SpatialIndex,
+    /// Stars at either end of an NPC gate
+    pub npc_gate_stars: SpatialIndex,
+    /// Stars at either end of a smart gate
+    pub smart_gate_stars: SpatialIndex,
+    /// The in-game item ID of each smart gate connection
+    pub smart_gate_item_ids: HashMap<ConnectionId, u32>,
+    pub jump_edges: JumpEdges,
+    /// Gates into each star, as (star the gate is in, gate)
+    pub incoming_gates: Vec<Vec<(StarIdx, Connection)>>,
+    /// Precomputed speed-ups for some common path search settings
+    pub hierarchies: Vec<ContractionHierarchy>,
+    /// Jumps get IDs above this, so they never clash with gates
+    jump_id_base: ConnectionId,
+}
+
+/// The ID of the jump from one star to another
+fn jump_id(base: ConnectionId, stars: usize, from: StarIdx, to: StarIdx) -> ConnectionId {
+    base + from as ConnectionId * stars as ConnectionId + to as ConnectionId
 }
 impl Universe {
-    pub fn build(max_jump_dist: Meters) -> anyhow::Result<Universe> {
+    /// Load the universe from the snapshot if it is still up to date,
+    /// otherwise build it from the raw data
+    pub fn build(source: &UniverseSource, jump_edges: JumpEdges) -> Result<Universe> {
+        Universe::build_with_hierarchies(source, jump_edges, &[])
+    }
+
+    /// Like `build()`, but also make sure the universe has a hierarchy
+    /// for each of `hierarchies`, building any that the snapshot lacks.
+    /// Hierarchies are slow to build, so after building them we save a
+    /// snapshot with them in for next time.
+    pub fn build_with_hierarchies(
+        source: &UniverseSource,
+        jump_edges: JumpEdges,
+        hierarchies: &[HierarchyKey],
+    ) -> Result<Universe> {
         let t = std::time::Instant::now();
-        let raw_star_data = raw::RawStarMap::from_file("data/starmap.json")?;
-        let raw_smart_gates: Vec<raw::RawSmartGate> =
-            serde_json::from_str(&std::fs::read_to_string("data/smartgates.json")?)?;
+        let (starmap, smartgates) = source.read()?;
+        let header = SnapshotHeader::new(
+            jump_edges,
+            &[&starmap, smartgates.as_deref().unwrap_or_default()],
+        )
+        .with_hierarchies(hierarchies);
+        match snapshot::load(&source.snapshot, &header) {
+            Ok(Some(universe)) => {
+                info!("Loaded snapshot in {:.2}s", t.elapsed().as_secs_f64());
+                return Ok(universe);
+            }
+            Ok(None) => info!("Snapshot is out of date, rebuilding"),
+            Err(e) => info!("No usable snapshot ({}), rebuilding", e),
+        }
+        let mut universe =
+            Universe::build_from_json(source, &starmap, smartgates.as_deref(), jump_edges)?;
+        for key in hierarchies {
+            universe.add_hierarchy(*key);
+        }
+        if !hierarchies.is_empty() {
+            // The snapshot is only a cache, so not being able to write it
+            // shouldn't stop us from using the universe we just built
+            if let Err(e) = universe.save_snapshot(&source.snapshot, &header) {
+                warn!("Failed to save snapshot: {}", e);
+            }
+        }
+        Ok(universe)
+    }
+
+    /// Build the universe from the raw data, along with any hierarchies,
+    /// and save a snapshot of it for future calls to `build()`
+    pub fn build_snapshot(
+        source: &UniverseSource,
+        jump_edges: JumpEdges,
+        hierarchies: &[HierarchyKey],
+    ) -> Result<Universe> {
+        let (starmap, smartgates) = source.read()?;
+        let header = SnapshotHeader::new(
+            jump_edges,
+            &[&starmap, smartgates.as_deref().unwrap_or_default()],
+        )
+        .with_hierarchies(hierarchies);
+        let mut universe =
+            Universe::build_from_json(source, &starmap, smartgates.as_deref(), jump_edges)?;
+        for key in hierarchies {
+            universe.add_hierarchy(*key);
+        }
+        universe.save_snapshot(&source.snapshot, &header)?;
+        Ok(universe)
+    }
+
+    fn save_snapshot(&self, path: &std::path::Path, header: &SnapshotHeader) -> Result<()> {
+        let t = std::time::Instant::now();
+        snapshot::save(path, header, self)?;
+        info!(
+            "Saved snapshot to {} in {:.2}s",
+            path.display(),
+            t.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    fn build_from_json(
+        source: &UniverseSource,
+        starmap: &[u8],
+        smartgates: Option<&[u8]>,
+        jump_edges: JumpEdges,
+    ) -> Result<Universe> {
+        let t = std::time::Instant::now();
+        let (raw_star_data, raw_smart_gates) = raw::parse(source, starmap, smartgates)?;
         info!("Loaded raw data in {:.2}s", t.elapsed().as_secs_f64());
-        Universe::build_from_raw(raw_star_data, raw_smart_gates, max_jump_dist)
+        Universe::build_from_raw(raw_star_data, raw_smart_gates, jump_edges)
+    }
+
+    /// Check the raw data for problems which `build_from_raw` would
+    /// otherwise skip over or silently get wrong
+    pub fn validate(
+        raw_star_data: &raw::RawStarMap,
+        raw_smart_gates: &[raw::RawSmartGate],
+    ) -> ValidationReport {
+        validate::validate(raw_star_data, raw_smart_gates)
     }
 
     pub fn build_from_raw(
         raw_star_data: raw::RawStarMap,
         raw_smart_gates: Vec<raw::RawSmartGate>,
-        max_jump_dist: Meters,
-    ) -> anyhow::Result<Universe> {
+        jump_edges: JumpEdges,
+    ) -> Result<Universe> {
         let t = std::time::Instant::now();
         let n = raw_star_data.solar_systems.len();
         let mut star_id_to_idx: HashMap<SolarSystemId, StarIdx> = HashMap::with_capacity(n);
         let mut stars: Vec<Star> = Vec::with_capacity(n);
         let mut star_name_to_idx: HashMap<String, StarIdx> = HashMap::with_capacity(n);
-        let mut star_bucket_to_idx: HashMap<String, Vec<StarIdx>> = HashMap::new();
+        let mut region_to_stars: HashMap<RegionId, Vec<StarIdx>> = HashMap::new();
 
         for (idx, raw_star) in raw_star_data.solar_systems.iter().enumerate() {
             let star = Star {
-                name: raw_star.name.clone(),
+                name: raw_star.display_name(),
                 id: raw_star.solar_system_id,
+                region_id: raw_star.region_id,
+                constellation_id: raw_star.constellation_id,
                 loc: raw_star.center,
                 connections: Vec::new(),
             };
-            star_bucket_to_idx
-                .entry(star.bucket(max_jump_dist))
-                .or_default()
-                .push(idx);
             stars.push(star);
             star_id_to_idx.insert(raw_star.solar_system_id, idx);
-            star_name_to_idx.insert(raw_star.name.clone(), idx);
+            star_name_to_idx.insert(raw_star.display_name(), idx);
+            region_to_stars
+                .entry(raw_star.region_id)
+                .or_default()
+                .push(idx);
         }
+        let spatial = SpatialIndex::build(stars.iter().enumerate().map(|(idx, s)| (s.loc, idx)));
+        let names = NameIndex::build(
+            stars
+                .iter()
+                .enumerate()
+                .map(|(idx, s)| (s.name.as_str(), idx)),
+        );
         info!(
-            "Built star map with {} stars in {} buckets in {:.2}s",
+            "Built star map with {} stars in {:.2}s",
             stars.len(),
-            star_bucket_to_idx.len(),
             t.elapsed().as_secs_f64()
         );
 
@@ -196,6 +319,7 @@ impl Universe {
         );
 
         let t = std::time::Instant::now();
+        let mut smart_gate_item_ids = HashMap::new();
         for gate in raw_smart_gates.iter() {
             let Some(to_star_idx) = star_id_to_idx.get(&gate.to).cloned() else {
                 warn!("Smart gate has unknown target {}", gate.to);
@@ -213,6 +337,7 @@ impl Universe {
                 distance,
                 target: to_star_idx,
             });
+            smart_gate_item_ids.insert(conn_count, gate.item_id);
             conn_count += 1;
         }
         info!(
@@ -220,33 +345,60 @@ impl Universe {
             t.elapsed().as_secs_f64()
         );
 
-        let t = std::time::Instant::now();
-        for from_star_idx in (0..n).progress() {
-            for nearby_bucket in stars[from_star_idx].nearby_buckets(max_jump_dist) {
-                if !star_bucket_to_idx.contains_key(&nearby_bucket) {
-                    continue;
-                }
-                for to_star_idx in star_bucket_to_idx[&nearby_bucket].iter() {
-                    if from_star_idx == *to_star_idx {
-                        continue;
-                    }
-                    let distance = stars[from_star_idx].distance(&stars[*to_star_idx]);
-                    if distance < max_jump_dist {
-                        stars[from_star_idx].connections.push(Connection {
-                            id: conn_count,
-                            conn_type: ConnType::Jump,
-                            distance,
-                            target: *to_star_idx,
-                        });
-                        conn_count += 1;
-                    }
+        // Only gates have been added so far, so this is cheap
+        let gate_stars = |conn_type: ConnType| {
+            let mut gate_stars: Vec<StarIdx> = stars
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, star)| {
+                    star.connections
+                        .iter()
+                        .filter(|c| c.conn_type == conn_type)
+                        .flat_map(move |c| [idx, c.target])
+                })
+                .collect();
+            gate_stars.sort_unstable();
+            gate_stars.dedup();
+            SpatialIndex::build(gate_stars.into_iter().map(|idx| (stars[idx].loc, idx)))
+        };
+        let npc_gate_stars = gate_stars(ConnType::NpcGate);
+        let smart_gate_stars = gate_stars(ConnType::SmartGate);
+
+        // Jumps are numbered by their (from, to) pair, so that a jump's ID
+        // can be worked out from either end
+        let jump_id_base = conn_count;
+
+        if let JumpEdges::Precomputed(max_jump_dist) = jump_edges {
+            let t = std::time::Instant::now();
+            let nearby: Vec<Vec<(StarIdx, Meters)>> = stars
+                .par_iter()
+                .enumerate()
+                .progress_count(n as u64)
+                .map(|(from_star_idx, star)| {
+                    spatial
+                        .within(&star.loc, max_jump_dist)
+                        .into_iter()
+                        .filter(|(to_star_idx, distance)| {
+                            *to_star_idx != from_star_idx && *distance <= max_jump_dist
+                        })
+                        .collect()
+                })
+                .collect();
+            for (from_star_idx, nearby) in nearby.into_iter().enumerate() {
+                for (to_star_idx, distance) in nearby {
+                    stars[from_star_idx].connections.push(Connection {
+                        id: jump_id(jump_id_base, n, from_star_idx, to_star_idx),
+                        conn_type: ConnType::Jump,
+                        distance,
+                        target: to_star_idx,
+                    });
                 }
             }
+            info!(
+                "Built connections from jumps in {:.2}s",
+                t.elapsed().as_secs_f64()
+            );
         }
-        info!(
-            "Built connections from jumps in {:.2}s",
-            t.elapsed().as_secs_f64()
-        );
 
         // sort gates first, and then jumps by distance - then when we
         // reach a jump that is too long we can stop searching
@@ -256,36 +408,268 @@ impl Universe {
         });
         info!(
             "Sorted {} connections in {:.2}s",
-            conn_count,
+            stars.iter().map(|s| s.connections.len()).sum::<usize>(),
             t.elapsed().as_secs_f64()
         );
 
+        // Gates can be one-way, so searching backwards needs to know
+        // which gates lead into each star
+        let mut incoming_gates: Vec<Vec<(StarIdx, Connection)>> = vec![Vec::new(); n];
+        for (from_star_idx, star) in stars.iter().enumerate() {
+            for conn in star.connections.iter() {
+                if conn.conn_type != ConnType::Jump {
+                    incoming_gates[conn.target].push((from_star_idx, conn.clone()));
+                }
+            }
+        }
+
         Ok(Universe {
             stars,
             star_id_to_idx,
             star_name_to_idx,
+            names,
+            region_to_stars,
+            spatial,
+            npc_gate_stars,
+            smart_gate_stars,
+            smart_gate_item_ids,
+            incoming_gates,
+            hierarchies: Vec::new(),
+            jump_edges,
+            jump_id_base,
         })
     }
 
-    pub fn star_by_name(&self, name: &String) -> anyhow::Result<&Star> {
-        let star_idx = self
-            .star_name_to_idx
-            .get(name)
-            .ok_or_else(|| anyhow::anyhow!("Star not found: {}", name))?;
-        Ok(&self.stars[*star_idx])
+    /// Build a contraction hierarchy for these settings, unless we already
+    /// have one
+    pub fn add_hierarchy(&mut self, key: HierarchyKey) {
+        if self.hierarchies.iter().any(|h| h.key == key) {
+            return;
+        }
+        let t = std::time::Instant::now();
+        let hierarchy = ContractionHierarchy::build(self, key);
+        info!(
+            "Built hierarchy for {} ly, {:?}, smart gates {} with {} shortcuts in {:.2}s",
+            key.jump_distance.to_light_years(),
+            key.optimize,
+            key.use_smart_gates,
+            hierarchy.shortcut_count(),
+            t.elapsed().as_secs_f64()
+        );
+        self.hierarchies.push(hierarchy);
+    }
+
+    /// A hierarchy which can answer a path search with these settings
+    pub fn hierarchy(
+        &self,
+        jump_distance: Meters,
+        optimize: PathOptimize,
+        constraints: &PathConstraints,
+    ) -> Option<&ContractionHierarchy> {
+        self.hierarchies
+            .iter()
+            .find(|h| h.key.matches(jump_distance, optimize, constraints))
+    }
+
+    /// All connections out of a star which are usable with the given jump
+    /// range - gates first, then jumps from shortest to longest.
+    ///
+    /// With `JumpEdges::Precomputed`, jumps longer than the precomputed
+    /// distance are never returned, no matter how big `jump_distance` is.
+    pub fn connections(&self, star_idx: StarIdx, jump_distance: Meters) -> Vec<Connection> {
+        let star = &self.stars[star_idx];
+        match self.jump_edges {
+            JumpEdges::Precomputed(_) => star
+                .connections
+                .iter()
+                // take gates and short jumps - stop searching after we
+                // find a long jump
+                .take_while(|c| c.conn_type != ConnType::Jump || c.distance <= jump_distance)
+                .cloned()
+                .collect(),
+            JumpEdges::OnDemand => {
+                let mut jumps: Vec<Connection> = self
+                    .spatial
+                    .within(&star.loc, jump_distance)
+                    .into_iter()
+                    .filter(|(to_star_idx, _)| *to_star_idx != star_idx)
+                    .map(|(to_star_idx, distance)| Connection {
+                        id: jump_id(self.jump_id_base, self.stars.len(), star_idx, to_star_idx),
+                        conn_type: ConnType::Jump,
+                        distance,
+                        target: to_star_idx,
+                    })
+                    .collect();
+                jumps.sort_unstable();
+                let mut connections = star.connections.clone();
+                connections.extend(jumps);
+                connections
+            }
+        }
+    }
+
+    /// All connections into a star which are usable with the given jump
+    /// range, as (star the connection starts from, connection). The
+    /// reverse of `connections`, for searching backwards from the end.
+    pub fn incoming(&self, star_idx: StarIdx, jump_distance: Meters) -> Vec<(StarIdx, Connection)> {
+        // Jumps go both ways, so the stars we can jump to are the stars
+        // we can jump from
+        let jumps = self
+            .connections(star_idx, jump_distance)
+            .into_iter()
+            .filter(|c| c.conn_type == ConnType::Jump)
+            .map(|c| {
+                let from = c.target;
+                (
+                    from,
+                    Connection {
+                        id: jump_id(self.jump_id_base, self.stars.len(), from, star_idx),
+                        target: star_idx,
+                        ..c
+                    },
+                )
+            });
+        self.incoming_gates[star_idx]
+            .iter()
+            .cloned()
+            .chain(jumps)
+            .collect()
+    }
+
+    /// All region IDs, in ascending order
+    pub fn regions(&self) -> Vec<RegionId> {
+        let mut regions: Vec<RegionId> = self.region_to_stars.keys().cloned().collect();
+        regions.sort_unstable();
+        regions
+    }
+
+    /// All stars in a region (empty if the region doesn't exist)
+    pub fn stars_in_region(&self, region_id: RegionId) -> &[StarIdx] {
+        self.region_to_stars
+            .get(&region_id)
+            .map(|stars| stars.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The smallest axis-aligned box containing every star in a region,
+    /// as (min, max) corners
+    pub fn region_bounds(&self, region_id: RegionId) -> Option<(Point3D, Point3D)> {
+        let stars = self.stars_in_region(region_id);
+        let first = self.stars[*stars.first()?].loc;
+        Some(
+            stars
+                .iter()
+                .fold((first, first), |(mut min, mut max), idx| {
+                    let loc = self.stars[*idx].loc;
+                    for axis in 0..3 {
+                        min[axis] = min[axis].min(loc[axis]);
+                        max[axis] = max[axis].max(loc[axis]);
+                    }
+                    (min, max)
+                }),
+        )
+    }
+
+    /// The average location of the stars in a region
+    pub fn region_centroid(&self, region_id: RegionId) -> Option<Point3D> {
+        let stars = self.stars_in_region(region_id);
+        if stars.is_empty() {
+            return None;
+        }
+        let mut sum = [0.0; 3];
+        for idx in stars {
+            for (axis, total) in sum.iter_mut().enumerate() {
+                *total += self.stars[*idx].loc[axis];
+            }
+        }
+        Some(sum.map(|total| total / stars.len() as f64))
+    }
+
+    /// All stars within `radius` of `point`, with their distance from it
+    pub fn stars_within(&self, point: &Point3D, radius: Meters) -> Vec<(StarIdx, Meters)> {
+        self.spatial.within(point, radius)
+    }
+
+    /// The `k` stars closest to `point`, nearest first
+    pub fn k_nearest(&self, point: &Point3D, k: usize) -> Vec<(StarIdx, Meters)> {
+        self.spatial.k_nearest(point, k)
+    }
+
+    /// How far `point` is from the nearest star with a gate, or `None` if
+    /// there are no gates at all
+    pub fn nearest_gate(&self, point: &Point3D, use_smart_gates: bool) -> Option<Meters> {
+        let npc = self.npc_gate_stars.k_nearest(point, 1).first().map(|n| n.1);
+        let smart = match use_smart_gates {
+            true => self
+                .smart_gate_stars
+                .k_nearest(point, 1)
+                .first()
+                .map(|n| n.1),
+            false => None,
+        };
+        npc.into_iter().chain(smart).min()
+    }
+
+    /// All stars inside the axis-aligned box between `min` and `max`
+    pub fn stars_in_box(&self, min: &Point3D, max: &Point3D) -> Vec<StarIdx> {
+        self.spatial.in_box(min, max)
+    }
+
+    /// Find a star by name. Exact matches win, otherwise we ignore case
+    /// and O/0 mix-ups as long as that only matches one star. If nothing
+    /// matches, the error lists the closest names.
+    pub fn star_by_name(&self, name: &String) -> Result<&Star> {
+        if let Some(star_idx) = self.star_name_to_idx.get(name) {
+            return Ok(&self.stars[*star_idx]);
+        }
+        if let [star_idx] = self.names.lookup(name)[..] {
+            return Ok(&self.stars[star_idx]);
+        }
+        Err(Error::UnknownStar {
+            name: name.clone(),
+            suggestions: self
+                .suggest_stars(name, 5)
+                .into_iter()
+                .map(|s| s.name.clone())
+                .collect(),
+        })
+    }
+
+    /// Up to `limit` stars whose names start with `prefix`, ignoring case
+    /// and O/0, in name order
+    pub fn stars_with_prefix(&self, prefix: &str, limit: usize) -> Vec<&Star> {
+        self.names
+            .prefix(prefix, limit)
+            .into_iter()
+            .map(|idx| &self.stars[idx])
+            .collect()
+    }
+
+    /// Up to `limit` stars with names close to `name`, closest first
+    pub fn suggest_stars(&self, name: &str, limit: usize) -> Vec<&Star> {
+        self.names
+            .suggest(name, limit)
+            .into_iter()
+            .map(|(idx, _)| &self.stars[idx])
+            .collect()
     }
 
     #[cfg(test)]
     pub fn tiny_test() -> Universe {
-        Universe::tiny_test_r().unwrap()
+        Universe::tiny_test_r(JumpEdges::Precomputed(Meters::new(50.0)))
+            .expect("Failed to load tiny test universe")
     }
 
     #[cfg(test)]
-    pub fn tiny_test_r() -> anyhow::Result<Universe> {
-        let raw_star_data = raw::RawStarMap::from_file("data_fixtures/starmap.json")?;
-        let raw_smart_gates: Vec<raw::RawSmartGate> =
-            serde_json::from_str(&std::fs::read_to_string("data_fixtures/smartgates.json")?)?;
-        Universe::build_from_raw(raw_star_data, raw_smart_gates, Meters::new(50.0))
+    pub fn tiny_test_on_demand() -> Universe {
+        Universe::tiny_test_r(JumpEdges::OnDemand).expect("Failed to load tiny test universe")
+    }
+
+    #[cfg(test)]
+    pub fn tiny_test_r(jump_edges: JumpEdges) -> Result<Universe> {
+        let (raw_star_data, raw_smart_gates) =
+            UniverseSource::from_data_dir("data_fixtures").read_raw()?;
+        Universe::build_from_raw(raw_star_data, raw_smart_gates, jump_edges)
     }
 }
 
@@ -311,6 +695,229 @@ mod tests {
         assert_eq!(universe.stars[3].connections.len(), 5);
     }
 
+    #[test]
+    fn test_regions() {
+        assert_eq!(
+            Universe::tiny_test().regions(),
+            vec![1000, 1001, 1002, 1003]
+        );
+
+        // Every star in the fixture has a region to itself, so move 1 in
+        // with 0 to give the region queries more to do
+        let (mut raw_star_data, raw_smart_gates) = UniverseSource::from_data_dir("data_fixtures")
+            .read_raw()
+            .expect("Failed to load fixtures");
+        raw_star_data.solar_systems[0].constellation_id = Some(2000);
+        raw_star_data.solar_systems[1].region_id = 1000;
+        raw_star_data.solar_systems[1].constellation_id = Some(2000);
+        let universe =
+            Universe::build_from_raw(raw_star_data, raw_smart_gates, JumpEdges::OnDemand)
+                .expect("Failed to build");
+
+        assert_eq!(universe.regions(), vec![1000, 1002, 1003]);
+        assert_eq!(universe.stars_in_region(1000), &[0, 1]);
+        assert_eq!(universe.stars_in_region(1003), &[3]);
+        assert!(universe.stars_in_region(1234).is_empty());
+
+        assert_eq!(universe.stars[1].region_id, 1000);
+        assert_eq!(universe.stars[1].constellation_id, Some(2000));
+        assert_eq!(universe.stars[2].constellation_id, None);
+
+        assert_eq!(
+            universe.region_bounds(1000),
+            Some(([0.0, 0.0, 0.0], [0.0, 10.0, 0.0]))
+        );
+        assert_eq!(universe.region_centroid(1000), Some([0.0, 5.0, 0.0]));
+        assert_eq!(universe.region_bounds(1234), None);
+        assert_eq!(universe.region_centroid(1234), None);
+    }
+
+    #[test]
+    fn test_build_from_source() {
+        let snapshot = std::env::temp_dir().join(format!("eftb-source-{}.bin", std::process::id()));
+        let source = UniverseSource {
+            snapshot: snapshot.clone(),
+            ..UniverseSource::from_data_dir("data_fixtures")
+        };
+        let jump_edges = JumpEdges::Precomputed(Meters::new(50.0));
+
+        let built = Universe::build_snapshot(&source, jump_edges, &[]).expect("Failed to build");
+        let header = snapshot::read_header(&snapshot).expect("Failed to read snapshot");
+        assert_eq!(header.jump_edges, jump_edges);
+        let loaded = Universe::build(&source, jump_edges).expect("Failed to load");
+        std::fs::remove_file(&snapshot).expect("Failed to remove snapshot");
+
+        assert_eq!(loaded.stars.len(), built.stars.len());
+        assert_eq!(loaded.stars[3].connections.len(), 5);
+    }
+
+    #[test]
+    fn test_hierarchies_are_saved() {
+        let snapshot =
+            std::env::temp_dir().join(format!("eftb-hierarchy-{}.bin", std::process::id()));
+        let source = UniverseSource {
+            snapshot: snapshot.clone(),
+            ..UniverseSource::from_data_dir("data_fixtures")
+        };
+        let jump_edges = JumpEdges::Precomputed(Meters::new(50.0));
+        let key = HierarchyKey {
+            jump_distance: Meters::new(25.0),
+            optimize: PathOptimize::Hops,
+            use_smart_gates: true,
+        };
+
+        // Building the hierarchy leaves a snapshot with it in, so the next
+        // build can load it instead of building it again
+        Universe::build_with_hierarchies(&source, jump_edges, &[key]).expect("Failed to build");
+        let header = snapshot::read_header(&snapshot).expect("Failed to read snapshot");
+        let loaded = snapshot::load(&snapshot, &header).expect("Failed to load snapshot");
+        std::fs::remove_file(&snapshot).expect("Failed to remove snapshot");
+
+        assert_eq!(header.hierarchies, vec![key]);
+        let loaded = loaded.expect("Snapshot should be up to date");
+        assert_eq!(loaded.hierarchies.len(), 1);
+        assert_eq!(loaded.hierarchies[0].key, key);
+    }
+
+    #[test]
+    fn test_tiny_universe_on_demand() {
+        let universe = Universe::tiny_test_on_demand();
+        assert_eq!(universe.stars[0].connections.len(), 1);
+        assert_eq!(universe.stars[1].connections.len(), 0);
+        assert_eq!(universe.stars[2].connections.len(), 1);
+        assert_eq!(universe.stars[3].connections.len(), 2);
+
+        // Looking up connections should give the same answers as if they
+        // had been precomputed
+        let precomputed = Universe::tiny_test();
+        for idx in 0..universe.stars.len() {
+            for jump_distance in [0.1, 10.0, 25.0, 49.0] {
+                let key = |c: &Connection| (c.conn_type.clone(), c.target, c.distance);
+                let mut a: Vec<_> = universe
+                    .connections(idx, Meters::new(jump_distance))
+                    .iter()
+                    .map(key)
+                    .collect();
+                let mut b: Vec<_> = precomputed
+                    .connections(idx, Meters::new(jump_distance))
+                    .iter()
+                    .map(key)
+                    .collect();
+                a.sort();
+                b.sort();
+                assert_eq!(a, b);
+            }
+        }
+    }
+
+    // Pair numbering would overflow 32 bits long before the map got this big
+    #[test]
+    fn test_jump_ids_fit_large_maps() {
+        let n = 200_000;
+        assert_eq!(jump_id(10, n, 0, 1), 11);
+        let last = jump_id(10, n, n - 1, n - 2);
+        assert!(last > ConnectionId::from(u32::MAX));
+        assert!(last > jump_id(10, n, n - 2, n - 1));
+    }
+
+    // A star exactly at the edge of the range can be jumped to, however
+    // the jumps are found
+    #[test]
+    fn test_jump_at_range() {
+        let jump_distance = Meters::new(10.0);
+        let precomputed = Universe::tiny_test_r(JumpEdges::Precomputed(jump_distance))
+            .expect("Failed to load tiny test universe");
+        let on_demand = Universe::tiny_test_on_demand();
+        for universe in [precomputed, on_demand] {
+            let jumps: Vec<_> = universe
+                .connections(0, jump_distance)
+                .into_iter()
+                .filter(|c| c.conn_type == ConnType::Jump)
+                .map(|c| c.target)
+                .collect();
+            assert_eq!(jumps, vec![1]);
+        }
+    }
+
+    // Every connection out of a star is a connection into its target
+    #[test]
+    fn test_incoming_mirrors_connections() {
+        for universe in [Universe::tiny_test(), Universe::tiny_test_on_demand()] {
+            let jump_distance = Meters::new(25.0);
+            let mut outgoing = Vec::new();
+            let mut incoming = Vec::new();
+            for idx in 0..universe.stars.len() {
+                for conn in universe.connections(idx, jump_distance) {
+                    outgoing.push((idx, conn.target, conn.id, conn.conn_type));
+                }
+                for (from, conn) in universe.incoming(idx, jump_distance) {
+                    assert_eq!(conn.target, idx);
+                    incoming.push((from, conn.target, conn.id, conn.conn_type));
+                }
+            }
+            outgoing.sort();
+            incoming.sort();
+            assert_eq!(outgoing, incoming);
+            // The smart gates only go one way each
+            assert_eq!(universe.incoming_gates[2].len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_spatial_queries() {
+        let universe = Universe::tiny_test();
+
+        let mut near: Vec<StarIdx> = universe
+            .stars_within(&[0.0, 0.0, 0.0], Meters::new(15.0))
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect();
+        near.sort();
+        assert_eq!(near, vec![0, 1]);
+
+        let nearest = universe.k_nearest(&[19.0, 19.0, 0.0], 2);
+        assert_eq!(nearest.len(), 2);
+        assert_eq!(nearest[0].0, 3);
+        assert_eq!(nearest[1].0, 2);
+
+        let mut boxed = universe.stars_in_box(&[-1.0, 5.0, -1.0], &[1.0, 25.0, 1.0]);
+        boxed.sort();
+        assert_eq!(boxed, vec![1, 2]);
+
+        let star2 = universe.stars[2].loc;
+        assert_eq!(
+            universe.nearest_gate(&star2, false),
+            Some(Meters::new(20.0))
+        );
+        assert_eq!(universe.nearest_gate(&star2, true), Some(Meters::new(0.0)));
+    }
+
+    #[test]
+    fn test_star_by_name() {
+        let universe = Universe::tiny_test();
+        let find = |name: &str| universe.star_by_name(&name.to_string()).map(|s| s.id);
+
+        assert_eq!(find("SS1001").expect("exact"), 1001);
+        assert_eq!(find("ss1001").expect("lowercase"), 1001);
+        assert!(find("SSI001").is_err());
+        assert_eq!(find("sS1OO2").expect("O for 0"), 1002);
+
+        let err = find("SS1009").expect_err("no such star").to_string();
+        assert_eq!(
+            err,
+            "Star not found: SS1009 (did you mean SS1000, SS1001, SS1002, SS1003?)"
+        );
+        let err = find("Nowhere").expect_err("no such star").to_string();
+        assert_eq!(err, "Star not found: Nowhere");
+
+        let prefixed: Vec<&str> = universe
+            .stars_with_prefix("ss100", 2)
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(prefixed, vec!["SS1000", "SS1001"]);
+    }
+
     #[test]
     fn test_distance() {
         let a = Star {
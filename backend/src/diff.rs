@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::data::{Point3D, SolarSystemId};
+use crate::raw::{RawSmartGate, RawSolarSystem, RawStarMap};
+use crate::spatial;
+use crate::units::Meters;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SystemRef {
+    pub id: SolarSystemId,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RenamedSystem {
+    pub id: SolarSystemId,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MovedSystem {
+    pub id: SolarSystemId,
+    pub name: String,
+    pub old_center: Point3D,
+    pub new_center: Point3D,
+    pub distance: Meters,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GateRef {
+    pub item_id: u32,
+    pub name: String,
+    pub from: SolarSystemId,
+    pub to: SolarSystemId,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RepointedGate {
+    pub item_id: u32,
+    pub name: String,
+    pub old_from: SolarSystemId,
+    pub old_to: SolarSystemId,
+    pub new_from: SolarSystemId,
+    pub new_to: SolarSystemId,
+}
+
+/// Everything that changed between two versions of the raw data. Systems
+/// are matched by solar system ID and smart gates by item ID, and every
+/// list is sorted by ID.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UniverseDiff {
+    pub systems_added: Vec<SystemRef>,
+    pub systems_removed: Vec<SystemRef>,
+    pub systems_renamed: Vec<RenamedSystem>,
+    pub systems_moved: Vec<MovedSystem>,
+    pub jumps_added: Vec<(SolarSystemId, SolarSystemId)>,
+    pub jumps_removed: Vec<(SolarSystemId, SolarSystemId)>,
+    pub gates_added: Vec<GateRef>,
+    pub gates_removed: Vec<GateRef>,
+    pub gates_repointed: Vec<RepointedGate>,
+}
+
+impl UniverseDiff {
+    pub fn is_empty(&self) -> bool {
+        self.change_count() == 0
+    }
+
+    pub fn change_count(&self) -> usize {
+        self.systems_added.len()
+            + self.systems_removed.len()
+            + self.systems_renamed.len()
+            + self.systems_moved.len()
+            + self.jumps_added.len()
+            + self.jumps_removed.len()
+            + self.gates_added.len()
+            + self.gates_removed.len()
+            + self.gates_repointed.len()
+    }
+}
+
+impl std::fmt::Display for UniverseDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for s in &self.systems_added {
+            writeln!(f, "+ system {} ({})", s.name, s.id)?;
+        }
+        for s in &self.systems_removed {
+            writeln!(f, "- system {} ({})", s.name, s.id)?;
+        }
+        for s in &self.systems_renamed {
+            writeln!(
+                f,
+                "~ system {} renamed {} -> {}",
+                s.id, s.old_name, s.new_name
+            )?;
+        }
+        for s in &self.systems_moved {
+            writeln!(
+                f,
+                "~ system {} ({}) moved {:.3} ly",
+                s.name,
+                s.id,
+                s.distance.to_light_years()
+            )?;
+        }
+        for (from, to) in &self.jumps_added {
+            writeln!(f, "+ jump {} -> {}", from, to)?;
+        }
+        for (from, to) in &self.jumps_removed {
+            writeln!(f, "- jump {} -> {}", from, to)?;
+        }
+        for g in &self.gates_added {
+            writeln!(
+                f,
+                "+ gate {} ({}) {} -> {}",
+                g.name, g.item_id, g.from, g.to
+            )?;
+        }
+        for g in &self.gates_removed {
+            writeln!(
+                f,
+                "- gate {} ({}) {} -> {}",
+                g.name, g.item_id, g.from, g.to
+            )?;
+        }
+        for g in &self.gates_repointed {
+            writeln!(
+                f,
+                "~ gate {} ({}) {} -> {} now {} -> {}",
+                g.name, g.item_id, g.old_from, g.old_to, g.new_from, g.new_to
+            )?;
+        }
+        write!(f, "{} changes", self.change_count())
+    }
+}
+
+fn system_ref(system: &RawSolarSystem) -> SystemRef {
+    SystemRef {
+        id: system.solar_system_id,
+        name: system.display_name(),
+    }
+}
+
+fn gate_ref(gate: &RawSmartGate) -> GateRef {
+    GateRef {
+        item_id: gate.item_id,
+        name: gate.name.clone(),
+        from: gate.from,
+        to: gate.to,
+    }
+}
+
+/// Compare two versions of the raw data
+pub fn diff(
+    old_star_data: &RawStarMap,
+    old_smart_gates: &[RawSmartGate],
+    new_star_data: &RawStarMap,
+    new_smart_gates: &[RawSmartGate],
+) -> UniverseDiff {
+    let mut diff = UniverseDiff::default();
+
+    let old_systems: BTreeMap<SolarSystemId, &RawSolarSystem> = old_star_data
+        .solar_systems
+        .iter()
+        .map(|s| (s.solar_system_id, s))
+        .collect();
+    let new_systems: BTreeMap<SolarSystemId, &RawSolarSystem> = new_star_data
+        .solar_systems
+        .iter()
+        .map(|s| (s.solar_system_id, s))
+        .collect();
+    for (id, old) in &old_systems {
+        let Some(new) = new_systems.get(id) else {
+            diff.systems_removed.push(system_ref(old));
+            continue;
+        };
+        if old.name != new.name {
+            diff.systems_renamed.push(RenamedSystem {
+                id: *id,
+                old_name: old.display_name(),
+                new_name: new.display_name(),
+            });
+        }
+        if old.center != new.center {
+            diff.systems_moved.push(MovedSystem {
+                id: *id,
+                name: new.display_name(),
+                old_center: old.center,
+                new_center: new.center,
+                distance: spatial::distance(&old.center, &new.center),
+            });
+        }
+    }
+    for (id, new) in &new_systems {
+        if !old_systems.contains_key(id) {
+            diff.systems_added.push(system_ref(new));
+        }
+    }
+
+    let old_jumps: BTreeSet<(SolarSystemId, SolarSystemId)> = old_star_data
+        .jumps
+        .iter()
+        .map(|j| (j.from_system_id, j.to_system_id))
+        .collect();
+    let new_jumps: BTreeSet<(SolarSystemId, SolarSystemId)> = new_star_data
+        .jumps
+        .iter()
+        .map(|j| (j.from_system_id, j.to_system_id))
+        .collect();
+    diff.jumps_added = new_jumps.difference(&old_jumps).cloned().collect();
+    diff.jumps_removed = old_jumps.difference(&new_jumps).cloned().collect();
+
+    let old_gates: BTreeMap<u32, &RawSmartGate> =
+        old_smart_gates.iter().map(|g| (g.item_id, g)).collect();
+    let new_gates: BTreeMap<u32, &RawSmartGate> =
+        new_smart_gates.iter().map(|g| (g.item_id, g)).collect();
+    for (item_id, old) in &old_gates {
+        match new_gates.get(item_id) {
+            None => diff.gates_removed.push(gate_ref(old)),
+            Some(new) if (old.from, old.to) != (new.from, new.to) => {
+                diff.gates_repointed.push(RepointedGate {
+                    item_id: *item_id,
+                    name: new.name.clone(),
+                    old_from: old.from,
+                    old_to: old.to,
+                    new_from: new.from,
+                    new_to: new.to,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for (item_id, new) in &new_gates {
+        if !old_gates.contains_key(item_id) {
+            diff.gates_added.push(gate_ref(new));
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw::tests::{gate, jump, system};
+
+    fn old() -> (RawStarMap, Vec<RawSmartGate>) {
+        (
+            RawStarMap {
+                solar_systems: vec![
+                    system(1, Some("A"), [0.0, 0.0, 0.0]),
+                    system(2, Some("B"), [10.0, 0.0, 0.0]),
+                    system(3, Some("C"), [20.0, 0.0, 0.0]),
+                ],
+                jumps: vec![jump(1, 2), jump(2, 3)],
+            },
+            vec![gate(10, 1, 3), gate(11, 3, 1), gate(12, 2, 3)],
+        )
+    }
+
+    #[test]
+    fn test_no_changes() {
+        let (star_data, smart_gates) = old();
+        let diff = diff(&star_data, &smart_gates, &star_data, &smart_gates);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "0 changes");
+    }
+
+    #[test]
+    fn test_everything_changed() {
+        let (old_star_data, old_smart_gates) = old();
+        let new_star_data = RawStarMap {
+            solar_systems: vec![
+                system(1, Some("A"), [0.0, 0.0, 0.0]),
+                system(2, Some("B2"), [10.0, 0.0, 0.0]),
+                system(4, Some("D"), [0.0, 3.0, 4.0]),
+            ],
+            jumps: vec![jump(1, 2), jump(2, 4)],
+        };
+        let new_smart_gates = vec![gate(10, 1, 4), gate(12, 2, 3), gate(13, 4, 1)];
+        let mut new_star_data_moved = new_star_data;
+        new_star_data_moved.solar_systems[0].center = [0.0, 3.0, 4.0];
+
+        let diff = diff(
+            &old_star_data,
+            &old_smart_gates,
+            &new_star_data_moved,
+            &new_smart_gates,
+        );
+        assert_eq!(
+            diff.systems_added,
+            vec![SystemRef {
+                id: 4,
+                name: "D".to_string()
+            }]
+        );
+        assert_eq!(diff.systems_removed.len(), 1);
+        assert_eq!(diff.systems_removed[0].id, 3);
+        assert_eq!(
+            diff.systems_renamed,
+            vec![RenamedSystem {
+                id: 2,
+                old_name: "B".to_string(),
+                new_name: "B2".to_string()
+            }]
+        );
+        assert_eq!(diff.systems_moved.len(), 1);
+        assert_eq!(diff.systems_moved[0].id, 1);
+        assert_eq!(diff.systems_moved[0].distance, Meters::new(5.0));
+        assert_eq!(diff.jumps_added, vec![(2, 4)]);
+        assert_eq!(diff.jumps_removed, vec![(2, 3)]);
+        assert_eq!(diff.gates_added.len(), 1);
+        assert_eq!(diff.gates_added[0].item_id, 13);
+        assert_eq!(diff.gates_removed.len(), 1);
+        assert_eq!(diff.gates_removed[0].item_id, 11);
+        assert_eq!(
+            diff.gates_repointed,
+            vec![RepointedGate {
+                item_id: 10,
+                name: "Gate 10".to_string(),
+                old_from: 1,
+                old_to: 3,
+                new_from: 1,
+                new_to: 4
+            }]
+        );
+        assert_eq!(diff.change_count(), 9);
+    }
+
+    #[test]
+    fn test_formats() {
+        let (old_star_data, old_smart_gates) = old();
+        let (mut new_star_data, _) = old();
+        new_star_data.solar_systems.pop();
+        let diff = diff(&old_star_data, &old_smart_gates, &new_star_data, &[]);
+        assert_eq!(
+            diff.to_string(),
+            "- system C (3)\n\
+             - gate Gate 10 (10) 1 -> 3\n\
+             - gate Gate 11 (11) 3 -> 1\n\
+             - gate Gate 12 (12) 2 -> 3\n\
+             4 changes"
+        );
+        let json = serde_json::to_value(&diff).expect("Failed to serialize diff");
+        assert_eq!(
+            json["systems_removed"],
+            serde_json::json!([{"id": 3, "name": "C"}])
+        );
+        assert_eq!(json["jumps_added"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+/// Everything that can go wrong in the library
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Star not found: {name}{}", did_you_mean(suggestions))]
+    UnknownStar {
+        name: String,
+        /// The closest names, best first
+        suggestions: Vec<String>,
+    },
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse {file} at line {line}, column {column}: {message}")]
+    Parse {
+        file: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Bad snapshot {}: {message}", path.display())]
+    Snapshot { path: PathBuf, message: String },
+    #[error("Invalid {name}: {message}")]
+    InvalidParameter { name: &'static str, message: String },
+    #[error("Search timed out")]
+    Timeout,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Wrap a JSON error, keeping the location separate from the message
+    pub fn parse(file: impl Into<String>, err: serde_json::Error) -> Error {
+        let (line, column) = (err.line(), err.column());
+        let message = err.to_string();
+        let message = message
+            .strip_suffix(&format!(" at line {} column {}", line, column))
+            .unwrap_or(&message)
+            .to_string();
+        Error::Parse {
+            file: file.into(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    pub fn snapshot(path: impl Into<PathBuf>, message: impl ToString) -> Error {
+        Error::Snapshot {
+            path: path.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(" (did you mean {}?)", suggestions.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_location() {
+        let err = serde_json::from_str::<Vec<u32>>("[1,\n2,\nx]").expect_err("bad json");
+        let err = Error::parse("test.json", err);
+        assert!(matches!(
+            err,
+            Error::Parse {
+                line: 3,
+                column: 1,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Failed to parse test.json at line 3, column 1: expected value"
+        );
+    }
+
+    #[test]
+    fn test_unknown_star() {
+        let err = Error::UnknownStar {
+            name: "X".to_string(),
+            suggestions: vec![],
+        };
+        assert_eq!(err.to_string(), "Star not found: X");
+        let err = Error::UnknownStar {
+            name: "X".to_string(),
+            suggestions: vec!["Y".to_string(), "Z".to_string()],
+        };
+        assert_eq!(err.to_string(), "Star not found: X (did you mean Y, Z?)");
+    }
+}
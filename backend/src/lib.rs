@@ -1,7 +1,15 @@
 pub mod calc;
 pub mod data;
+pub mod diff;
+pub mod error;
+pub mod names;
 pub mod raw;
+pub mod snapshot;
+pub mod source;
+pub mod spatial;
 pub mod units;
+pub mod validate;
 
 pub use calc::exit::calc_exit;
 pub use calc::path::calc_path;
+pub use error::{Error, Result};
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+
+use crate::data::StarIdx;
+
+/// Normalize a star name for matching: case-insensitive, and treating the
+/// letter O and the digit 0 as the same thing, since they get mixed up
+/// all the time when typing names like `E9R-5PC`.
+pub fn normalize(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            c => c,
+        })
+        .collect()
+}
+
+/// Edit distance between two strings (insertions, deletions and substitutions)
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr: Vec<usize> = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            curr[j + 1] = substitute.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+/// Star names, normalized and sorted so that exact and prefix lookups
+/// are a binary search away
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NameIndex {
+    entries: Vec<(String, StarIdx)>,
+}
+
+impl NameIndex {
+    pub fn build<'a>(names: impl IntoIterator<Item = (&'a str, StarIdx)>) -> NameIndex {
+        let mut entries: Vec<(String, StarIdx)> = names
+            .into_iter()
+            .map(|(name, idx)| (normalize(name), idx))
+            .collect();
+        entries.sort();
+        NameIndex { entries }
+    }
+
+    /// Stars whose names match `name` after normalization. Usually one,
+    /// but names which only differ by O/0 or case will all be returned.
+    pub fn lookup(&self, name: &str) -> Vec<StarIdx> {
+        let name = normalize(name);
+        let start = self
+            .entries
+            .partition_point(|(n, _)| n.as_str() < name.as_str());
+        self.entries[start..]
+            .iter()
+            .take_while(|(n, _)| *n == name)
+            .map(|(_, idx)| *idx)
+            .collect()
+    }
+
+    /// Up to `limit` stars whose names start with `prefix`, in name order
+    pub fn prefix(&self, prefix: &str, limit: usize) -> Vec<StarIdx> {
+        let prefix = normalize(prefix);
+        let start = self
+            .entries
+            .partition_point(|(n, _)| n.as_str() < prefix.as_str());
+        self.entries[start..]
+            .iter()
+            .take_while(|(n, _)| n.starts_with(&prefix))
+            .take(limit)
+            .map(|(_, idx)| *idx)
+            .collect()
+    }
+
+    /// Up to `limit` stars with names similar to `name`, closest first.
+    /// Anything which would need more than a third of the name changing
+    /// is too different to be a useful suggestion.
+    pub fn suggest(&self, name: &str, limit: usize) -> Vec<(StarIdx, usize)> {
+        let name = normalize(name);
+        let max_distance = (name.chars().count() / 3).max(1);
+        let mut found: Vec<(usize, &str, StarIdx)> = self
+            .entries
+            .iter()
+            .filter_map(|(n, idx)| {
+                let d = levenshtein(&name, n);
+                (d <= max_distance).then_some((d, n.as_str(), *idx))
+            })
+            .collect();
+        found.sort();
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(d, _, idx)| (idx, d))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> NameIndex {
+        NameIndex::build([
+            ("E9R-5PC", 0),
+            ("E9R-5PD", 1),
+            ("EOO-123", 2),
+            ("ABC-123", 3),
+        ])
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("E9R-5PC", "E9R-5PC"), 0);
+        assert_eq!(levenshtein("E9R-5PC", "E9R5PC"), 1);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let index = index();
+        assert_eq!(index.lookup("E9R-5PC"), vec![0]);
+        assert_eq!(index.lookup("e9r-5pc"), vec![0]);
+        assert_eq!(index.lookup(" e9r-5pc "), vec![0]);
+        assert_eq!(index.lookup("E00-123"), vec![2]);
+        assert_eq!(index.lookup("eo0-123"), vec![2]);
+        assert_eq!(index.lookup("E9R"), Vec::<StarIdx>::new());
+    }
+
+    #[test]
+    fn test_prefix() {
+        let index = index();
+        assert_eq!(index.prefix("e9r", 10), vec![0, 1]);
+        assert_eq!(index.prefix("e9r", 1), vec![0]);
+        assert_eq!(index.prefix("E0", 10), vec![2]);
+        assert_eq!(index.prefix("X", 10), Vec::<StarIdx>::new());
+        assert_eq!(index.prefix("", 10).len(), 4);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let index = index();
+        // One typo away from two names - ties are broken by name
+        assert_eq!(index.suggest("E9R-5PX", 10), vec![(0, 1), (1, 1)]);
+        assert_eq!(index.suggest("E9R5PD", 10), vec![(1, 1), (0, 2)]);
+        assert_eq!(index.suggest("E9R5PD", 1), vec![(1, 1)]);
+        assert_eq!(index.suggest("nothing like it", 10), vec![]);
+    }
+}
@@ -1,6 +1,8 @@
-use anyhow::Result;
 use serde::Deserialize;
 
+use crate::error::{Error, Result};
+use crate::source::UniverseSource;
+
 // ====================================================================
 // Data structures for the starmap pickle extracted from the client
 // ====================================================================
@@ -14,11 +16,33 @@ pub struct RawStarMap {
 
 impl RawStarMap {
     pub fn from_file(file: &str) -> Result<Self> {
-        let file = std::fs::read_to_string(file)?;
-        serde_json::from_str::<Self>(&file).map_err(|e| e.into())
+        let data = std::fs::read_to_string(file).map_err(|e| Error::io(file, e))?;
+        serde_json::from_str::<Self>(&data).map_err(|e| Error::parse(file, e))
     }
 }
 
+/// Parse the contents of starmap.json and (optionally) smartgates.json,
+/// naming the files from `source` in any errors
+pub fn parse(
+    source: &UniverseSource,
+    starmap: &[u8],
+    smartgates: Option<&[u8]>,
+) -> Result<(RawStarMap, Vec<RawSmartGate>)> {
+    let raw_star_data: RawStarMap = serde_json::from_slice(starmap)
+        .map_err(|e| Error::parse(source.starmap.display().to_string(), e))?;
+    let raw_smart_gates: Vec<RawSmartGate> = match smartgates {
+        Some(smartgates) => serde_json::from_slice(smartgates).map_err(|e| {
+            let file = source
+                .smartgates
+                .as_ref()
+                .map_or("smartgates".to_string(), |path| path.display().to_string());
+            Error::parse(file, e)
+        })?,
+        None => Vec::new(),
+    };
+    Ok((raw_star_data, raw_smart_gates))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RawJump {
     #[serde(rename(deserialize = "fromSystemID"))]
@@ -31,14 +55,27 @@ pub struct RawJump {
 
 #[derive(Debug, Deserialize)]
 pub struct RawSolarSystem {
-    pub name: String,
+    /// The generator writes `null` for systems it can't find a name for
+    pub name: Option<String>,
     pub center: [f64; 3],
     #[serde(rename(deserialize = "regionID"))]
     pub region_id: u32,
+    /// Older starmaps don't include constellations
+    #[serde(rename(deserialize = "constellationID"), default)]
+    pub constellation_id: Option<u32>,
     #[serde(rename(deserialize = "solarSystemID"))]
     pub solar_system_id: u32,
 }
 
+impl RawSolarSystem {
+    /// The system's name, falling back to its ID if it has no name
+    pub fn display_name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.solar_system_id.to_string())
+    }
+}
+
 // ====================================================================
 // Data structures for smartgates.json
 // ====================================================================
@@ -52,3 +89,37 @@ pub struct RawSmartGate {
     pub from: u32,
     pub to: u32,
 }
+
+/// Building blocks for tests which need raw data of their own
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn system(id: u32, name: Option<&str>, center: [f64; 3]) -> RawSolarSystem {
+        RawSolarSystem {
+            name: name.map(|n| n.to_string()),
+            center,
+            region_id: 1,
+            constellation_id: None,
+            solar_system_id: id,
+        }
+    }
+
+    pub(crate) fn jump(from: u32, to: u32) -> RawJump {
+        RawJump {
+            from_system_id: from,
+            to_system_id: to,
+            jump_type: 0,
+        }
+    }
+
+    pub(crate) fn gate(item_id: u32, from: u32, to: u32) -> RawSmartGate {
+        RawSmartGate {
+            id: item_id.to_string(),
+            item_id,
+            name: format!("Gate {}", item_id),
+            from,
+            to,
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::calc::hierarchy::HierarchyKey;
+use crate::data::{JumpEdges, Universe};
+use crate::error::{Error, Result};
+
+const MAGIC: &[u8; 4] = b"EFTB";
+
+/// Bump this whenever the layout of `Universe` (or anything inside it)
+/// changes, so that old snapshots get rebuilt instead of mis-read
+pub const FORMAT_VERSION: u32 = 8;
+
+/// Describes what a snapshot was built from, so that we can tell if it
+/// is still valid for the current inputs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub format_version: u32,
+    pub jump_edges: JumpEdges,
+    pub input_hash: u64,
+    /// Settings which the snapshot has contraction hierarchies for
+    pub hierarchies: Vec<HierarchyKey>,
+}
+
+impl SnapshotHeader {
+    pub fn new(jump_edges: JumpEdges, inputs: &[&[u8]]) -> SnapshotHeader {
+        SnapshotHeader {
+            format_version: FORMAT_VERSION,
+            jump_edges,
+            input_hash: hash_inputs(inputs),
+            hierarchies: Vec::new(),
+        }
+    }
+
+    pub fn with_hierarchies(mut self, hierarchies: &[HierarchyKey]) -> SnapshotHeader {
+        self.hierarchies = hierarchies.to_vec();
+        self
+    }
+
+    /// Can a universe built with this header answer queries for `wanted`?
+    pub fn satisfies(&self, wanted: &SnapshotHeader) -> bool {
+        self.format_version == wanted.format_version
+            && self.input_hash == wanted.input_hash
+            && self.jump_edges.covers(&wanted.jump_edges)
+            && wanted
+                .hierarchies
+                .iter()
+                .all(|key| self.hierarchies.contains(key))
+    }
+}
+
+/// Hash the raw bytes of each input file. Each file is length-prefixed so
+/// that moving bytes from one file to the next changes the hash.
+pub fn hash_inputs(inputs: &[&[u8]]) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    for input in inputs {
+        hasher.update(&(input.len() as u64).to_le_bytes());
+        hasher.update(input);
+    }
+    hasher.digest()
+}
+
+pub fn save(path: impl AsRef<Path>, header: &SnapshotHeader, universe: &Universe) -> Result<()> {
+    let path = path.as_ref();
+    let io = |e| Error::io(path, e);
+    let mut writer = BufWriter::new(File::create(path).map_err(io)?);
+    writer.write_all(MAGIC).map_err(io)?;
+    bincode::serialize_into(&mut writer, header).map_err(|e| Error::snapshot(path, e))?;
+    bincode::serialize_into(&mut writer, universe).map_err(|e| Error::snapshot(path, e))?;
+    writer.flush().map_err(io)?;
+    Ok(())
+}
+
+pub fn read_header(path: impl AsRef<Path>) -> Result<SnapshotHeader> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path).map_err(|e| Error::io(path, e))?);
+    read_header_from(path, &mut reader)
+}
+
+fn read_header_from(path: &Path, reader: &mut impl Read) -> Result<SnapshotHeader> {
+    let mut magic = [0u8; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|e| Error::io(path, e))?;
+    if &magic != MAGIC {
+        return Err(Error::snapshot(path, "Not a universe snapshot"));
+    }
+    bincode::deserialize_from(reader).map_err(|e| Error::snapshot(path, e))
+}
+
+/// Load a snapshot if it satisfies `wanted`, or return `None` if it is
+/// out of date. Only the header is read in the out-of-date case.
+pub fn load(path: impl AsRef<Path>, wanted: &SnapshotHeader) -> Result<Option<Universe>> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path).map_err(|e| Error::io(path, e))?);
+    let header = read_header_from(path, &mut reader)?;
+    if !header.satisfies(wanted) {
+        return Ok(None);
+    }
+    let universe = bincode::deserialize_from(reader).map_err(|e| Error::snapshot(path, e))?;
+    Ok(Some(universe))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Meters;
+
+    #[test]
+    fn test_round_trip() {
+        let universe = Universe::tiny_test();
+        let header = SnapshotHeader::new(universe.jump_edges, &[b"starmap", b"smartgates"]);
+        let path = std::env::temp_dir().join(format!("eftb-test-{}.bin", std::process::id()));
+
+        save(&path, &header, &universe).expect("Failed to save snapshot");
+        assert_eq!(read_header(&path).expect("Failed to read header"), header);
+        let loaded = load(&path, &header)
+            .expect("Failed to load snapshot")
+            .expect("Snapshot should match");
+        std::fs::remove_file(&path).expect("Failed to remove snapshot");
+
+        assert_eq!(loaded.stars.len(), universe.stars.len());
+        assert_eq!(loaded.star_name_to_idx, universe.star_name_to_idx);
+        for (a, b) in loaded.stars.iter().zip(universe.stars.iter()) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.loc, b.loc);
+            assert_eq!(a.connections, b.connections);
+        }
+        assert_eq!(
+            loaded
+                .stars_within(&[0.0, 0.0, 0.0], Meters::new(15.0))
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_header_satisfies() {
+        let built = SnapshotHeader::new(JumpEdges::Precomputed(Meters::new(500.0)), &[b"a"]);
+
+        // Smaller jump ranges can be answered from a bigger precomputed range
+        let smaller = SnapshotHeader::new(JumpEdges::Precomputed(Meters::new(100.0)), &[b"a"]);
+        assert!(built.satisfies(&smaller));
+
+        // But bigger ones can't
+        let bigger = SnapshotHeader::new(JumpEdges::Precomputed(Meters::new(600.0)), &[b"a"]);
+        assert!(!built.satisfies(&bigger));
+        let on_demand = SnapshotHeader::new(JumpEdges::OnDemand, &[b"a"]);
+        assert!(!built.satisfies(&on_demand));
+        assert!(on_demand.satisfies(&bigger));
+
+        // And changed inputs always invalidate the snapshot
+        let changed = SnapshotHeader::new(JumpEdges::Precomputed(Meters::new(100.0)), &[b"b"]);
+        assert!(!built.satisfies(&changed));
+        let moved = SnapshotHeader::new(JumpEdges::Precomputed(Meters::new(100.0)), &[b"", b"a"]);
+        assert!(!built.satisfies(&moved));
+
+        // Any hierarchies we want must have been built
+        let hops = HierarchyKey {
+            jump_distance: Meters::new(100.0),
+            optimize: crate::calc::path::PathOptimize::Hops,
+            use_smart_gates: false,
+        };
+        let wants_hierarchy = smaller.clone().with_hierarchies(&[hops]);
+        assert!(!built.satisfies(&wants_hierarchy));
+        let with_hierarchy = built.clone().with_hierarchies(&[hops]);
+        assert!(with_hierarchy.satisfies(&wants_hierarchy));
+        assert!(with_hierarchy.satisfies(&smaller));
+    }
+}
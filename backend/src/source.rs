@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::raw::{self, RawSmartGate, RawStarMap};
+
+/// Where to find the files that a Universe is built from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UniverseSource {
+    pub starmap: PathBuf,
+    /// `None`, or a file which doesn't exist, means "no smart gates"
+    pub smartgates: Option<PathBuf>,
+    /// Where the built Universe is cached between runs
+    pub snapshot: PathBuf,
+}
+
+impl UniverseSource {
+    /// Use `starmap.json`, `smartgates.json` and `universe.bin` from a
+    /// single directory
+    pub fn from_data_dir(dir: impl AsRef<Path>) -> UniverseSource {
+        let dir = dir.as_ref();
+        UniverseSource {
+            starmap: dir.join("starmap.json"),
+            smartgates: Some(dir.join("smartgates.json")),
+            snapshot: dir.join("universe.bin"),
+        }
+    }
+
+    /// Use the files from `data_dir`, except for any which are given explicitly
+    pub fn with_overrides(
+        data_dir: impl AsRef<Path>,
+        starmap: Option<PathBuf>,
+        smartgates: Option<PathBuf>,
+        snapshot: Option<PathBuf>,
+    ) -> UniverseSource {
+        let defaults = UniverseSource::from_data_dir(data_dir);
+        UniverseSource {
+            starmap: starmap.unwrap_or(defaults.starmap),
+            smartgates: smartgates.or(defaults.smartgates),
+            snapshot: snapshot.unwrap_or(defaults.snapshot),
+        }
+    }
+
+    /// Read the raw bytes of the starmap and smart gate files
+    pub fn read(&self) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let starmap = std::fs::read(&self.starmap).map_err(|e| Error::io(&self.starmap, e))?;
+        let smartgates = match &self.smartgates {
+            Some(path) => match std::fs::read(path) {
+                Ok(data) => Some(data),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    info!("{} not found, using no smart gates", path.display());
+                    None
+                }
+                Err(e) => return Err(Error::io(path, e)),
+            },
+            None => None,
+        };
+        Ok((starmap, smartgates))
+    }
+
+    /// Read and parse the starmap and smart gate files
+    pub fn read_raw(&self) -> Result<(RawStarMap, Vec<RawSmartGate>)> {
+        let (starmap, smartgates) = self.read()?;
+        raw::parse(self, &starmap, smartgates.as_deref())
+    }
+}
+
+impl Default for UniverseSource {
+    fn default() -> UniverseSource {
+        UniverseSource::from_data_dir("data")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_smartgates() {
+        let source = UniverseSource {
+            smartgates: Some(PathBuf::from("data_fixtures/does_not_exist.json")),
+            ..UniverseSource::from_data_dir("data_fixtures")
+        };
+        let (starmap, smartgates) = source.read().expect("Failed to read source");
+        assert!(!starmap.is_empty());
+        assert!(smartgates.is_none());
+    }
+
+    #[test]
+    fn test_missing_starmap() {
+        let source = UniverseSource::from_data_dir("data_fixtures/does_not_exist");
+        assert!(source.read().is_err());
+    }
+
+    #[test]
+    fn test_parse_error_names_file() {
+        let dir = std::env::temp_dir().join(format!("eftb-bad-json-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to make data dir");
+        std::fs::write(dir.join("starmap.json"), "{\n  nope").expect("Failed to write starmap");
+        let source = UniverseSource::from_data_dir(&dir);
+        let err = source.read_raw().expect_err("Starmap should not parse");
+        std::fs::remove_dir_all(&dir).expect("Failed to remove data dir");
+        match err {
+            Error::Parse { file, line, .. } => {
+                assert_eq!(file, source.starmap.display().to_string());
+                assert_eq!(line, 2);
+            }
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Point3D, StarIdx};
+use crate::units::Meters;
+
+/// Straight-line distance between two points
+pub fn distance(a: &Point3D, b: &Point3D) -> Meters {
+    Meters::new(distance_sq(a, b).sqrt())
+}
+
+fn distance_sq(a: &Point3D, b: &Point3D) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+/// A static k-d tree over star locations.
+///
+/// The tree is stored implicitly: each slice of `nodes` has its splitting
+/// point at the middle, with everything left of it on the low side of the
+/// split axis and everything right of it on the high side. The split axis
+/// cycles x -> y -> z as we go deeper.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpatialIndex {
+    nodes: Vec<(Point3D, StarIdx)>,
+}
+
+impl SpatialIndex {
+    pub fn build(points: impl IntoIterator<Item = (Point3D, StarIdx)>) -> SpatialIndex {
+        let mut nodes: Vec<(Point3D, StarIdx)> = points.into_iter().collect();
+        build_subtree(&mut nodes, 0);
+        SpatialIndex { nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// All points within `radius` of `point` (inclusive), in no particular order
+    pub fn within(&self, point: &Point3D, radius: Meters) -> Vec<(StarIdx, Meters)> {
+        let mut found = Vec::new();
+        self.within_subtree(&self.nodes, 0, point, radius.get(), &mut found);
+        found
+    }
+
+    fn within_subtree(
+        &self,
+        nodes: &[(Point3D, StarIdx)],
+        depth: usize,
+        point: &Point3D,
+        radius: f64,
+        found: &mut Vec<(StarIdx, Meters)>,
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+        let axis = depth % 3;
+        let mid = nodes.len() / 2;
+        let (loc, idx) = &nodes[mid];
+        let d = distance(point, loc);
+        if d.get() <= radius {
+            found.push((*idx, d));
+        }
+        let delta = point[axis] - loc[axis];
+        if delta - radius <= 0.0 {
+            self.within_subtree(&nodes[..mid], depth + 1, point, radius, found);
+        }
+        if delta + radius >= 0.0 {
+            self.within_subtree(&nodes[mid + 1..], depth + 1, point, radius, found);
+        }
+    }
+
+    /// The `k` points closest to `point`, nearest first
+    pub fn k_nearest(&self, point: &Point3D, k: usize) -> Vec<(StarIdx, Meters)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut best: BinaryHeap<Candidate> = BinaryHeap::with_capacity(k + 1);
+        self.nearest_subtree(&self.nodes, 0, point, k, &mut best);
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|c| (c.idx, Meters::new(c.dist_sq.sqrt())))
+            .collect()
+    }
+
+    fn nearest_subtree(
+        &self,
+        nodes: &[(Point3D, StarIdx)],
+        depth: usize,
+        point: &Point3D,
+        k: usize,
+        best: &mut BinaryHeap<Candidate>,
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+        let axis = depth % 3;
+        let mid = nodes.len() / 2;
+        let (loc, idx) = &nodes[mid];
+        best.push(Candidate {
+            dist_sq: distance_sq(point, loc),
+            idx: *idx,
+        });
+        if best.len() > k {
+            best.pop();
+        }
+
+        // Search the side of the split that the point is on first, then only
+        // look at the other side if it could contain something closer than
+        // the worst of what we have so far
+        let delta = point[axis] - loc[axis];
+        let (near, far) = if delta <= 0.0 {
+            (&nodes[..mid], &nodes[mid + 1..])
+        } else {
+            (&nodes[mid + 1..], &nodes[..mid])
+        };
+        self.nearest_subtree(near, depth + 1, point, k, best);
+        let worst = best.peek().map(|c| c.dist_sq).unwrap_or(f64::INFINITY);
+        if best.len() < k || delta * delta <= worst {
+            self.nearest_subtree(far, depth + 1, point, k, best);
+        }
+    }
+
+    /// All points inside the axis-aligned box between `min` and `max` (inclusive)
+    pub fn in_box(&self, min: &Point3D, max: &Point3D) -> Vec<StarIdx> {
+        let mut found = Vec::new();
+        self.box_subtree(&self.nodes, 0, min, max, &mut found);
+        found
+    }
+
+    fn box_subtree(
+        &self,
+        nodes: &[(Point3D, StarIdx)],
+        depth: usize,
+        min: &Point3D,
+        max: &Point3D,
+        found: &mut Vec<StarIdx>,
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+        let axis = depth % 3;
+        let mid = nodes.len() / 2;
+        let (loc, idx) = &nodes[mid];
+        if (0..3).all(|a| min[a] <= loc[a] && loc[a] <= max[a]) {
+            found.push(*idx);
+        }
+        if min[axis] <= loc[axis] {
+            self.box_subtree(&nodes[..mid], depth + 1, min, max, found);
+        }
+        if loc[axis] <= max[axis] {
+            self.box_subtree(&nodes[mid + 1..], depth + 1, min, max, found);
+        }
+    }
+}
+
+fn build_subtree(nodes: &mut [(Point3D, StarIdx)], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let axis = depth % 3;
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| a.0[axis].total_cmp(&b.0[axis]));
+    let (left, right) = nodes.split_at_mut(mid);
+    build_subtree(left, depth + 1);
+    build_subtree(&mut right[1..], depth + 1);
+}
+
+/// Max-heap entry for k-nearest search, so that the furthest of the
+/// current best candidates is the one that gets evicted
+struct Candidate {
+    dist_sq: f64,
+    idx: StarIdx,
+}
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist_sq
+            .total_cmp(&other.dist_sq)
+            .then_with(|| self.idx.cmp(&other.idx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deterministic scatter of points, so we can compare against brute force
+    fn scatter(n: usize) -> Vec<(Point3D, StarIdx)> {
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % 1000) as f64
+        };
+        (0..n).map(|i| ([next(), next(), next()], i)).collect()
+    }
+
+    #[test]
+    fn test_within_matches_brute_force() {
+        let points = scatter(500);
+        let index = SpatialIndex::build(points.clone());
+        let centre = [500.0, 500.0, 500.0];
+        let radius = Meters::new(200.0);
+
+        let mut found: Vec<StarIdx> = index
+            .within(&centre, radius)
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect();
+        found.sort();
+        let expected: Vec<StarIdx> = points
+            .iter()
+            .filter(|(loc, _)| distance(&centre, loc) <= radius)
+            .map(|(_, idx)| *idx)
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_k_nearest_matches_brute_force() {
+        let points = scatter(500);
+        let index = SpatialIndex::build(points.clone());
+        let centre = [123.0, 456.0, 789.0];
+
+        let found = index.k_nearest(&centre, 10);
+        let mut expected: Vec<(StarIdx, Meters)> = points
+            .iter()
+            .map(|(loc, idx)| (*idx, distance(&centre, loc)))
+            .collect();
+        expected.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        expected.truncate(10);
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_k_nearest_more_than_available() {
+        let index = SpatialIndex::build(scatter(3));
+        assert_eq!(index.k_nearest(&[0.0, 0.0, 0.0], 10).len(), 3);
+        assert!(SpatialIndex::default()
+            .k_nearest(&[0.0, 0.0, 0.0], 1)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_in_box_matches_brute_force() {
+        let points = scatter(500);
+        let index = SpatialIndex::build(points.clone());
+        let min = [100.0, 200.0, 300.0];
+        let max = [400.0, 600.0, 900.0];
+
+        let mut found = index.in_box(&min, &max);
+        found.sort();
+        let expected: Vec<StarIdx> = points
+            .iter()
+            .filter(|(loc, _)| (0..3).all(|a| min[a] <= loc[a] && loc[a] <= max[a]))
+            .map(|(_, idx)| *idx)
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+}
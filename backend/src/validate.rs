@@ -0,0 +1,366 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::data::SolarSystemId;
+use crate::raw::{RawSmartGate, RawStarMap};
+
+/// Coordinates are in meters, and the whole map fits within ~1e20 in
+/// every direction - anything ten times further out than that is junk
+pub const MAX_COORDINATE: f64 = 1e21;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Something wrong with the input data
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    DuplicateSystemId {
+        id: SolarSystemId,
+    },
+    DuplicateSystemName {
+        name: String,
+        ids: Vec<SolarSystemId>,
+    },
+    NullSystemName {
+        id: SolarSystemId,
+    },
+    NonFiniteCoordinates {
+        id: SolarSystemId,
+        center: [f64; 3],
+    },
+    OutOfBoundsCoordinates {
+        id: SolarSystemId,
+        center: [f64; 3],
+    },
+    DanglingJump {
+        from: SolarSystemId,
+        to: SolarSystemId,
+        missing: SolarSystemId,
+    },
+    SelfLoopJump {
+        id: SolarSystemId,
+    },
+    DuplicateSmartGateId {
+        item_id: u32,
+    },
+    DanglingSmartGate {
+        item_id: u32,
+        from: SolarSystemId,
+        to: SolarSystemId,
+        missing: SolarSystemId,
+    },
+    SelfLoopSmartGate {
+        item_id: u32,
+        id: SolarSystemId,
+    },
+    OneWaySmartGate {
+        item_id: u32,
+        from: SolarSystemId,
+        to: SolarSystemId,
+    },
+}
+
+impl Issue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            // These make the built universe wrong (stars overwriting each
+            // other, NaN distances, or connections silently dropped)
+            Issue::DuplicateSystemId { .. }
+            | Issue::DuplicateSystemName { .. }
+            | Issue::NonFiniteCoordinates { .. }
+            | Issue::DanglingJump { .. }
+            | Issue::DanglingSmartGate { .. } => Severity::Error,
+            // These are suspicious, but the universe still works
+            Issue::NullSystemName { .. }
+            | Issue::OutOfBoundsCoordinates { .. }
+            | Issue::SelfLoopJump { .. }
+            | Issue::DuplicateSmartGateId { .. }
+            | Issue::SelfLoopSmartGate { .. }
+            | Issue::OneWaySmartGate { .. } => Severity::Warning,
+        }
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::DuplicateSystemId { id } => {
+                write!(f, "Solar system ID {} appears more than once", id)
+            }
+            Issue::DuplicateSystemName { name, ids } => {
+                write!(f, "Solar system name {:?} is used by {:?}", name, ids)
+            }
+            Issue::NullSystemName { id } => write!(f, "Solar system {} has no name", id),
+            Issue::NonFiniteCoordinates { id, center } => {
+                write!(
+                    f,
+                    "Solar system {} has non-finite coordinates {:?}",
+                    id, center
+                )
+            }
+            Issue::OutOfBoundsCoordinates { id, center } => {
+                write!(f, "Solar system {} is out of bounds at {:?}", id, center)
+            }
+            Issue::DanglingJump { from, to, missing } => {
+                write!(
+                    f,
+                    "Jump {} -> {} has unknown endpoint {}",
+                    from, to, missing
+                )
+            }
+            Issue::SelfLoopJump { id } => write!(f, "Jump from {} leads back to itself", id),
+            Issue::DuplicateSmartGateId { item_id } => {
+                write!(f, "Smart gate ID {} appears more than once", item_id)
+            }
+            Issue::DanglingSmartGate {
+                item_id,
+                from,
+                to,
+                missing,
+            } => write!(
+                f,
+                "Smart gate {} ({} -> {}) has unknown endpoint {}",
+                item_id, from, to, missing
+            ),
+            Issue::SelfLoopSmartGate { item_id, id } => {
+                write!(f, "Smart gate {} leads from {} back to itself", item_id, id)
+            }
+            Issue::OneWaySmartGate { item_id, from, to } => write!(
+                f,
+                "Smart gate {} ({} -> {}) has no gate going back",
+                item_id, from, to
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    #[serde(flatten)]
+    pub issue: Issue,
+}
+
+impl From<Issue> for Finding {
+    fn from(issue: Issue) -> Finding {
+        Finding {
+            severity: issue.severity(),
+            issue,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    fn push(&mut self, issue: Issue) {
+        self.findings.push(issue.into());
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for finding in &self.findings {
+            let label = match finding.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            writeln!(f, "{}: {}", label, finding.issue)?;
+        }
+        write!(
+            f,
+            "{} errors, {} warnings",
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        )
+    }
+}
+
+pub fn validate(raw_star_data: &RawStarMap, raw_smart_gates: &[RawSmartGate]) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let mut ids: HashSet<SolarSystemId> = HashSet::new();
+    let mut names: HashMap<&str, Vec<SolarSystemId>> = HashMap::new();
+    for system in &raw_star_data.solar_systems {
+        let id = system.solar_system_id;
+        if !ids.insert(id) {
+            report.push(Issue::DuplicateSystemId { id });
+        }
+        match &system.name {
+            Some(name) => names.entry(name).or_default().push(id),
+            None => report.push(Issue::NullSystemName { id }),
+        }
+        let center = system.center;
+        if center.iter().any(|c| !c.is_finite()) {
+            report.push(Issue::NonFiniteCoordinates { id, center });
+        } else if center.iter().any(|c| c.abs() > MAX_COORDINATE) {
+            report.push(Issue::OutOfBoundsCoordinates { id, center });
+        }
+    }
+    let mut duplicate_names: Vec<(&str, Vec<SolarSystemId>)> =
+        names.into_iter().filter(|(_, ids)| ids.len() > 1).collect();
+    duplicate_names.sort();
+    for (name, ids) in duplicate_names {
+        report.push(Issue::DuplicateSystemName {
+            name: name.to_string(),
+            ids,
+        });
+    }
+
+    for jump in &raw_star_data.jumps {
+        let (from, to) = (jump.from_system_id, jump.to_system_id);
+        if from == to {
+            report.push(Issue::SelfLoopJump { id: from });
+        }
+        for missing in [from, to] {
+            if !ids.contains(&missing) {
+                report.push(Issue::DanglingJump { from, to, missing });
+            }
+        }
+    }
+
+    let mut gate_ids: HashSet<u32> = HashSet::new();
+    let gate_pairs: HashSet<(SolarSystemId, SolarSystemId)> =
+        raw_smart_gates.iter().map(|g| (g.from, g.to)).collect();
+    for gate in raw_smart_gates {
+        let (item_id, from, to) = (gate.item_id, gate.from, gate.to);
+        if !gate_ids.insert(item_id) {
+            report.push(Issue::DuplicateSmartGateId { item_id });
+        }
+        if from == to {
+            report.push(Issue::SelfLoopSmartGate { item_id, id: from });
+        } else if !gate_pairs.contains(&(to, from)) {
+            report.push(Issue::OneWaySmartGate { item_id, from, to });
+        }
+        for missing in [from, to] {
+            if !ids.contains(&missing) {
+                report.push(Issue::DanglingSmartGate {
+                    item_id,
+                    from,
+                    to,
+                    missing,
+                });
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw::tests::{gate, jump, system};
+
+    #[test]
+    fn test_fixtures() {
+        let raw_star_data = RawStarMap::from_file("data_fixtures/starmap.json")
+            .expect("Failed to load starmap fixture");
+        let raw_smart_gates: Vec<RawSmartGate> = serde_json::from_str(
+            &std::fs::read_to_string("data_fixtures/smartgates.json")
+                .expect("Failed to read smart gate fixture"),
+        )
+        .expect("Failed to parse smart gate fixture");
+        let report = validate(&raw_star_data, &raw_smart_gates);
+        // Both of the fixture's smart gates share an item ID, which other
+        // tests rely on to avoid both at once
+        let issues: Vec<Issue> = report.findings.iter().map(|f| f.issue.clone()).collect();
+        assert_eq!(issues, vec![Issue::DuplicateSmartGateId { item_id: 12345 }]);
+    }
+
+    #[test]
+    fn test_everything_wrong() {
+        let raw_star_data = RawStarMap {
+            solar_systems: vec![
+                system(1, Some("A"), [0.0, 0.0, 0.0]),
+                system(1, Some("B"), [0.0, 0.0, 0.0]),
+                system(2, Some("A"), [f64::NAN, 0.0, 0.0]),
+                system(3, None, [0.0, 1e22, 0.0]),
+            ],
+            jumps: vec![jump(1, 4), jump(2, 2)],
+        };
+        let raw_smart_gates = vec![gate(10, 1, 2), gate(10, 3, 3), gate(11, 3, 5)];
+        let report = validate(&raw_star_data, &raw_smart_gates);
+
+        let issues: Vec<Issue> = report.findings.iter().map(|f| f.issue.clone()).collect();
+        assert!(matches!(
+            issues.as_slice(),
+            [
+                Issue::DuplicateSystemId { id: 1 },
+                Issue::NonFiniteCoordinates { id: 2, .. },
+                Issue::NullSystemName { id: 3 },
+                Issue::OutOfBoundsCoordinates { id: 3, .. },
+                Issue::DuplicateSystemName { .. },
+                Issue::DanglingJump {
+                    from: 1,
+                    to: 4,
+                    missing: 4
+                },
+                Issue::SelfLoopJump { id: 2 },
+                Issue::OneWaySmartGate {
+                    item_id: 10,
+                    from: 1,
+                    to: 2
+                },
+                Issue::DuplicateSmartGateId { item_id: 10 },
+                Issue::SelfLoopSmartGate { item_id: 10, id: 3 },
+                Issue::OneWaySmartGate { item_id: 11, .. },
+                Issue::DanglingSmartGate {
+                    item_id: 11,
+                    missing: 5,
+                    ..
+                },
+            ]
+        ));
+        assert_eq!(
+            issues[4],
+            Issue::DuplicateSystemName {
+                name: "A".to_string(),
+                ids: vec![1, 2]
+            }
+        );
+        assert!(report.has_errors());
+        assert_eq!(report.count(Severity::Error), 5);
+        assert_eq!(report.count(Severity::Warning), 7);
+    }
+
+    #[test]
+    fn test_report_formats() {
+        let raw_star_data = RawStarMap {
+            solar_systems: vec![system(1, None, [0.0, 0.0, 0.0])],
+            jumps: vec![],
+        };
+        let report = validate(&raw_star_data, &[]);
+        assert!(!report.has_errors());
+        assert_eq!(
+            report.to_string(),
+            "warning: Solar system 1 has no name\n0 errors, 1 warnings"
+        );
+        assert_eq!(
+            serde_json::to_value(&report).expect("Failed to serialize report"),
+            serde_json::json!({
+                "findings": [{"severity": "warning", "kind": "null_system_name", "id": 1}]
+            })
+        );
+    }
+}
@@ -2,20 +2,228 @@
 extern crate rocket;
 
 use std::io::Cursor;
-use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
-use eftb::calc::path::PathOptimize;
+use eftb::calc::alternatives::AlternativesResult;
+use eftb::calc::budget::SearchBudget;
+use eftb::calc::hierarchy::HierarchyKey;
+use eftb::calc::nearest::{NearestResult, SystemFilter};
+use eftb::calc::path::{PathAlgorithm, PathConstraints, PathOptimize, SearchStats};
+use eftb::calc::rally::{FleetMember, MemberRange, RallyObjective, RallyResult};
+use eftb::calc::reach::{ReachLimits, ReachResult};
+use eftb::calc::route::{RouteResult, WaypointOrder};
+use eftb::calc::ship::{ShipPathResult, ShipProfile};
+use eftb::calc::weighted::{ParetoResult, PathWeights};
 use eftb::data;
-use eftb::data::{ConnType, SolarSystemId, Star};
+use eftb::data::{
+    ConnType, Connection, ConstellationId, Point3D, RegionId, SolarSystemId, Star, StarIdx,
+};
+use eftb::source::UniverseSource;
 use eftb::units::Meters;
 use rocket::fs::NamedFile;
 use rocket::http::ContentType;
 use rocket::http::Status;
-use rocket::request::Request;
+use rocket::request::{self, FromRequest, Request};
 use rocket::response::{self, Responder, Response};
 use rocket::serde::json::Json;
 use rocket::State;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+// ====================================================================
+// config
+
+/// Settings read from Rocket.toml or ROCKET_* environment variables,
+/// eg `ROCKET_DATA_DIR=/srv/eftb/data`
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct WebConfig {
+    /// Directory containing starmap.json and smartgates.json
+    data_dir: PathBuf,
+    /// Use this starmap instead of the one in data_dir
+    starmap: Option<PathBuf>,
+    /// Use this smart gate list instead of the one in data_dir
+    smartgates: Option<PathBuf>,
+    /// Cache the built universe here instead of in data_dir
+    snapshot: Option<PathBuf>,
+    /// Directory containing the built frontend
+    dist_dir: PathBuf,
+    /// Precompute jumps up to this many light-years, instead of looking
+    /// them up on demand
+    max_jump_distance: Option<f64>,
+    /// Check the data files for changes this often (in seconds), and
+    /// reload if they have changed. 0 to disable.
+    reload_interval: u64,
+    /// Bearer token for the /api/admin endpoints, which are disabled if
+    /// this is unset
+    admin_token: Option<String>,
+    /// Settings to build contraction hierarchies for, so that /api/path
+    /// requests which use exactly those settings are answered instantly,
+    /// eg `[{ jump = 100.0, optimize = "hops", use_smart_gates = true }]`
+    hierarchies: Vec<WebHierarchy>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WebHierarchy {
+    /// Jump range in light-years
+    jump: f64,
+    optimize: PathOptimize,
+    #[serde(default)]
+    use_smart_gates: bool,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig {
+            data_dir: PathBuf::from("data"),
+            starmap: None,
+            smartgates: None,
+            snapshot: None,
+            dist_dir: PathBuf::from("dist"),
+            max_jump_distance: None,
+            reload_interval: 10,
+            admin_token: None,
+            hierarchies: Vec::new(),
+        }
+    }
+}
+
+impl WebConfig {
+    fn source(&self) -> UniverseSource {
+        UniverseSource::with_overrides(
+            &self.data_dir,
+            self.starmap.clone(),
+            self.smartgates.clone(),
+            self.snapshot.clone(),
+        )
+    }
+
+    fn jump_edges(&self) -> data::JumpEdges {
+        match self.max_jump_distance {
+            Some(ly) => data::JumpEdges::Precomputed(Meters::from_light_years(ly)),
+            None => data::JumpEdges::OnDemand,
+        }
+    }
+
+    fn hierarchies(&self) -> Vec<HierarchyKey> {
+        self.hierarchies
+            .iter()
+            .map(|h| HierarchyKey {
+                jump_distance: Meters::from_light_years(h.jump),
+                optimize: h.optimize,
+                use_smart_gates: h.use_smart_gates,
+            })
+            .collect()
+    }
+}
+
+// ====================================================================
+// universe state
+
+/// A built universe, numbered so that clients can tell which version
+/// of the data they got an answer from
+struct Generation {
+    id: u64,
+    universe: data::Universe,
+}
+
+/// The current generation, which can be swapped out for a new one at
+/// any time. Requests grab an `Arc` to the current generation when they
+/// start, so a reload never changes the data under a running request.
+#[derive(Clone)]
+struct Universes {
+    current: Arc<RwLock<Arc<Generation>>>,
+    reload_lock: Arc<Mutex<()>>,
+    source: UniverseSource,
+    jump_edges: data::JumpEdges,
+    hierarchies: Vec<HierarchyKey>,
+}
+
+impl Universes {
+    fn new(
+        source: UniverseSource,
+        jump_edges: data::JumpEdges,
+        hierarchies: Vec<HierarchyKey>,
+    ) -> eftb::Result<Universes> {
+        let universe = data::Universe::build_with_hierarchies(&source, jump_edges, &hierarchies)?;
+        Ok(Universes {
+            current: Arc::new(RwLock::new(Arc::new(Generation { id: 1, universe }))),
+            reload_lock: Arc::new(Mutex::new(())),
+            source,
+            jump_edges,
+            hierarchies,
+        })
+    }
+
+    fn current(&self) -> Arc<Generation> {
+        self.current.read().expect("Universe lock poisoned").clone()
+    }
+
+    /// Build a fresh universe from the data files and swap it in,
+    /// returning the new generation number. This is slow, so call it
+    /// from a blocking thread.
+    ///
+    /// If the snapshot doesn't have our hierarchies, the new data is
+    /// swapped in without them straight away (so /api/path falls back to
+    /// A* for a while), and again with them once they're built.
+    fn reload(&self) -> eftb::Result<u64> {
+        let _guard = self.reload_lock.lock().expect("Reload lock poisoned");
+        let universe = data::Universe::build(&self.source, self.jump_edges)?;
+        let complete = self
+            .hierarchies
+            .iter()
+            .all(|key| universe.hierarchies.iter().any(|h| h.key == *key));
+        let id = self.swap(universe);
+        if complete {
+            return Ok(id);
+        }
+        info!("Building hierarchies for generation {}", id);
+        let universe = data::Universe::build_with_hierarchies(
+            &self.source,
+            self.jump_edges,
+            &self.hierarchies,
+        )?;
+        Ok(self.swap(universe))
+    }
+
+    fn swap(&self, universe: data::Universe) -> u64 {
+        let mut current = self.current.write().expect("Universe lock poisoned");
+        let id = current.id + 1;
+        *current = Arc::new(Generation { id, universe });
+        info!("Reloaded universe, now on generation {}", id);
+        id
+    }
+
+    /// The modification times of the data files, to tell if they've changed
+    fn data_mtimes(&self) -> Vec<Option<SystemTime>> {
+        std::iter::once(&self.source.starmap)
+            .chain(self.source.smartgates.iter())
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    /// Poll the data files in a background thread, and reload when they change
+    fn watch(&self, interval: Duration) {
+        let universes = self.clone();
+        std::thread::spawn(move || {
+            let mut last_seen = universes.data_mtimes();
+            loop {
+                std::thread::sleep(interval);
+                let mtimes = universes.data_mtimes();
+                if mtimes == last_seen {
+                    continue;
+                }
+                info!("Data files changed, reloading");
+                last_seen = mtimes;
+                if let Err(e) = universes.reload() {
+                    error!("Failed to reload universe: {:?}", e);
+                }
+            }
+        });
+    }
+}
 
 // ====================================================================
 // common
@@ -34,23 +242,105 @@ impl<'r> Responder<'r, 'static> for CustomError {
     }
 }
 
-impl From<anyhow::Error> for CustomError {
-    fn from(err: anyhow::Error) -> Self {
-        CustomError(Status::InternalServerError, format!("{:?}", err))
+impl From<eftb::Error> for CustomError {
+    fn from(err: eftb::Error) -> Self {
+        let status = match err {
+            eftb::Error::UnknownStar { .. } => Status::NotFound,
+            eftb::Error::InvalidParameter { .. } => Status::BadRequest,
+            eftb::Error::Timeout => Status::ServiceUnavailable,
+            eftb::Error::Io { .. } | eftb::Error::Parse { .. } | eftb::Error::Snapshot { .. } => {
+                Status::InternalServerError
+            }
+        };
+        CustomError(status, err.to_string())
     }
 }
 
+#[derive(Debug, Serialize)]
+struct WebStar {
+    id: SolarSystemId,
+    name: String,
+}
+
+/// Look up a star by name, forgiving case and O/0 mix-ups. The 404
+/// lists the closest names if there are any.
 fn get_star(universe: &data::Universe, name: String) -> Result<&Star, CustomError> {
-    let star = universe.star_by_name(&name).or(Err(CustomError(
-        Status::NotFound,
-        format!("Solar system {} not found", name),
-    )))?;
-    Ok(star)
+    Ok(universe.star_by_name(&name)?)
+}
+
+/// How long a request may spend searching before giving up
+fn search_budget() -> SearchBudget {
+    SearchBudget::unlimited().with_time(Duration::from_secs(5))
+}
+
+fn get_jump_distance(jump: f64) -> Result<Meters, CustomError> {
+    if !jump.is_finite() || jump < 0.0 {
+        return Err(eftb::Error::InvalidParameter {
+            name: "jump",
+            message: format!("{} is not a valid jump distance", jump),
+        }
+        .into());
+    }
+    Ok(Meters::from_light_years(jump))
+}
+
+fn get_optimize(optimize: String) -> Result<PathOptimize, CustomError> {
+    match optimize.as_str() {
+        "fuel" => Ok(PathOptimize::Fuel),
+        "distance" => Ok(PathOptimize::Distance),
+        "hops" => Ok(PathOptimize::Hops),
+        _ => Err(eftb::Error::InvalidParameter {
+            name: "optimize",
+            message: format!("{:?} is not one of fuel, distance or hops", optimize),
+        }
+        .into()),
+    }
+}
+
+/// Systems, regions and gates to keep away from, eg
+/// `avoid_system=A&avoid_system=B&avoid_region=123&max_jumps=5`
+#[derive(Debug, FromForm)]
+struct WebAvoid {
+    avoid_system: Vec<String>,
+    avoid_region: Vec<RegionId>,
+    avoid_gate: Vec<u32>,
+    no_npc_gates: Option<bool>,
+    no_jumps: Option<bool>,
+    max_jumps: Option<usize>,
+}
+
+fn get_constraints(
+    universe: &data::Universe,
+    use_smart_gates: bool,
+    avoid: WebAvoid,
+) -> Result<PathConstraints, CustomError> {
+    Ok(PathConstraints {
+        use_smart_gates,
+        avoid_systems: avoid
+            .avoid_system
+            .into_iter()
+            .map(|name| get_star(universe, name).map(|s| s.id))
+            .collect::<Result<_, CustomError>>()?,
+        avoid_regions: avoid.avoid_region.into_iter().collect(),
+        avoid_smart_gates: avoid.avoid_gate.into_iter().collect(),
+        no_npc_gates: avoid.no_npc_gates.unwrap_or(false),
+        no_jumps: avoid.no_jumps.unwrap_or(false),
+        max_jumps: avoid.max_jumps,
+        ..Default::default()
+    })
+}
+
+fn web_conn_type(conn_type: &ConnType) -> String {
+    match conn_type {
+        ConnType::Jump => "jump".to_string(),
+        ConnType::NpcGate => "npc_gate".to_string(),
+        ConnType::SmartGate => "smart_gate".to_string(),
+    }
 }
 
 #[get("/<_..>", rank = 2)]
-async fn index() -> Option<NamedFile> {
-    NamedFile::open(Path::new("./dist").join("index.html"))
+async fn index(config: &State<WebConfig>) -> Option<NamedFile> {
+    NamedFile::open(config.dist_dir.join("index.html"))
         .await
         .ok()
 }
@@ -61,42 +351,151 @@ async fn index() -> Option<NamedFile> {
 #[derive(Debug, Serialize)]
 struct StarsReturn {
     version: u32,
+    generation: u64,
     data: Vec<String>,
 }
 
-#[get("/stars")]
-fn get_stars(universe: &State<data::Universe>) -> Json<StarsReturn> {
-    let names = universe
-        .stars
-        .iter()
-        .map(|star| star.name.clone())
-        .collect();
+/// All star names, or with `prefix`, up to `limit` names which start
+/// with it (ignoring case and O/0)
+#[get("/stars?<prefix>&<limit>")]
+fn get_stars(
+    universes: &State<Universes>,
+    prefix: Option<String>,
+    limit: Option<usize>,
+) -> Json<StarsReturn> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let names = match prefix {
+        Some(prefix) => universe
+            .stars_with_prefix(&prefix, limit.unwrap_or(20))
+            .into_iter()
+            .map(|star| star.name.clone())
+            .collect(),
+        None => universe
+            .stars
+            .iter()
+            .map(|star| star.name.clone())
+            .collect(),
+    };
     Json(StarsReturn {
         version: 1,
+        generation: generation.id,
         data: names,
     })
 }
 
+// ====================================================================
+// get_star
+
+#[derive(Debug, Serialize)]
+struct StarInfo {
+    id: SolarSystemId,
+    name: String,
+    region_id: RegionId,
+    constellation_id: Option<ConstellationId>,
+}
+#[derive(Debug, Serialize)]
+struct StarReturn {
+    version: u32,
+    generation: u64,
+    data: StarInfo,
+}
+
+#[get("/star?<name>")]
+fn get_star_info(
+    universes: &State<Universes>,
+    name: String,
+) -> Result<Json<StarReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let star = get_star(universe, name)?;
+    Ok(Json(StarReturn {
+        version: 1,
+        generation: generation.id,
+        data: StarInfo {
+            id: star.id,
+            name: star.name.clone(),
+            region_id: star.region_id,
+            constellation_id: star.constellation_id,
+        },
+    }))
+}
+
+// ====================================================================
+// get_region
+
+#[derive(Debug, Serialize)]
+struct RegionInfo {
+    id: RegionId,
+    bounds: (Point3D, Point3D),
+    centroid: Point3D,
+    stars: Vec<WebStar>,
+}
+#[derive(Debug, Serialize)]
+struct RegionReturn {
+    version: u32,
+    generation: u64,
+    data: RegionInfo,
+}
+
+#[get("/region?<id>")]
+fn get_region(
+    universes: &State<Universes>,
+    id: RegionId,
+) -> Result<Json<RegionReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let (Some(bounds), Some(centroid)) = (universe.region_bounds(id), universe.region_centroid(id))
+    else {
+        return Err(CustomError(
+            Status::NotFound,
+            format!("Region {} not found", id),
+        ));
+    };
+    let stars = universe
+        .stars_in_region(id)
+        .iter()
+        .map(|idx| WebStar {
+            id: universe.stars[*idx].id,
+            name: universe.stars[*idx].name.clone(),
+        })
+        .collect();
+    Ok(Json(RegionReturn {
+        version: 1,
+        generation: generation.id,
+        data: RegionInfo {
+            id,
+            bounds,
+            centroid,
+            stars,
+        },
+    }))
+}
+
 // ====================================================================
 // calc_dist
 
 #[derive(Debug, Serialize)]
 struct DistReturn {
     version: u32,
+    generation: u64,
     data: f64,
 }
 
 #[get("/dist?<start>&<end>")]
 fn calc_dist(
-    universe: &State<data::Universe>,
+    universes: &State<Universes>,
     start: String,
     end: String,
 ) -> Result<Json<DistReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
     let start = get_star(universe, start)?;
     let end = get_star(universe, end)?;
     let dist: Meters = start.distance(end);
     Ok(Json(DistReturn {
         version: 1,
+        generation: generation.id,
         data: dist.to_light_years(),
     }))
 }
@@ -104,11 +503,6 @@ fn calc_dist(
 // ====================================================================
 // calc_path
 
-#[derive(Debug, Serialize)]
-struct WebStar {
-    id: SolarSystemId,
-    name: String,
-}
 #[derive(Debug, Serialize)]
 struct PathStep {
     from: WebStar,
@@ -116,100 +510,958 @@ struct PathStep {
     distance: f64,
     to: WebStar,
 }
+
+/// Describe each step of a path, starting from `start_idx`
+fn web_path(universe: &data::Universe, start_idx: StarIdx, path: &[Connection]) -> Vec<PathStep> {
+    let mut result = Vec::new();
+    let mut last_idx = start_idx;
+    for conn in path {
+        result.push(PathStep {
+            from: WebStar {
+                id: universe.stars[last_idx].id,
+                name: universe.stars[last_idx].name.clone(),
+            },
+            conn_type: web_conn_type(&conn.conn_type),
+            distance: conn.distance.to_light_years(),
+            to: WebStar {
+                id: universe.stars[conn.target].id,
+                name: universe.stars[conn.target].name.clone(),
+            },
+        });
+        last_idx = conn.target;
+    }
+    result
+}
+
 #[derive(Debug, Serialize)]
 struct PathReturn {
     version: u32,
+    generation: u64,
+    /// The search ran out of time, and `data` only goes as far as the
+    /// closest it got to the end
+    partial: bool,
     data: Vec<PathStep>,
+    /// What the search did, if asked for with `explain=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<WebSearchStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct WebSearchStats {
+    algorithm: String,
+    expanded: usize,
+    pushed: usize,
+    peak_open: usize,
+    start_h: f64,
+    final_g: f64,
+    final_h: f64,
+    /// Seconds spent in each phase
+    setup_time: f64,
+    search_time: f64,
+    path_time: f64,
 }
 
-#[get("/path?<start>&<end>&<jump>&<optimize>&<use_smart_gates>")]
+impl From<SearchStats> for WebSearchStats {
+    fn from(stats: SearchStats) -> Self {
+        WebSearchStats {
+            algorithm: match stats.algorithm {
+                PathAlgorithm::AStar => "astar",
+                PathAlgorithm::Bidirectional => "bidirectional",
+                PathAlgorithm::Hierarchy => "hierarchy",
+            }
+            .to_string(),
+            expanded: stats.expanded,
+            pushed: stats.pushed,
+            peak_open: stats.peak_open,
+            start_h: stats.start_h,
+            final_g: stats.final_g,
+            final_h: stats.final_h,
+            setup_time: stats.setup_time.as_secs_f64(),
+            search_time: stats.search_time.as_secs_f64(),
+            path_time: stats.path_time.as_secs_f64(),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/path?<start>&<end>&<jump>&<optimize>&<use_smart_gates>&<algorithm>&<explain>&<avoid..>")]
 fn calc_path(
-    universe: &State<data::Universe>,
+    universes: &State<Universes>,
     start: String,
     end: String,
     jump: f64,
     optimize: String,
     use_smart_gates: bool,
+    algorithm: Option<String>,
+    explain: Option<bool>,
+    avoid: WebAvoid,
 ) -> Result<Json<PathReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
     let start = get_star(universe, start)?;
     let end = get_star(universe, end)?;
-    let optimize = match optimize.as_str() {
-        "fuel" => PathOptimize::Fuel,
-        "distance" => PathOptimize::Distance,
-        "hops" => PathOptimize::Hops,
-        _ => {
-            return Err(CustomError(
-                Status::BadRequest,
-                "Invalid optimize value".to_string(),
-            ))
+    let optimize = get_optimize(optimize)?;
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+    // Use a hierarchy if we have one for these settings, since that is
+    // much faster and finds equally good paths
+    let algorithm = match algorithm.as_deref() {
+        None | Some("hierarchy") => PathAlgorithm::Hierarchy,
+        Some("astar") => PathAlgorithm::AStar,
+        Some("bidirectional") => PathAlgorithm::Bidirectional,
+        Some(other) => {
+            return Err(eftb::Error::InvalidParameter {
+                name: "algorithm",
+                message: format!(
+                    "{:?} is not one of astar, bidirectional or hierarchy",
+                    other
+                ),
+            }
+            .into())
         }
     };
 
-    let result = eftb::calc_path(
+    let (result, stats) = eftb::calc::path::calc_path_explained(
         universe,
         start,
         end,
-        Meters::from_light_years(jump),
+        get_jump_distance(jump)?,
         optimize,
-        use_smart_gates,
-        Some(5),
+        &constraints,
+        algorithm,
+        &search_budget(),
     );
+    let stats = explain.unwrap_or(false).then(|| stats.into());
+    match result {
+        eftb::calc::path::PathResult::Found(path) => Ok(Json(PathReturn {
+            version: 2,
+            generation: generation.id,
+            partial: false,
+            data: web_path(universe, universe.star_id_to_idx[&start.id], &path),
+            stats,
+        })),
+        eftb::calc::path::PathResult::NotFound => {
+            Err(CustomError(Status::NotFound, "No path found".to_string()))
+        }
+        eftb::calc::path::PathResult::Timeout(partial) if partial.is_empty() => {
+            Err(eftb::Error::Timeout.into())
+        }
+        eftb::calc::path::PathResult::Timeout(partial) => Ok(Json(PathReturn {
+            version: 2,
+            generation: generation.id,
+            partial: true,
+            data: web_path(universe, universe.star_id_to_idx[&start.id], &partial),
+            stats,
+        })),
+    }
+}
+
+// ====================================================================
+// calc_weighted_path
+
+/// The cheapest path, with the cost made up from the given weights (any
+/// left out count for nothing)
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/weighted_path?<start>&<end>&<jump>&<use_smart_gates>&<jump_ly>&<total_ly>&<hops>&<smart_gates>&<avoid..>"
+)]
+fn calc_weighted_path(
+    universes: &State<Universes>,
+    start: String,
+    end: String,
+    jump: f64,
+    use_smart_gates: bool,
+    jump_ly: Option<f64>,
+    total_ly: Option<f64>,
+    hops: Option<f64>,
+    smart_gates: Option<f64>,
+    avoid: WebAvoid,
+) -> Result<Json<PathReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let start = get_star(universe, start)?;
+    let end = get_star(universe, end)?;
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+    let weights = PathWeights::new(
+        jump_ly.unwrap_or(0.0),
+        total_ly.unwrap_or(0.0),
+        hops.unwrap_or(0.0),
+        smart_gates.unwrap_or(0.0),
+    )?;
+
+    let result = eftb::calc::weighted::calc_weighted_path(
+        universe,
+        start,
+        end,
+        get_jump_distance(jump)?,
+        &weights,
+        &constraints,
+        &search_budget(),
+    );
+    match result {
+        eftb::calc::path::PathResult::Found(path) => Ok(Json(PathReturn {
+            version: 2,
+            generation: generation.id,
+            partial: false,
+            data: web_path(universe, universe.star_id_to_idx[&start.id], &path),
+            stats: None,
+        })),
+        eftb::calc::path::PathResult::NotFound => {
+            Err(CustomError(Status::NotFound, "No path found".to_string()))
+        }
+        eftb::calc::path::PathResult::Timeout(_) => Err(eftb::Error::Timeout.into()),
+    }
+}
+
+// ====================================================================
+// calc_pareto
+
+#[derive(Debug, Serialize)]
+struct WebParetoRoute {
+    fuel: f64,
+    hops: usize,
+    steps: Vec<PathStep>,
+}
+#[derive(Debug, Serialize)]
+struct ParetoReturn {
+    version: u32,
+    generation: u64,
+    /// The search ran out of time, and there may be routes with fewer
+    /// hops than these
+    partial: bool,
+    data: Vec<WebParetoRoute>,
+}
+
+/// Every route which isn't beaten on both fuel and hops, least fuel first
+#[get("/pareto?<start>&<end>&<jump>&<use_smart_gates>&<avoid..>")]
+fn calc_pareto(
+    universes: &State<Universes>,
+    start: String,
+    end: String,
+    jump: f64,
+    use_smart_gates: bool,
+    avoid: WebAvoid,
+) -> Result<Json<ParetoReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let start = get_star(universe, start)?;
+    let end = get_star(universe, end)?;
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+
+    let result = eftb::calc::weighted::calc_pareto_front(
+        universe,
+        start,
+        end,
+        get_jump_distance(jump)?,
+        &constraints,
+        &search_budget(),
+    );
+    let (routes, partial) = match result {
+        ParetoResult::Found(routes) => (routes, false),
+        ParetoResult::NotFound => {
+            return Err(CustomError(Status::NotFound, "No path found".to_string()))
+        }
+        ParetoResult::Timeout(routes) if routes.is_empty() => {
+            return Err(eftb::Error::Timeout.into())
+        }
+        ParetoResult::Timeout(routes) => (routes, true),
+    };
+    let start_idx = universe.star_id_to_idx[&start.id];
+    Ok(Json(ParetoReturn {
+        version: 1,
+        generation: generation.id,
+        partial,
+        data: routes
+            .iter()
+            .map(|route| WebParetoRoute {
+                fuel: route.fuel,
+                hops: route.hops,
+                steps: web_path(universe, start_idx, &route.path),
+            })
+            .collect(),
+    }))
+}
+
+// ====================================================================
+// calc_alternatives
+
+#[derive(Debug, Serialize)]
+struct WebAlternative {
+    cost: f64,
+    /// How much more this costs than the best route
+    extra_cost: f64,
+    distance: f64,
+    hops: usize,
+    steps: Vec<PathStep>,
+}
+#[derive(Debug, Serialize)]
+struct AlternativesReturn {
+    version: u32,
+    generation: u64,
+    data: Vec<WebAlternative>,
+}
+
+/// Up to `count` of the best routes, cheapest first
+#[allow(clippy::too_many_arguments)]
+#[get("/alternatives?<start>&<end>&<jump>&<optimize>&<use_smart_gates>&<count>&<avoid..>")]
+fn calc_alternatives(
+    universes: &State<Universes>,
+    start: String,
+    end: String,
+    jump: f64,
+    optimize: String,
+    use_smart_gates: bool,
+    count: usize,
+    avoid: WebAvoid,
+) -> Result<Json<AlternativesReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let start = get_star(universe, start)?;
+    let end = get_star(universe, end)?;
+    let optimize = get_optimize(optimize)?;
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+    if count == 0 || count > 20 {
+        return Err(eftb::Error::InvalidParameter {
+            name: "count",
+            message: format!("{} is not between 1 and 20", count),
+        }
+        .into());
+    }
+
+    let result = eftb::calc::alternatives::calc_alternatives(
+        universe,
+        start,
+        end,
+        get_jump_distance(jump)?,
+        optimize,
+        &constraints,
+        count,
+        &search_budget(),
+    )?;
+    let start_idx = universe.star_id_to_idx[&start.id];
+    match result {
+        AlternativesResult::Found(routes) => Ok(Json(AlternativesReturn {
+            version: 1,
+            generation: generation.id,
+            data: routes
+                .iter()
+                .map(|route| WebAlternative {
+                    cost: route.cost,
+                    extra_cost: route.extra_cost,
+                    distance: route.distance().to_light_years(),
+                    hops: route.path.len(),
+                    steps: web_path(universe, start_idx, &route.path),
+                })
+                .collect(),
+        })),
+        AlternativesResult::NotFound => {
+            Err(CustomError(Status::NotFound, "No path found".to_string()))
+        }
+        AlternativesResult::Timeout => Err(eftb::Error::Timeout.into()),
+    }
+}
+
+// ====================================================================
+// calc_route
+
+#[derive(Debug, Serialize)]
+struct RouteLeg {
+    from: WebStar,
+    to: WebStar,
+    distance: f64,
+    hops: usize,
+    steps: Vec<PathStep>,
+}
+#[derive(Debug, Serialize)]
+struct RouteInfo {
+    order: Vec<WebStar>,
+    distance: f64,
+    legs: Vec<RouteLeg>,
+}
+#[derive(Debug, Serialize)]
+struct RouteReturn {
+    version: u32,
+    generation: u64,
+    data: RouteInfo,
+}
+
+/// A path through every one of `waypoints` (given as `waypoints=A&waypoints=B&...`),
+/// visiting them in the cheapest order if `optimize_order` is set
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/route?<waypoints>&<jump>&<optimize>&<use_smart_gates>&<optimize_order>&<fixed_end>&<avoid..>"
+)]
+fn calc_route(
+    universes: &State<Universes>,
+    waypoints: Vec<String>,
+    jump: f64,
+    optimize: String,
+    use_smart_gates: bool,
+    optimize_order: Option<bool>,
+    fixed_end: Option<bool>,
+    avoid: WebAvoid,
+) -> Result<Json<RouteReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let waypoints = waypoints
+        .into_iter()
+        .map(|name| get_star(universe, name))
+        .collect::<Result<Vec<&Star>, CustomError>>()?;
+    let order = match optimize_order.unwrap_or(false) {
+        true => WaypointOrder::Optimize {
+            fixed_end: fixed_end.unwrap_or(false),
+        },
+        false => WaypointOrder::AsGiven,
+    };
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+
+    let result = eftb::calc::route::calc_route(
+        universe,
+        &waypoints,
+        get_jump_distance(jump)?,
+        get_optimize(optimize)?,
+        &constraints,
+        order,
+        &search_budget(),
+    )?;
+    let web_star = |idx: StarIdx| WebStar {
+        id: universe.stars[idx].id,
+        name: universe.stars[idx].name.clone(),
+    };
+    match result {
+        RouteResult::Found(route) => Ok(Json(RouteReturn {
+            version: 1,
+            generation: generation.id,
+            data: RouteInfo {
+                order: route.order.iter().map(|&idx| web_star(idx)).collect(),
+                distance: route.distance().to_light_years(),
+                legs: route
+                    .legs
+                    .iter()
+                    .map(|leg| RouteLeg {
+                        from: web_star(leg.from),
+                        to: web_star(leg.to),
+                        distance: leg.distance.to_light_years(),
+                        hops: leg.path.len(),
+                        steps: web_path(universe, leg.from, &leg.path),
+                    })
+                    .collect(),
+            },
+        })),
+        RouteResult::NotFound { from, to } => Err(CustomError(
+            Status::NotFound,
+            format!(
+                "No path found from {} to {}",
+                universe.stars[from].name, universe.stars[to].name
+            ),
+        )),
+        RouteResult::Timeout => Err(eftb::Error::Timeout.into()),
+    }
+}
+
+// ====================================================================
+// calc_ship_path
+
+#[derive(Debug, Deserialize)]
+struct WebShip {
+    hull_mass: f64,
+    fittings_mass: Option<f64>,
+    cargo_mass: Option<f64>,
+    tank_size: f64,
+    current_fuel: f64,
+    fuel_efficiency: f64,
+}
+
+impl WebShip {
+    fn profile(&self) -> ShipProfile {
+        ShipProfile {
+            hull_mass: self.hull_mass,
+            fittings_mass: self.fittings_mass.unwrap_or(0.0),
+            cargo_mass: self.cargo_mass.unwrap_or(0.0),
+            tank_size: self.tank_size,
+            current_fuel: self.current_fuel,
+            fuel_efficiency: self.fuel_efficiency,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ShipPathStep {
+    from: WebStar,
+    conn_type: String,
+    distance: f64,
+    to: WebStar,
+    fuel_used: f64,
+    fuel_left: f64,
+}
+#[derive(Debug, Serialize)]
+struct ShipPathReturn {
+    version: u32,
+    generation: u64,
+    data: Vec<ShipPathStep>,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/ship_path?<start>&<end>&<optimize>&<use_smart_gates>&<hull_mass>&<fittings_mass>&<cargo_mass>&<tank_size>&<current_fuel>&<fuel_efficiency>&<avoid..>"
+)]
+fn calc_ship_path(
+    universes: &State<Universes>,
+    start: String,
+    end: String,
+    optimize: String,
+    use_smart_gates: bool,
+    hull_mass: f64,
+    fittings_mass: Option<f64>,
+    cargo_mass: Option<f64>,
+    tank_size: f64,
+    current_fuel: f64,
+    fuel_efficiency: f64,
+    avoid: WebAvoid,
+) -> Result<Json<ShipPathReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let start = get_star(universe, start)?;
+    let end = get_star(universe, end)?;
+    let optimize = get_optimize(optimize)?;
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+    let ship = WebShip {
+        hull_mass,
+        fittings_mass,
+        cargo_mass,
+        tank_size,
+        current_fuel,
+        fuel_efficiency,
+    }
+    .profile();
+
+    let result = eftb::calc::ship::calc_ship_path(
+        universe,
+        start,
+        end,
+        &ship,
+        optimize,
+        &constraints,
+        &search_budget(),
+    )?;
     match result {
-        eftb::calc::path::PathResult::Found(path) => {
+        ShipPathResult::Found(steps) => {
             let mut result = Vec::new();
             let mut last_idx = universe.star_id_to_idx[&start.id];
-            for conn in path {
-                result.push(PathStep {
+            for step in steps {
+                let conn = &step.conn;
+                result.push(ShipPathStep {
                     from: WebStar {
                         id: universe.stars[last_idx].id,
                         name: universe.stars[last_idx].name.clone(),
                     },
-                    conn_type: match conn.conn_type {
-                        ConnType::Jump => "jump".to_string(),
-                        ConnType::NpcGate => "npc_gate".to_string(),
-                        ConnType::SmartGate => "smart_gate".to_string(),
-                    },
+                    conn_type: web_conn_type(&conn.conn_type),
                     distance: conn.distance.to_light_years(),
                     to: WebStar {
                         id: universe.stars[conn.target].id,
                         name: universe.stars[conn.target].name.clone(),
                     },
+                    fuel_used: step.fuel_used,
+                    fuel_left: step.fuel_left,
                 });
                 last_idx = conn.target;
             }
-            Ok(Json(PathReturn {
-                version: 2,
+            Ok(Json(ShipPathReturn {
+                version: 1,
+                generation: generation.id,
                 data: result,
             }))
         }
-        eftb::calc::path::PathResult::NotFound => {
-            Err(CustomError(Status::NotFound, "No path found".to_string()))
-        }
-        eftb::calc::path::PathResult::Timeout => Err(CustomError(
-            Status::InternalServerError,
-            "Path calculation timed out".to_string(),
+        ShipPathResult::NotFound => Err(CustomError(
+            Status::NotFound,
+            "No path found with the fuel available".to_string(),
         )),
+        ShipPathResult::Timeout => Err(eftb::Error::Timeout.into()),
     }
 }
 
+// ====================================================================
+// calc_reach
+
+#[derive(Debug, Serialize)]
+struct WebReachable {
+    star: WebStar,
+    cost: f64,
+    hops: usize,
+    /// The cheapest way there from the start. With hops or jumps limited
+    /// this can differ from the way to the star before it, so each route
+    /// is given in full.
+    steps: Vec<PathStep>,
+}
+#[derive(Debug, Serialize)]
+struct ReachReturn {
+    version: u32,
+    generation: u64,
+    /// The search ran out of time, and `data` only has the closest
+    /// systems
+    partial: bool,
+    data: Vec<WebReachable>,
+}
+
+/// Every system reachable from `start`, cheapest first
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/reach?<start>&<jump>&<optimize>&<use_smart_gates>&<max_cost>&<max_hops>&<max_jump_ly>&<avoid..>"
+)]
+fn calc_reach(
+    universes: &State<Universes>,
+    start: String,
+    jump: f64,
+    optimize: String,
+    use_smart_gates: bool,
+    max_cost: Option<f64>,
+    max_hops: Option<usize>,
+    max_jump_ly: Option<f64>,
+    avoid: WebAvoid,
+) -> Result<Json<ReachReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let start = get_star(universe, start)?;
+    let optimize = get_optimize(optimize)?;
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+    let limits = ReachLimits {
+        max_cost,
+        max_hops,
+        max_jump_ly,
+    };
+    limits.validate()?;
+
+    let result = eftb::calc::reach::calc_reach(
+        universe,
+        start,
+        get_jump_distance(jump)?,
+        &optimize,
+        &constraints,
+        &limits,
+        &search_budget(),
+    );
+    let (reach, partial) = match result {
+        ReachResult::Found(reach) => (reach, false),
+        ReachResult::Timeout(reach) => (reach, true),
+    };
+    let start_idx = universe.star_id_to_idx[&start.id];
+    Ok(Json(ReachReturn {
+        version: 2,
+        generation: generation.id,
+        partial,
+        data: reach
+            .stars()
+            .into_iter()
+            .map(|r| WebReachable {
+                star: WebStar {
+                    id: universe.stars[r.star_idx].id,
+                    name: universe.stars[r.star_idx].name.clone(),
+                },
+                cost: r.cost,
+                hops: r.hops,
+                steps: web_path(
+                    universe,
+                    start_idx,
+                    &reach.path_to(r.star_idx).unwrap_or_default(),
+                ),
+            })
+            .collect(),
+    }))
+}
+
+// ====================================================================
+// calc_rally
+
+/// Where one member of the fleet is, and either their jump range or
+/// their ship
+#[derive(Debug, Deserialize)]
+struct WebMember {
+    start: String,
+    jump: Option<f64>,
+    ship: Option<WebShip>,
+}
+#[derive(Debug, Deserialize)]
+struct RallyRequest {
+    members: Vec<WebMember>,
+    optimize: String,
+    #[serde(default)]
+    use_smart_gates: bool,
+    #[serde(default)]
+    objective: RallyObjective,
+    count: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct WebRallyMember {
+    start: WebStar,
+    cost: f64,
+    hops: usize,
+    distance: f64,
+    steps: Vec<PathStep>,
+}
+#[derive(Debug, Serialize)]
+struct WebRallyPoint {
+    star: WebStar,
+    /// The worst or total of the members' costs, depending on the
+    /// objective
+    score: f64,
+    members: Vec<WebRallyMember>,
+}
+#[derive(Debug, Serialize)]
+struct RallyReturn {
+    version: u32,
+    generation: u64,
+    data: Vec<WebRallyPoint>,
+}
+
+/// The best places for a fleet to meet, best first. The fleet goes in
+/// the body, since each member can bring their own ship, eg
+/// `{"members": [{"start": "A", "jump": 100}, {"start": "B", "ship": {...}}],
+/// "optimize": "fuel", "objective": "max"}`
+#[post("/rally?<avoid..>", data = "<request>")]
+fn calc_rally(
+    universes: &State<Universes>,
+    request: Json<RallyRequest>,
+    avoid: WebAvoid,
+) -> Result<Json<RallyReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let request = request.into_inner();
+    let optimize = get_optimize(request.optimize)?;
+    let constraints = get_constraints(universe, request.use_smart_gates, avoid)?;
+    let count = request.count.unwrap_or(5);
+    if count == 0 || count > 20 {
+        return Err(eftb::Error::InvalidParameter {
+            name: "count",
+            message: format!("{} is not between 1 and 20", count),
+        }
+        .into());
+    }
+    let members = request
+        .members
+        .into_iter()
+        .map(|member| {
+            let range = match (member.jump, &member.ship) {
+                (Some(jump), None) => MemberRange::Jump(get_jump_distance(jump)?),
+                (None, Some(ship)) => MemberRange::Ship(ship.profile()),
+                _ => {
+                    return Err(eftb::Error::InvalidParameter {
+                        name: "members",
+                        message: format!(
+                            "{} needs either a jump range or a ship, but not both",
+                            member.start
+                        ),
+                    }
+                    .into())
+                }
+            };
+            Ok(FleetMember {
+                start: get_star(universe, member.start)?,
+                range,
+            })
+        })
+        .collect::<Result<Vec<_>, CustomError>>()?;
+
+    let result = eftb::calc::rally::calc_rally(
+        universe,
+        &members,
+        &optimize,
+        &constraints,
+        request.objective,
+        count,
+        &search_budget(),
+    )?;
+    let points = match result {
+        RallyResult::Found(points) => points,
+        RallyResult::NotFound => {
+            return Err(CustomError(
+                Status::NotFound,
+                "Nowhere that everyone can reach".to_string(),
+            ))
+        }
+        RallyResult::Timeout => return Err(eftb::Error::Timeout.into()),
+    };
+    let web_star = |idx: StarIdx| WebStar {
+        id: universe.stars[idx].id,
+        name: universe.stars[idx].name.clone(),
+    };
+    Ok(Json(RallyReturn {
+        version: 1,
+        generation: generation.id,
+        data: points
+            .iter()
+            .map(|point| WebRallyPoint {
+                star: web_star(point.star_idx),
+                score: point.score,
+                members: members
+                    .iter()
+                    .zip(point.costs.iter().zip(&point.paths))
+                    .map(|(member, (cost, path))| {
+                        let start_idx = universe.star_id_to_idx[&member.start.id];
+                        WebRallyMember {
+                            start: web_star(start_idx),
+                            cost: *cost,
+                            hops: path.len(),
+                            distance: path.iter().map(|c| c.distance.to_light_years()).sum(),
+                            steps: web_path(universe, start_idx, path),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }))
+}
+
+// ====================================================================
+// calc_nearest
+
+#[derive(Debug, Serialize)]
+struct WebNearest {
+    star: WebStar,
+    cost: f64,
+    hops: usize,
+    distance: f64,
+    steps: Vec<PathStep>,
+}
+#[derive(Debug, Serialize)]
+struct NearestReturn {
+    version: u32,
+    generation: u64,
+    /// The search ran out of time, and `data` only has the matches found
+    /// before then
+    partial: bool,
+    data: Vec<WebNearest>,
+}
+
+/// The systems cheapest to get to from `start` which match `filter`, one
+/// of `smart_gate`, `region` (with `region=ID`), `other_network`, or
+/// `one_of` (with one or more `system=NAME`)
+#[allow(clippy::too_many_arguments)]
+#[get("/nearest?<start>&<jump>&<optimize>&<use_smart_gates>&<filter>&<region>&<system>&<count>&<avoid..>")]
+fn calc_nearest(
+    universes: &State<Universes>,
+    start: String,
+    jump: f64,
+    optimize: String,
+    use_smart_gates: bool,
+    filter: String,
+    region: Option<RegionId>,
+    system: Vec<String>,
+    count: Option<usize>,
+    avoid: WebAvoid,
+) -> Result<Json<NearestReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
+    let start = get_star(universe, start)?;
+    let optimize = get_optimize(optimize)?;
+    let constraints = get_constraints(universe, use_smart_gates, avoid)?;
+    let count = count.unwrap_or(1);
+    if count == 0 || count > 20 {
+        return Err(eftb::Error::InvalidParameter {
+            name: "count",
+            message: format!("{} is not between 1 and 20", count),
+        }
+        .into());
+    }
+    let filter = match (filter.as_str(), region) {
+        ("smart_gate", _) => SystemFilter::SmartGate,
+        ("region", Some(region)) => SystemFilter::Region(region),
+        ("region", None) => {
+            return Err(eftb::Error::InvalidParameter {
+                name: "region",
+                message: "the region filter needs a region".to_string(),
+            }
+            .into())
+        }
+        ("other_network", _) => SystemFilter::OtherGateNetwork,
+        ("one_of", _) if system.is_empty() => {
+            return Err(eftb::Error::InvalidParameter {
+                name: "system",
+                message: "the one_of filter needs at least one system".to_string(),
+            }
+            .into())
+        }
+        ("one_of", _) => SystemFilter::OneOf(
+            system
+                .into_iter()
+                .map(|name| get_star(universe, name).map(|s| s.id))
+                .collect::<Result<_, CustomError>>()?,
+        ),
+        (other, _) => {
+            return Err(eftb::Error::InvalidParameter {
+                name: "filter",
+                message: format!("{} is not a known filter", other),
+            }
+            .into())
+        }
+    };
+
+    let matches = filter.predicate(universe, start);
+    let result = eftb::calc::nearest::calc_nearest(
+        universe,
+        start,
+        get_jump_distance(jump)?,
+        &optimize,
+        &constraints,
+        &matches,
+        count,
+        &search_budget(),
+    );
+    let (found, partial) = match result {
+        NearestResult::Found(found) => (found, false),
+        NearestResult::NotFound => {
+            return Err(CustomError(
+                Status::NotFound,
+                "No matching system found".to_string(),
+            ))
+        }
+        NearestResult::Timeout(found) => (found, true),
+    };
+    let start_idx = universe.star_id_to_idx[&start.id];
+    Ok(Json(NearestReturn {
+        version: 1,
+        generation: generation.id,
+        partial,
+        data: found
+            .iter()
+            .map(|nearest| WebNearest {
+                star: WebStar {
+                    id: universe.stars[nearest.star_idx].id,
+                    name: universe.stars[nearest.star_idx].name.clone(),
+                },
+                cost: nearest.cost,
+                hops: nearest.path.len(),
+                distance: nearest
+                    .path
+                    .iter()
+                    .map(|c| c.distance.to_light_years())
+                    .sum(),
+                steps: web_path(universe, start_idx, &nearest.path),
+            })
+            .collect(),
+    }))
+}
+
 // ====================================================================
 // calc_exit
 
 #[derive(Debug, Serialize)]
 struct ExitReturn {
     version: u32,
+    generation: u64,
     data: Vec<(String, String, f64)>,
 }
 
 #[get("/exit?<start>&<jump>")]
 fn calc_exit(
-    universe: &State<data::Universe>,
+    universes: &State<Universes>,
     start: String,
     jump: f64,
 ) -> Result<Json<ExitReturn>, CustomError> {
+    let generation = universes.current();
+    let universe = &generation.universe;
     let start = get_star(universe, start)?;
 
-    let exits = eftb::calc_exit(universe, start, Meters::from_light_years(jump));
+    let exits = eftb::calc_exit(universe, start, get_jump_distance(jump)?);
 
     let mut result: Vec<(String, String, f64)> = Vec::new();
     for (from, to) in exits {
@@ -221,21 +1473,152 @@ fn calc_exit(
     }
     Ok(Json(ExitReturn {
         version: 1,
+        generation: generation.id,
         data: result,
     }))
 }
 
+// ====================================================================
+// admin
+
+/// Request guard for admin endpoints, which need
+/// `Authorization: Bearer <admin_token>`
+struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Admin, ()> {
+        let expected = req
+            .rocket()
+            .state::<WebConfig>()
+            .and_then(|config| config.admin_token.as_deref());
+        let given = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "));
+        match (expected, given) {
+            // Constant time, so the response time doesn't give the token
+            // away a byte at a time
+            (Some(expected), Some(given))
+                if bool::from(expected.as_bytes().ct_eq(given.as_bytes())) =>
+            {
+                request::Outcome::Success(Admin)
+            }
+            _ => request::Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ReloadReturn {
+    version: u32,
+    generation: u64,
+}
+
+#[post("/admin/reload")]
+async fn admin_reload(
+    _admin: Admin,
+    universes: &State<Universes>,
+) -> Result<Json<ReloadReturn>, CustomError> {
+    let universes = universes.inner().clone();
+    let generation = rocket::tokio::task::spawn_blocking(move || universes.reload())
+        .await
+        .map_err(|e| CustomError(Status::InternalServerError, e.to_string()))??;
+
+    Ok(Json(ReloadReturn {
+        version: 1,
+        generation,
+    }))
+}
+
 // ====================================================================
 // launch
 
 #[launch]
 fn rocket() -> _ {
-    let universe = data::Universe::build(Meters::from_light_years(500.0))
+    let rocket = rocket::build();
+    let config: WebConfig = rocket
+        .figment()
+        .extract()
+        .expect("Failed to read configuration");
+    let universes = Universes::new(config.source(), config.jump_edges(), config.hierarchies())
         .expect("Failed to load universe data");
+    if config.reload_interval > 0 {
+        universes.watch(Duration::from_secs(config.reload_interval));
+    }
+    let dist_dir = config.dist_dir.clone();
 
-    rocket::build()
-        .manage(universe)
-        .mount("/", rocket::fs::FileServer::from("./dist").rank(1))
-        .mount("/api", routes![get_stars, calc_dist, calc_path, calc_exit])
+    rocket
+        .manage(universes)
+        .manage(config)
+        .mount("/", rocket::fs::FileServer::from(dist_dir).rank(1))
+        .mount(
+            "/api",
+            routes![
+                get_stars,
+                get_star_info,
+                get_region,
+                calc_dist,
+                calc_path,
+                calc_weighted_path,
+                calc_pareto,
+                calc_alternatives,
+                calc_route,
+                calc_ship_path,
+                calc_reach,
+                calc_rally,
+                calc_nearest,
+                calc_exit,
+                admin_reload
+            ],
+        )
         .mount("/", routes![index])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("eftb-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to make data dir");
+        for file in ["starmap.json", "smartgates.json"] {
+            std::fs::copy(PathBuf::from("data_fixtures").join(file), dir.join(file))
+                .expect("Failed to copy fixture");
+        }
+        let source = UniverseSource::from_data_dir(&dir);
+        let key = HierarchyKey {
+            jump_distance: Meters::new(25.0),
+            optimize: PathOptimize::Hops,
+            use_smart_gates: true,
+        };
+        let universes = Universes::new(
+            source.clone(),
+            data::JumpEdges::Precomputed(Meters::new(50.0)),
+            vec![key],
+        )
+        .expect("Failed to build");
+
+        let old = universes.current();
+        assert_eq!(old.id, 1);
+        assert_eq!(universes.reload().expect("Failed to reload"), 2);
+        assert_eq!(universes.current().id, 2);
+
+        // Without the snapshot the hierarchy has to be built again, which
+        // swaps in a generation without it first
+        std::fs::remove_file(&source.snapshot).expect("Failed to remove snapshot");
+        assert_eq!(universes.reload().expect("Failed to reload"), 4);
+        let current = universes.current();
+        std::fs::remove_dir_all(&dir).expect("Failed to remove data dir");
+        assert_eq!(current.id, 4);
+        assert_eq!(current.universe.hierarchies.len(), 1);
+
+        // Requests still holding the first generation can carry on with it
+        assert_eq!(old.id, 1);
+        assert_eq!(old.universe.stars.len(), 4);
+        assert_eq!(old.universe.stars[3].name, "SS1003");
+    }
+}
@@ -26,6 +26,7 @@ class GenStarmap(restool.ResToolBase):
                     "solarSystemID": ssid,
                     "center": ss["center"],
                     "regionID": ss["regionID"],
+                    "constellationID": ss["constellationID"],
                 }
             )
 